@@ -0,0 +1,98 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+use qhyccd_sdk::archive::ArchiveReader;
+use qhyccd_sdk::astro;
+use qhyccd_sdk::fits::{self, FitsOptions};
+use qhyccd_sdk::ser::{SerOptions, SerWriter};
+
+const USAGE: &str = "Usage:
+    archive_tool list <archive>
+    archive_tool extract <archive> <fits|ser> <output> [--from <utc>] [--to <utc>]
+
+Times are UTC like 2024-05-01T21:30:00.5. FITS output is a directory with one file per frame,
+SER output is a single file.";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let res = match args.first().map(|command| command.as_str()) {
+        Some("list") if args.len() == 2 => list(Path::new(&args[1])),
+        Some("extract") if args.len() >= 4 => extract(&args[1..]),
+        _ => Err(USAGE.to_string()),
+    };
+
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        },
+    }
+}
+
+fn list(path: &Path) -> Result<(), String> {
+    let reader = ArchiveReader::open(path).map_err(|err| err.to_string())?;
+    let mut raw_total = 0;
+    let mut stored_total = 0;
+    for (index, entry) in reader.get_entries().iter().enumerate() {
+        println!("{:6} {} {}x{}x{} {}bpp exposure: {}us gain: {} ratio: {:.2}",
+            index, astro::format_utc(entry.get_time()), entry.width, entry.height, entry.channels, entry.bpp,
+            entry.exposure, entry.gain, entry.raw_len as f64 / entry.record_len as f64);
+        raw_total += entry.raw_len;
+        stored_total += entry.record_len;
+    }
+    println!("{} frames, {} MB stored, {} MB raw", reader.get_frame_count(), stored_total / 1_000_000, raw_total / 1_000_000);
+
+    Ok(())
+}
+
+fn extract(args: &[String]) -> Result<(), String> {
+    let mut reader = ArchiveReader::open(Path::new(&args[0])).map_err(|err| err.to_string())?;
+    let format = args[1].as_str();
+    let output = Path::new(&args[2]);
+
+    let mut from = None;
+    let mut to = None;
+    let mut options = args[3..].iter();
+    while let Some(option) = options.next() {
+        let value = options.next().ok_or_else(|| USAGE.to_string())?;
+        let time = astro::parse_utc(value).ok_or_else(|| format!("Invalid time: {}", value))?;
+        match option.as_str() {
+            "--from" => from = Some(time),
+            "--to" => to = Some(time),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let entries = reader.get_entries();
+    if entries.is_empty() {
+        return Err("The archive holds no frames".to_string())
+    }
+    let range = reader.find_range(from.unwrap_or(entries[0].get_time()), to.unwrap_or(entries[entries.len() - 1].get_time()));
+    if range.is_empty() {
+        return Err("No frames in the given time range".to_string())
+    }
+
+    match format {
+        "fits" => {
+            fs::create_dir_all(output).map_err(|err| err.to_string())?;
+            for index in range.clone() {
+                let frame = reader.read_frame(index).map_err(|err| err.to_string())?;
+                let path = output.join(format!("frame_{:06}.fits", index));
+                fits::write(&path, &frame, None, &FitsOptions::default()).map_err(|err| err.to_string())?;
+            }
+        },
+        "ser" => {
+            let mut writer = SerWriter::create(output, SerOptions::default()).map_err(|err| err.to_string())?;
+            for index in range.clone() {
+                let frame = reader.read_frame(index).map_err(|err| err.to_string())?;
+                writer.write_frame(&frame).map_err(|err| err.to_string())?;
+            }
+            writer.finish().map_err(|err| err.to_string())?;
+        },
+        _ => return Err(USAGE.to_string()),
+    }
+    println!("Extracted {} frames to {}", range.len(), output.display());
+
+    Ok(())
+}
@@ -2,6 +2,10 @@ extern crate opencv;
 
 //use qhyccd_sdk::sdk::QhyCcd;
 use qhyccd_sdk::camera::{Camera, ControlParam};
+use qhyccd_sdk::supervisor::{CameraSupervisor, ConnectionState, SupervisorConfig};
+use qhyccd_sdk::export::ExportOptions;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
  use opencv::{
     // core,
@@ -22,24 +26,30 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     camera.set_debug_info(true);
     camera.set_control(&ControlParam::Exposure, 200.0, false);
 
+    let mut supervisor = CameraSupervisor::new(&camera, SupervisorConfig::default());
+
     let window_name = "Live";
     highgui::named_window(window_name, highgui::WINDOW_NORMAL)?;
 
     loop {
         let mut frame = Mat::default();
-        let _ = camera.get_frame(&mut frame, true);
-    
-        if frame.size()?.width > 0 {
+        let res = camera.get_frame(&mut frame, true);
+
+        // While the camera is away the supervisor keeps rescanning, the window stays up
+        let state = supervisor.check(&mut camera, res);
+        if state == ConnectionState::Connected && res && frame.size()?.width > 0 {
             highgui::imshow(window_name, &frame)?;
+        }
 
-            let key = highgui::wait_key(20)?;
-            if key == 27 {
-                break;
-            }
-        } else {
-            println!("No frame received, please check the camera connection.");
+        let key = highgui::wait_key(20)?;
+        if key == 27 {
             break;
         }
+        // 's' saves the next frame as a TIFF at the camera bit depth with its metadata next to it
+        if key == 's' as i32 {
+            let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            camera.save_snapshot(Path::new(&format!("snapshot_{}", secs)), &ExportOptions::default());
+        }
     }
 
     Ok(())
@@ -0,0 +1,555 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::frame::{Frame, FrameMetadata};
+
+const RECORD_MAGIC: &[u8; 4] = b"QRAF";
+const RECORD_PREFIX_LEN: u64 = 12;
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Io(io::Error),
+    Format(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArchiveOptions {
+    // zstd level, 1 to 3 keeps up with several full resolution frames per second on one core
+    pub level: i32,
+    // Sync data and index to disk after every frame, slower but nothing is lost on power failure
+    pub sync_every_frame: bool,
+}
+
+// One line of the index file, enough to find and filter frames without reading the data file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    pub offset: u64,
+    pub record_len: u64,
+    pub timestamp_us: i64,
+    pub width: u32,
+    pub height: u32,
+    pub bpp: u32,
+    pub channels: u32,
+    pub raw_len: u64,
+    pub exposure: u32,
+    pub gain: u32,
+    pub sensor_temperature: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordHeader {
+    width: u32,
+    height: u32,
+    bpp: u32,
+    channels: u32,
+    timestamp_us: i64,
+    raw_len: u64,
+    metadata: FrameMetadata,
+}
+
+// Frames are appended to <name>.qra as self describing records: magic, header and payload lengths,
+// the JSON header with the full frame metadata and the compressed samples. <name>.idx holds one
+// JSON line per record and is rebuilt from the records when it falls behind, e.g. after a crash.
+pub struct ArchiveWriter {
+    data_path: PathBuf,
+    data: File,
+    index: File,
+    options: ArchiveOptions,
+    compressor: zstd::bulk::Compressor<'static>,
+    entries: Vec<ArchiveEntry>,
+    end: u64,
+}
+
+pub struct ArchiveReader {
+    data_path: PathBuf,
+    data: File,
+    entries: Vec<ArchiveEntry>,
+}
+
+impl ArchiveWriter {
+    // Appends to an existing archive, a partly written record at the end is dropped
+    pub fn open(path: &Path, options: ArchiveOptions) -> Result<Self, ArchiveError> {
+        let data_path = path.with_extension("qra");
+        let mut data = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&data_path).map_err(ArchiveError::Io)?;
+        let (entries, end, is_index_stale) = load_entries(&data_path, &mut data)?;
+        if data.metadata().map_err(ArchiveError::Io)?.len() > end {
+            eprintln!("Dropping incomplete record at the end of archive: {}", data_path.display());
+            data.set_len(end).map_err(ArchiveError::Io)?;
+        }
+        if is_index_stale {
+            write_index(&index_path(&data_path), &entries)?;
+        }
+        let index = OpenOptions::new().append(true).create(true).open(index_path(&data_path)).map_err(ArchiveError::Io)?;
+
+        let mut compressor = zstd::bulk::Compressor::new(options.level).map_err(ArchiveError::Io)?;
+        compressor.set_parameter(zstd::zstd_safe::CParameter::ChecksumFlag(true)).map_err(ArchiveError::Io)?;
+
+        Ok(ArchiveWriter { data_path, data, index, options, compressor, entries, end })
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.data_path
+    }
+
+    pub fn get_entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    // Size of the data file in bytes
+    pub fn get_size(&self) -> u64 {
+        self.end
+    }
+
+    pub fn append(&mut self, frame: &Frame) -> Result<ArchiveEntry, ArchiveError> {
+        if frame.data.len() < frame.data_len() {
+            return Err(ArchiveError::Format(format!("frame has {} bytes, expected {}", frame.data.len(), frame.data_len())))
+        }
+        // Never before the previous frame, the index stays in time order for the binary searches;
+        // a frame without any time is stored right after the previous one
+        let last_us = self.entries.last().map(|entry| entry.timestamp_us);
+        let timestamp_us = match (frame.timestamp().map(to_micros), last_us) {
+            (Some(timestamp_us), Some(last_us)) => timestamp_us.max(last_us),
+            (Some(timestamp_us), None) => timestamp_us,
+            (None, Some(last_us)) => last_us,
+            (None, None) => to_micros(SystemTime::now()),
+        };
+        let header = RecordHeader {
+            width: frame.width,
+            height: frame.height,
+            bpp: frame.bpp,
+            channels: frame.channels,
+            timestamp_us,
+            raw_len: frame.data_len() as u64,
+            metadata: frame.metadata.clone(),
+        };
+        let header_bytes = serde_json::to_vec(&header).map_err(|err| ArchiveError::Format(err.to_string()))?;
+        let payload = self.compressor.compress(&encode(frame)).map_err(ArchiveError::Io)?;
+
+        let mut record = Vec::with_capacity(RECORD_PREFIX_LEN as usize + header_bytes.len() + payload.len());
+        record.extend_from_slice(RECORD_MAGIC);
+        record.extend_from_slice(&(header_bytes.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&header_bytes);
+        record.extend_from_slice(&payload);
+        self.data.seek(SeekFrom::Start(self.end)).map_err(ArchiveError::Io)?;
+        self.data.write_all(&record).map_err(ArchiveError::Io)?;
+
+        let entry = ArchiveEntry::new(self.end, record.len() as u64, &header);
+        let mut line = serde_json::to_string(&entry).map_err(|err| ArchiveError::Format(err.to_string()))?;
+        line.push('\n');
+        self.index.write_all(line.as_bytes()).map_err(ArchiveError::Io)?;
+        if self.options.sync_every_frame {
+            self.data.sync_data().map_err(ArchiveError::Io)?;
+            self.index.sync_data().map_err(ArchiveError::Io)?;
+        }
+        self.end += record.len() as u64;
+        self.entries.push(entry.clone());
+
+        Ok(entry)
+    }
+
+    pub fn sync(&mut self) -> Result<(), ArchiveError> {
+        self.data.sync_data().map_err(ArchiveError::Io)?;
+        self.index.sync_data().map_err(ArchiveError::Io)
+    }
+}
+
+impl ArchiveReader {
+    pub fn open(path: &Path) -> Result<Self, ArchiveError> {
+        let data_path = path.with_extension("qra");
+        let mut data = File::open(&data_path).map_err(ArchiveError::Io)?;
+        let (entries, _, _) = load_entries(&data_path, &mut data)?;
+
+        Ok(ArchiveReader { data_path, data, entries })
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.data_path
+    }
+
+    pub fn get_entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    pub fn get_frame_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    // Index of the first frame at or after the given time, frames are appended in time order
+    pub fn find_frame(&self, time: SystemTime) -> Option<usize> {
+        let timestamp_us = to_micros(time);
+        let index = self.entries.partition_point(|entry| entry.timestamp_us < timestamp_us);
+        if index < self.entries.len() { Some(index) } else { None }
+    }
+
+    // Indices of the frames taken between start and end, both included
+    pub fn find_range(&self, start: SystemTime, end: SystemTime) -> std::ops::Range<usize> {
+        let (start_us, end_us) = (to_micros(start), to_micros(end));
+        let first = self.entries.partition_point(|entry| entry.timestamp_us < start_us);
+        let last = self.entries.partition_point(|entry| entry.timestamp_us <= end_us);
+        first..last.max(first)
+    }
+
+    pub fn read_frame(&mut self, index: usize) -> Result<Frame, ArchiveError> {
+        let entry = self.entries.get(index).cloned()
+            .ok_or_else(|| ArchiveError::Format(format!("frame {} out of {}", index, self.entries.len())))?;
+        let mut record = vec![0u8; entry.record_len as usize];
+        self.data.seek(SeekFrom::Start(entry.offset)).map_err(ArchiveError::Io)?;
+        self.data.read_exact(&mut record).map_err(ArchiveError::Io)?;
+        let (header, header_len, payload_len) = parse_record_prefix(&record)?;
+        if RECORD_PREFIX_LEN as usize + header_len + payload_len != record.len() {
+            return Err(ArchiveError::Format(format!("record {} does not match the index", index)))
+        }
+
+        let payload = &record[RECORD_PREFIX_LEN as usize + header_len..];
+        let residuals = zstd::bulk::decompress(payload, header.raw_len as usize).map_err(ArchiveError::Io)?;
+        let mut frame = Frame::new(header.width, header.height, header.bpp, header.channels);
+        frame.metadata = header.metadata;
+        decode(&residuals, &mut frame)?;
+
+        Ok(frame)
+    }
+
+    pub fn read_time(&mut self, time: SystemTime) -> Result<Frame, ArchiveError> {
+        let index = self.find_frame(time).ok_or_else(|| ArchiveError::Format("no frame at or after the given time".to_string()))?;
+        self.read_frame(index)
+    }
+}
+
+impl ArchiveEntry {
+    fn new(offset: u64, record_len: u64, header: &RecordHeader) -> Self {
+        ArchiveEntry {
+            offset,
+            record_len,
+            timestamp_us: header.timestamp_us,
+            width: header.width,
+            height: header.height,
+            bpp: header.bpp,
+            channels: header.channels,
+            raw_len: header.raw_len,
+            exposure: header.metadata.params.exposure,
+            gain: header.metadata.params.gain,
+            sensor_temperature: header.metadata.sensor_temperature,
+        }
+    }
+
+    pub fn get_time(&self) -> SystemTime {
+        from_micros(self.timestamp_us)
+    }
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiveError::Io(err) => write!(f, "Archive I/O error: {}", err),
+            ArchiveError::Format(message) => write!(f, "Archive format error: {}", message),
+        }
+    }
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        ArchiveOptions {
+            level: 3,
+            sync_every_frame: false,
+        }
+    }
+}
+
+pub fn index_path(data_path: &Path) -> PathBuf {
+    data_path.with_extension("idx")
+}
+
+// Each sample is predicted by the previous one of the same colour: two to the left on Bayer
+// frames, one pixel to the left otherwise, and the one above for the first samples of a row.
+// Residuals are zigzag coded and split into low and high byte planes, which zstd packs well.
+pub fn encode(frame: &Frame) -> Vec<u8> {
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let channels = frame.channels.max(1) as usize;
+    let step = if frame.is_bayer() { 2 } else { 1 };
+    let row_len = width * channels;
+    let count = frame.sample_count();
+    let is_wide = frame.bytes_per_sample() == 2;
+
+    let mut encoded = vec![0u8; frame.data_len()];
+    let (low, high) = encoded.split_at_mut(count);
+    for y in 0..height {
+        for x in 0..row_len {
+            let index = y * row_len + x;
+            let prediction = predict(frame, index, x, y, step * channels, step, row_len);
+            let value = frame.get_sample(index);
+            let residual = if is_wide {
+                zigzag16(value.wrapping_sub(prediction) as i16)
+            } else {
+                zigzag8((value as u8).wrapping_sub(prediction as u8) as i8) as u16
+            };
+            low[index] = residual as u8;
+            if is_wide {
+                high[index] = (residual >> 8) as u8;
+            }
+        }
+    }
+    encoded
+}
+
+pub fn decode(encoded: &[u8], frame: &mut Frame) -> Result<(), ArchiveError> {
+    if encoded.len() != frame.data_len() {
+        return Err(ArchiveError::Format(format!("decoded {} bytes, expected {}", encoded.len(), frame.data_len())))
+    }
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let channels = frame.channels.max(1) as usize;
+    let step = if frame.is_bayer() { 2 } else { 1 };
+    let row_len = width * channels;
+    let count = frame.sample_count();
+    let is_wide = frame.bytes_per_sample() == 2;
+
+    for y in 0..height {
+        for x in 0..row_len {
+            let index = y * row_len + x;
+            let prediction = predict(frame, index, x, y, step * channels, step, row_len);
+            let value = if is_wide {
+                let residual = encoded[index] as u16 | (encoded[count + index] as u16) << 8;
+                prediction.wrapping_add(unzigzag16(residual) as u16)
+            } else {
+                (prediction as u8).wrapping_add(unzigzag8(encoded[index]) as u8) as u16
+            };
+            frame.set_sample(index, value);
+        }
+    }
+    Ok(())
+}
+
+fn predict(frame: &Frame, index: usize, x: usize, y: usize, left: usize, up: usize, row_len: usize) -> u16 {
+    if x >= left {
+        frame.get_sample(index - left)
+    } else if y >= up {
+        frame.get_sample(index - up * row_len)
+    } else {
+        0
+    }
+}
+
+fn zigzag16(value: i16) -> u16 {
+    ((value << 1) ^ (value >> 15)) as u16
+}
+
+fn unzigzag16(value: u16) -> i16 {
+    ((value >> 1) as i16) ^ -((value & 1) as i16)
+}
+
+fn zigzag8(value: i8) -> u8 {
+    ((value << 1) ^ (value >> 7)) as u8
+}
+
+fn unzigzag8(value: u8) -> i8 {
+    ((value >> 1) as i8) ^ -((value & 1) as i8)
+}
+
+pub fn to_micros(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_micros() as i64,
+        Err(err) => -(err.duration().as_micros() as i64),
+    }
+}
+
+pub fn from_micros(micros: i64) -> SystemTime {
+    let duration = Duration::from_micros(micros.unsigned_abs());
+    if micros >= 0 { UNIX_EPOCH + duration } else { UNIX_EPOCH - duration }
+}
+
+fn parse_record_prefix(record: &[u8]) -> Result<(RecordHeader, usize, usize), ArchiveError> {
+    if record.len() < RECORD_PREFIX_LEN as usize || &record[..4] != RECORD_MAGIC {
+        return Err(ArchiveError::Format("bad record magic".to_string()))
+    }
+    let header_len = u32::from_le_bytes(record[4..8].try_into().unwrap()) as usize;
+    let payload_len = u32::from_le_bytes(record[8..12].try_into().unwrap()) as usize;
+    let header_bytes = record.get(RECORD_PREFIX_LEN as usize..RECORD_PREFIX_LEN as usize + header_len)
+        .ok_or_else(|| ArchiveError::Format("record header cut short".to_string()))?;
+    let header = serde_json::from_slice(header_bytes).map_err(|err| ArchiveError::Format(err.to_string()))?;
+    Ok((header, header_len, payload_len))
+}
+
+// Entries from the index, completed by scanning the records behind the last indexed one.
+// Returns the entries, the end of the last complete record and whether the index needs rewriting.
+fn load_entries(data_path: &Path, data: &mut File) -> Result<(Vec<ArchiveEntry>, u64, bool), ArchiveError> {
+    let data_len = data.metadata().map_err(ArchiveError::Io)?.len();
+    let mut entries = Vec::new();
+    let mut is_index_stale = false;
+    if let Ok(index) = File::open(index_path(data_path)) {
+        for line in BufReader::new(index).lines() {
+            let line = line.map_err(ArchiveError::Io)?;
+            match serde_json::from_str::<ArchiveEntry>(&line) {
+                Ok(entry) if entry.offset + entry.record_len <= data_len => entries.push(entry),
+                // A torn last line or an entry for data that never made it to disk
+                _ => {
+                    is_index_stale = true;
+                    break;
+                },
+            }
+        }
+    }
+
+    let mut end = entries.last().map(|entry| entry.offset + entry.record_len).unwrap_or(0);
+    data.seek(SeekFrom::Start(end)).map_err(ArchiveError::Io)?;
+    let mut reader = BufReader::new(&mut *data);
+    loop {
+        let mut prefix = [0u8; RECORD_PREFIX_LEN as usize];
+        if reader.read_exact(&mut prefix).is_err() || &prefix[..4] != RECORD_MAGIC {
+            break;
+        }
+        let header_len = u32::from_le_bytes(prefix[4..8].try_into().unwrap()) as u64;
+        let payload_len = u32::from_le_bytes(prefix[8..12].try_into().unwrap()) as u64;
+        let record_len = RECORD_PREFIX_LEN + header_len + payload_len;
+        if end + record_len > data_len {
+            break;
+        }
+        let mut header_bytes = vec![0u8; header_len as usize];
+        if reader.read_exact(&mut header_bytes).is_err() {
+            break;
+        }
+        let has_header = serde_json::from_slice::<RecordHeader>(&header_bytes);
+        if has_header.is_err() {
+            break;
+        }
+        reader.seek_relative(payload_len as i64).map_err(ArchiveError::Io)?;
+        entries.push(ArchiveEntry::new(end, record_len, &has_header.unwrap()));
+        end += record_len;
+        is_index_stale = true;
+    }
+
+    Ok((entries, end, is_index_stale))
+}
+
+fn write_index(path: &Path, entries: &[ArchiveEntry]) -> Result<(), ArchiveError> {
+    let mut content = String::new();
+    for entry in entries {
+        content.push_str(&serde_json::to_string(entry).map_err(|err| ArchiveError::Format(err.to_string()))?);
+        content.push('\n');
+    }
+    std::fs::write(path, content).map_err(ArchiveError::Io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdk::BayerFormat;
+
+    fn test_frame(bpp: u32, channels: u32, bayer_format: BayerFormat, seconds: u64) -> Frame {
+        let mut frame = Frame::new(11, 6, bpp, channels);
+        for index in 0..frame.sample_count() {
+            frame.set_sample(index, (index * 7919 % (frame.max_value() as usize + 1)) as u16);
+        }
+        // Steps between the extremes wrap around in the residuals
+        frame.set_sample(0, frame.max_value());
+        frame.set_sample(1, 0);
+        frame.metadata.bayer_format = bayer_format;
+        frame.metadata.exposure_start = Some(UNIX_EPOCH + Duration::from_secs(seconds));
+        frame.metadata.params.exposure = 1000 + seconds as u32;
+        frame
+    }
+
+    fn test_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("qhyccd_archive_{}_{}.qra", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(index_path(&path));
+        path
+    }
+
+    fn remove(path: &Path) {
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(index_path(path)).unwrap();
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        for (bpp, channels, bayer_format) in [(8, 1, BayerFormat::Mono), (16, 1, BayerFormat::Mono), (8, 1, BayerFormat::RG),
+                                              (16, 1, BayerFormat::GB), (8, 3, BayerFormat::Mono), (16, 3, BayerFormat::Mono)] {
+            let frame = test_frame(bpp, channels, bayer_format, 0);
+            let encoded = encode(&frame);
+            assert_eq!(encoded.len(), frame.data_len());
+            let mut decoded = Frame::new(frame.width, frame.height, bpp, channels);
+            decoded.metadata.bayer_format = bayer_format;
+            decode(&encoded, &mut decoded).unwrap();
+            assert_eq!(decoded.data, frame.data, "{} bit, {} channels, {:?}", bpp, channels, bayer_format);
+        }
+    }
+
+    #[test]
+    fn archive_round_trip() {
+        let path = test_path("round_trip");
+        let frames = [test_frame(16, 1, BayerFormat::RG, 10), test_frame(8, 1, BayerFormat::Mono, 20), test_frame(8, 3, BayerFormat::Mono, 30)];
+        let mut writer = ArchiveWriter::open(&path, ArchiveOptions::default()).unwrap();
+        for frame in frames.iter() {
+            writer.append(frame).unwrap();
+        }
+        drop(writer);
+
+        let mut reader = ArchiveReader::open(&path).unwrap();
+        assert_eq!(reader.get_frame_count(), 3);
+        for (index, frame) in frames.iter().enumerate() {
+            let read = reader.read_frame(index).unwrap();
+            assert_eq!((read.width, read.height, read.bpp, read.channels), (frame.width, frame.height, frame.bpp, frame.channels));
+            assert_eq!(read.data, frame.data);
+            assert_eq!(read.metadata.params.exposure, frame.metadata.params.exposure);
+            assert_eq!(read.metadata.bayer_format, frame.metadata.bayer_format);
+        }
+        assert_eq!(reader.find_range(UNIX_EPOCH + Duration::from_secs(15), UNIX_EPOCH + Duration::from_secs(30)), 1..3);
+        remove(&path);
+    }
+
+    #[test]
+    fn keeps_entries_in_time_order() {
+        let path = test_path("time_order");
+        let mut writer = ArchiveWriter::open(&path, ArchiveOptions::default()).unwrap();
+        writer.append(&test_frame(8, 1, BayerFormat::Mono, 20)).unwrap();
+        writer.append(&test_frame(8, 1, BayerFormat::Mono, 10)).unwrap();
+        let mut untimed = test_frame(8, 1, BayerFormat::Mono, 0);
+        untimed.metadata.exposure_start = None;
+        writer.append(&untimed).unwrap();
+        writer.append(&test_frame(8, 1, BayerFormat::Mono, 30)).unwrap();
+
+        let times: Vec<i64> = writer.get_entries().iter().map(|entry| entry.timestamp_us / 1_000_000).collect();
+        assert_eq!(times, [20, 20, 20, 30]);
+        drop(writer);
+        remove(&path);
+    }
+
+    #[test]
+    fn rebuilds_index_after_torn_record() {
+        let path = test_path("torn");
+        let mut writer = ArchiveWriter::open(&path, ArchiveOptions::default()).unwrap();
+        for seconds in [10, 20, 30] {
+            writer.append(&test_frame(16, 1, BayerFormat::RG, seconds)).unwrap();
+        }
+        let last = writer.get_entries()[2].clone();
+        drop(writer);
+
+        // The last record only partly made it to disk and its index line is torn
+        let data = OpenOptions::new().write(true).open(&path).unwrap();
+        data.set_len(last.offset + last.record_len / 2).unwrap();
+        let index = std::fs::read_to_string(index_path(&path)).unwrap();
+        std::fs::write(index_path(&path), &index[..index.len() - 10]).unwrap();
+
+        let mut writer = ArchiveWriter::open(&path, ArchiveOptions::default()).unwrap();
+        assert_eq!(writer.get_entries().len(), 2);
+        assert_eq!(writer.get_size(), last.offset);
+        writer.append(&test_frame(16, 1, BayerFormat::RG, 40)).unwrap();
+        drop(writer);
+
+        let mut reader = ArchiveReader::open(&path).unwrap();
+        let times: Vec<i64> = reader.get_entries().iter().map(|entry| entry.timestamp_us / 1_000_000).collect();
+        assert_eq!(times, [10, 20, 40]);
+        assert_eq!(reader.read_frame(2).unwrap().data, test_frame(16, 1, BayerFormat::RG, 40).data);
+
+        // Without an index every record is found again
+        std::fs::remove_file(index_path(&path)).unwrap();
+        let writer = ArchiveWriter::open(&path, ArchiveOptions::default()).unwrap();
+        assert_eq!(writer.get_entries(), reader.get_entries());
+        drop(writer);
+        remove(&path);
+    }
+}
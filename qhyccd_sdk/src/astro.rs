@@ -0,0 +1,242 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const UNIX_EPOCH_JD: f64 = 2440587.5;
+const J2000_JD: f64 = 2451545.0;
+const EARTH_RADIUS_KM: f64 = 6378.14;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SiteLocation {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub elevation: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EquatorialPosition {
+    pub right_ascension: f64,
+    pub declination: f64,
+    pub distance_km: f64,
+}
+
+pub fn julian_date(time: SystemTime) -> f64 {
+    let seconds = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs_f64(),
+        Err(before) => -before.duration().as_secs_f64(),
+    };
+    seconds / 86400.0 + UNIX_EPOCH_JD
+}
+
+// Low precision solar coordinates from the Astronomical Almanac, good to ~0.01 deg
+pub fn sun_position(jd: f64) -> EquatorialPosition {
+    let n = jd - J2000_JD;
+    let mean_longitude = normalize_degrees(280.460 + 0.9856474 * n);
+    let mean_anomaly = normalize_degrees(357.528 + 0.9856003 * n).to_radians();
+    let ecliptic_longitude = (mean_longitude + 1.915 * mean_anomaly.sin() + 0.020 * (2.0 * mean_anomaly).sin()).to_radians();
+    let obliquity = (23.439 - 0.0000004 * n).to_radians();
+
+    let right_ascension = (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+    let distance_au = 1.00014 - 0.01671 * mean_anomaly.cos() - 0.00014 * (2.0 * mean_anomaly).cos();
+
+    EquatorialPosition {
+        right_ascension: normalize_degrees(right_ascension.to_degrees()),
+        declination: declination.to_degrees(),
+        distance_km: distance_au * 149_597_870.7,
+    }
+}
+
+// Truncated lunar theory (main periodic terms only), good to a few tenths of a degree
+pub fn moon_position(jd: f64) -> EquatorialPosition {
+    let d = jd - J2000_JD;
+    let mean_longitude = normalize_degrees(218.316 + 13.176396 * d);
+    let mean_anomaly = normalize_degrees(134.963 + 13.064993 * d).to_radians();
+    let mean_elongation = normalize_degrees(297.850 + 12.190749 * d).to_radians();
+    let argument_of_latitude = normalize_degrees(93.272 + 13.229350 * d).to_radians();
+
+    let longitude = (mean_longitude
+        + 6.289 * mean_anomaly.sin()
+        + 1.274 * (2.0 * mean_elongation - mean_anomaly).sin()
+        + 0.658 * (2.0 * mean_elongation).sin()
+        + 0.214 * (2.0 * mean_anomaly).sin()).to_radians();
+    let latitude = (5.128 * argument_of_latitude.sin()).to_radians();
+    let distance_km = 385001.0 - 20905.0 * mean_anomaly.cos();
+    let obliquity = (23.439 - 0.0000004 * d).to_radians();
+
+    let right_ascension = (longitude.sin() * obliquity.cos() - latitude.tan() * obliquity.sin()).atan2(longitude.cos());
+    let declination = (latitude.sin() * obliquity.cos() + latitude.cos() * obliquity.sin() * longitude.sin()).asin();
+
+    EquatorialPosition {
+        right_ascension: normalize_degrees(right_ascension.to_degrees()),
+        declination: declination.to_degrees(),
+        distance_km,
+    }
+}
+
+pub fn local_sidereal_time(jd: f64, longitude: f64) -> f64 {
+    let gmst = 280.46061837 + 360.98564736629 * (jd - J2000_JD);
+    normalize_degrees(gmst + longitude)
+}
+
+// Geometric altitude, corrected for horizontal parallax and for the dip of the horizon seen from the site elevation
+pub fn altitude(position: &EquatorialPosition, site: &SiteLocation, jd: f64) -> f64 {
+    let hour_angle = (local_sidereal_time(jd, site.longitude) - position.right_ascension).to_radians();
+    let latitude = site.latitude.to_radians();
+    let declination = position.declination.to_radians();
+
+    let sin_altitude = latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+    let geocentric = sin_altitude.clamp(-1.0, 1.0).asin();
+    let parallax = (EARTH_RADIUS_KM / position.distance_km * geocentric.cos()).asin();
+
+    (geocentric - parallax).to_degrees() + horizon_dip(site.elevation)
+}
+
+pub fn sun_altitude(site: &SiteLocation, time: SystemTime) -> f64 {
+    let jd = julian_date(time);
+    altitude(&sun_position(jd), site, jd)
+}
+
+pub fn moon_altitude(site: &SiteLocation, time: SystemTime) -> f64 {
+    let jd = julian_date(time);
+    altitude(&moon_position(jd), site, jd)
+}
+
+// Fraction of the lunar disc that is lit, from the sun-moon elongation
+pub fn moon_illumination(time: SystemTime) -> f64 {
+    let jd = julian_date(time);
+    let sun = sun_position(jd);
+    let moon = moon_position(jd);
+
+    let (sun_ra, sun_dec) = (sun.right_ascension.to_radians(), sun.declination.to_radians());
+    let (moon_ra, moon_dec) = (moon.right_ascension.to_radians(), moon.declination.to_radians());
+    let cos_elongation = sun_dec.sin() * moon_dec.sin() + sun_dec.cos() * moon_dec.cos() * (sun_ra - moon_ra).cos();
+
+    (1.0 - cos_elongation.clamp(-1.0, 1.0)) / 2.0
+}
+
+// ISO 8601 UTC with microseconds, e.g. 2024-06-21T12:00:00.000000
+pub fn format_utc(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let days = (since.as_secs() / 86400) as i64;
+    let seconds = since.as_secs() % 86400;
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}", year, month, day,
+        seconds / 3600, seconds / 60 % 60, seconds % 60, since.subsec_micros())
+}
+
+// Accepts what format_utc writes, with or without the fraction and a trailing Z
+pub fn parse_utc(text: &str) -> Option<SystemTime> {
+    let text = text.trim().trim_end_matches('Z');
+    if !text.is_ascii() {
+        return None
+    }
+    let (date, time) = text.split_once('T')?;
+    let number = |part: &str| if !part.is_empty() && part.bytes().all(|c| c.is_ascii_digit()) { part.parse::<u64>().ok() } else { None };
+    let mut date_parts = date.split('-').map(number);
+    let (year, month, day) = (date_parts.next()??, date_parts.next()??, date_parts.next()??);
+    let (clock, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut clock_parts = clock.split(':').map(number);
+    let (hour, minute, second) = (clock_parts.next()??, clock_parts.next()??, clock_parts.next()??);
+    if date_parts.next().is_some() || clock_parts.next().is_some() || !fraction.bytes().all(|c| c.is_ascii_digit()) {
+        return None
+    }
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 59 {
+        return None
+    }
+    let nanos = if fraction.is_empty() { 0 } else { format!("{:0<9}", &fraction[..fraction.len().min(9)]).parse::<u32>().ok()? };
+
+    // Rejects days past the end of the month, e.g. 02-30
+    let days = days_from_civil(year as i64, month as u32, day as u32);
+    if civil_from_days(days) != (year as i64, month as u32, day as u32) {
+        return None
+    }
+    Some(UNIX_EPOCH + Duration::new(days as u64 * 86400 + hour * 3600 + minute * 60 + second, nanos))
+}
+
+// Days since 1970-01-01 to proleptic Gregorian dates and back, after Howard Hinnant
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let day_of_year = (153 * mp + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn horizon_dip(elevation: f64) -> f64 {
+    if elevation > 0.0 {
+        0.0293 * elevation.sqrt()
+    } else {
+        0.0
+    }
+}
+
+fn normalize_degrees(value: f64) -> f64 {
+    value.rem_euclid(360.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GREENWICH: SiteLocation = SiteLocation { latitude: 51.4769, longitude: 0.0, elevation: 0.0 };
+
+    fn utc(text: &str) -> SystemTime {
+        parse_utc(text).unwrap()
+    }
+
+    #[test]
+    fn julian_date_of_j2000() {
+        assert_eq!(julian_date(utc("2000-01-01T12:00:00")), J2000_JD);
+        assert_eq!(julian_date(UNIX_EPOCH), UNIX_EPOCH_JD);
+    }
+
+    #[test]
+    fn sun_declination_at_solstice_and_equinox() {
+        // June solstice 2024-06-20 20:51 UTC, March equinox 2024-03-20 03:06 UTC
+        let solstice = sun_position(julian_date(utc("2024-06-20T20:51:00")));
+        assert!((solstice.declination - 23.44).abs() < 0.02, "{:?}", solstice);
+        let equinox = sun_position(julian_date(utc("2024-03-20T03:06:00")));
+        assert!(equinox.declination.abs() < 0.02, "{:?}", equinox);
+    }
+
+    #[test]
+    fn sun_altitude_at_greenwich() {
+        // Upper and lower culmination around the solstices: 90 - latitude + declination and latitude + declination - 90
+        let noon = sun_altitude(&GREENWICH, utc("2024-06-21T12:02:00"));
+        assert!((noon - 61.96).abs() < 0.05, "{}", noon);
+        let midnight = sun_altitude(&GREENWICH, utc("2024-12-21T00:00:00"));
+        assert!((midnight + 61.96).abs() < 0.05, "{}", midnight);
+        // The horizon is lower seen from a mountain
+        let mountain = SiteLocation { elevation: 2500.0, ..GREENWICH };
+        assert!((sun_altitude(&mountain, utc("2024-06-21T12:02:00")) - noon - 0.0293 * 50.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn moon_illumination_at_full_and_new_moon() {
+        assert!(moon_illumination(utc("2024-01-25T17:54:00")) > 0.99);
+        assert!(moon_illumination(utc("2024-01-11T11:57:00")) < 0.01);
+    }
+
+    #[test]
+    fn utc_round_trip() {
+        let time = utc("2024-02-29T23:59:58.123456Z");
+        assert_eq!(format_utc(time), "2024-02-29T23:59:58.123456");
+        assert_eq!(parse_utc(&format_utc(time)), Some(time));
+        assert_eq!(parse_utc("2023-02-29T00:00:00"), None);
+        assert_eq!(parse_utc("2024-01-01 00:00:00"), None);
+    }
+}
@@ -16,6 +16,14 @@ extern "C" {
     pub fn CloseQHYCCD(handle: *mut QhyCcdHandle) -> u32;
     pub fn SetQHYCCDStreamMode(handle: *mut QhyCcdHandle, mode: u8) -> u32;
     pub fn InitQHYCCD(handle: *mut QhyCcdHandle) -> u32;
+    pub fn GetQHYCCDNumberOfReadModes(handle: *mut QhyCcdHandle, numModes: *mut u32) -> u32;
+    pub fn GetQHYCCDReadModeName(
+        handle: *mut QhyCcdHandle,
+        modeNumber: u32,
+        name: *mut ::std::os::raw::c_char,
+    ) -> u32;
+    pub fn SetQHYCCDReadMode(handle: *mut QhyCcdHandle, modeNumber: u32) -> u32;
+    pub fn GetQHYCCDReadMode(handle: *mut QhyCcdHandle, modeNumber: *mut u32) -> u32;
     pub fn IsQHYCCDControlAvailable(handle: *mut QhyCcdHandle, controlId: u32) -> u32;
     pub fn SetQHYCCDParam(handle: *mut QhyCcdHandle, controlId: u32, value: f64) -> u32;
     pub fn GetQHYCCDParam(handle: *mut QhyCcdHandle, controlId: u32) -> f64;
@@ -91,6 +99,23 @@ extern "C" {
     ) -> u32;
     pub fn GetQHYCCDCameraStatus(h: *mut QhyCcdHandle, buf: *mut u8) -> u32;
     pub fn SetQHYCCDDebayerOnOff(h: *mut QhyCcdHandle, onoff: bool) -> u32;
+    pub fn SetQHYCCDTrigerMode(handle: *mut QhyCcdHandle, trigerMode: u32) -> u32;
+    pub fn SetQHYCCDTrigerFunction(h: *mut QhyCcdHandle, value: bool) -> u32;
+    pub fn EnableQHYCCDTrigerOut(handle: *mut QhyCcdHandle) -> u32;
+    pub fn SendSoftTriggerQHYCCD(handle: *mut QhyCcdHandle) -> u32;
+    pub fn EnableQHYCCDBurstMode(h: *mut QhyCcdHandle, i: bool) -> u32;
+    pub fn SetQHYCCDBurstModeStartEnd(h: *mut QhyCcdHandle, start: u16, end: u16) -> u32;
+    pub fn EnableQHYCCDBurstCountFun(h: *mut QhyCcdHandle, i: bool) -> u32;
+    pub fn ResetQHYCCDFrameCounter(h: *mut QhyCcdHandle) -> u32;
+    pub fn SetQHYCCDBurstIDLE(h: *mut QhyCcdHandle) -> u32;
+    pub fn ReleaseQHYCCDBurstIDLE(h: *mut QhyCcdHandle) -> u32;
+    pub fn SetQHYCCDBurstModePatchNumber(h: *mut QhyCcdHandle, value: u32) -> u32;
+    pub fn ControlQHYCCDGuide(handle: *mut QhyCcdHandle, direction: u32, duration: u16) -> u32;
+    pub fn IsQHYCCDCFWPlugged(handle: *mut QhyCcdHandle) -> u32;
+    pub fn SendOrder2QHYCCDCFW(handle: *mut QhyCcdHandle, order: *mut ::std::os::raw::c_char, length: u32) -> u32;
+    pub fn GetQHYCCDCFWStatus(handle: *mut QhyCcdHandle, status: *mut ::std::os::raw::c_char) -> u32;
+    pub fn GetQHYCCDFWVersion(h: *mut QhyCcdHandle, buf: *mut u8) -> u32;
+    pub fn GetQHYCCDFPGAVersion(h: *mut QhyCcdHandle, fpga_index: u8, buf: *mut u8) -> u32;
     pub fn GetQHYCCDSDKVersion(
         year: *mut u32,
         month: *mut u32,
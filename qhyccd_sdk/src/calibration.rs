@@ -0,0 +1,559 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use crate::camera::{BinMode, Camera, ControlParam};
+use crate::frame::{Frame, FrameMetadata};
+
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MasterKind {
+    Bias,
+    Dark,
+    Flat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CombineMethod {
+    Median,
+    SigmaClippedMean { sigma: f64, iterations: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CalibrationKey {
+    pub serial_num: String,
+    pub exposure: u32,
+    pub gain: u32,
+    pub offset: u32,
+    pub bin_mode: BinMode,
+    pub bpp: u32,
+    pub temperature_bucket: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MasterHeader {
+    pub name: String,
+    pub kind: MasterKind,
+    pub key: CalibrationKey,
+    pub width: u32,
+    pub height: u32,
+    pub frame_count: u32,
+    pub method: CombineMethod,
+    pub temperature: Option<f64>,
+    pub created: SystemTime,
+}
+
+// Bias and dark masters are in ADU, flats are dark subtracted and normalised to a mean of 1
+#[derive(Debug, Clone)]
+pub struct MasterFrame {
+    pub header: MasterHeader,
+    pub data: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CalibrationInfo {
+    pub bias: Option<String>,
+    pub dark: Option<String>,
+    pub flat: Option<String>,
+    pub dark_scale: f64,
+}
+
+// Masters are stored as <name>.json with the header next to <name>.f32 with the samples in little endian
+pub struct CalibrationLibrary {
+    dir: PathBuf,
+    temperature_step: f64,
+    headers: Vec<MasterHeader>,
+    loaded: HashMap<String, MasterFrame>,
+}
+
+// Shortest exposure the cameras accept, used for bias frames
+const BIAS_EXPOSURE_US: u32 = 1;
+
+impl CalibrationKey {
+    pub fn from_metadata(metadata: &FrameMetadata, temperature_step: f64) -> Self {
+        CalibrationKey {
+            serial_num: metadata.serial_num.clone(),
+            exposure: metadata.params.exposure,
+            gain: metadata.params.gain,
+            offset: metadata.params.offset,
+            bin_mode: metadata.params.bin_mode.clone(),
+            bpp: metadata.params.bpp,
+            temperature_bucket: metadata.sensor_temperature.map(|temperature| temperature_bucket(temperature, temperature_step)),
+        }
+    }
+}
+
+impl MasterFrame {
+    pub fn combine(kind: MasterKind, frames: &[Frame], method: CombineMethod, temperature_step: f64) -> Option<MasterFrame> {
+        let first = frames.first()?;
+        if frames.iter().any(|frame| frame.width != first.width || frame.height != first.height || frame.channels != first.channels) {
+            eprintln!("Calibration frames differ in size");
+            return None
+        }
+
+        let mut stack = vec![0f32; frames.len()];
+        let data = (0..first.sample_count()).map(|index| {
+            for (sample, frame) in stack.iter_mut().zip(frames) {
+                *sample = frame.get_sample(index) as f32;
+            }
+            combine_stack(&mut stack, method)
+        }).collect();
+
+        let temperatures: Vec<f64> = frames.iter().filter_map(|frame| frame.metadata.sensor_temperature).collect();
+        let temperature = if temperatures.is_empty() { None } else { Some(temperatures.iter().sum::<f64>() / temperatures.len() as f64) };
+        let mut metadata = first.metadata.clone();
+        metadata.sensor_temperature = temperature;
+        let key = CalibrationKey::from_metadata(&metadata, temperature_step);
+        let created = SystemTime::now();
+
+        Some(MasterFrame {
+            header: MasterHeader {
+                name: master_name(kind, &key, created),
+                kind,
+                key,
+                width: first.width,
+                height: first.height,
+                frame_count: frames.len() as u32,
+                method,
+                temperature,
+                created,
+            },
+            data,
+        })
+    }
+
+    // Removes the dark signal of the flat exposure and scales the flat to a mean of 1
+    pub fn normalise_flat(&mut self, offset: &[f32]) {
+        if offset.len() == self.data.len() {
+            for (value, offset) in self.data.iter_mut().zip(offset.iter()) {
+                *value -= offset;
+            }
+        }
+        let mean = self.data.iter().map(|value| *value as f64).sum::<f64>() / self.data.len().max(1) as f64;
+        if mean > 0.0 {
+            for value in self.data.iter_mut() {
+                *value = (*value as f64 / mean) as f32;
+            }
+        }
+    }
+}
+
+impl CalibrationLibrary {
+    pub fn open(dir: &Path, temperature_step: f64) -> io::Result<CalibrationLibrary> {
+        fs::create_dir_all(dir)?;
+        let mut headers = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let content = fs::read_to_string(&path)?;
+            match serde_json::from_str::<MasterHeader>(&content) {
+                Ok(header) => headers.push(header),
+                Err(err) => eprintln!("Skipping calibration master {}: {}", path.display(), err),
+            }
+        }
+
+        Ok(CalibrationLibrary {
+            dir: dir.to_path_buf(),
+            temperature_step,
+            headers,
+            loaded: HashMap::new(),
+        })
+    }
+
+    pub fn get_masters(&self) -> &[MasterHeader] {
+        &self.headers
+    }
+
+    pub fn get_temperature_step(&self) -> f64 {
+        self.temperature_step
+    }
+
+    pub fn add(&mut self, master: MasterFrame) -> io::Result<()> {
+        let name = master.header.name.clone();
+        let header = serde_json::to_string_pretty(&master.header).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let bytes: Vec<u8> = master.data.iter().flat_map(|value| value.to_le_bytes()).collect();
+        fs::write(self.dir.join(format!("{}.f32", name)), bytes)?;
+        fs::write(self.dir.join(format!("{}.json", name)), header)?;
+
+        self.headers.retain(|header| header.name != name);
+        self.headers.push(master.header.clone());
+        self.loaded.insert(name, master);
+
+        Ok(())
+    }
+
+    // Same camera, binning, depth and frame size are required; the rest is scored, each degree
+    // of temperature weighs like one gain or offset step, exposure only matters for darks
+    pub fn select(&self, kind: MasterKind, key: &CalibrationKey, width: u32, height: u32) -> Option<&MasterHeader> {
+        let score = |header: &MasterHeader| {
+            let temperature = match (header.key.temperature_bucket, key.temperature_bucket) {
+                (Some(master), Some(frame)) => (master - frame).abs() as f64 * self.temperature_step,
+                _ => 100.0,
+            };
+            let exposure = if kind == MasterKind::Dark {
+                (header.key.exposure as f64 / key.exposure.max(1) as f64).ln().abs() * 10.0
+            } else {
+                0.0
+            };
+            let settings = if kind == MasterKind::Flat {
+                0.0
+            } else {
+                (header.key.gain as f64 - key.gain as f64).abs() + (header.key.offset as f64 - key.offset as f64).abs()
+            };
+            temperature + exposure + settings
+        };
+
+        self.headers.iter()
+            .filter(|header| header.kind == kind && header.key.serial_num == key.serial_num && header.key.bin_mode == key.bin_mode
+                && header.key.bpp == key.bpp && header.width == width && header.height == height)
+            .min_by(|a, b| score(a).total_cmp(&score(b)))
+    }
+
+    pub fn load(&mut self, name: &str) -> Option<&MasterFrame> {
+        if !self.loaded.contains_key(name) {
+            let header = self.headers.iter().find(|header| header.name == name)?.clone();
+            let has_bytes = fs::read(self.dir.join(format!("{}.f32", name)));
+            if has_bytes.is_err() {
+                eprintln!("Cannot read calibration master {}, error: {}", name, has_bytes.unwrap_err());
+                return None
+            }
+            let data = has_bytes.unwrap().chunks_exact(4).map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).collect();
+            self.loaded.insert(name.to_string(), MasterFrame { header, data });
+        }
+
+        self.loaded.get(name)
+    }
+
+    // (raw - bias - (dark - bias) * scale) / flat, the dark is used as is when the exposures match
+    pub fn calibrate(&mut self, frame: &mut Frame) -> Option<CalibrationInfo> {
+        let key = CalibrationKey::from_metadata(&frame.metadata, self.temperature_step);
+        let sample_count = frame.sample_count();
+        let (offset, mut info) = self.dark_offset(&key, frame.width, frame.height, sample_count);
+        let flat_name = self.select(MasterKind::Flat, &key, frame.width, frame.height).map(|header| header.name.clone());
+        if let Some(name) = &flat_name {
+            self.load(name);
+        }
+        let flat = flat_name.and_then(|name| self.loaded.get(&name)).filter(|master| master.data.len() == sample_count);
+        if info.bias.is_none() && info.dark.is_none() && flat.is_none() {
+            return None
+        }
+        if let Some(flat) = flat {
+            info.flat = Some(flat.header.name.clone());
+        }
+
+        let max_value = frame.max_value() as f32;
+        for (index, offset) in offset.iter().enumerate() {
+            let mut value = frame.get_sample(index) as f32 - offset;
+            if let Some(flat) = flat {
+                let gain = flat.data[index];
+                if gain > 0.01 {
+                    value /= gain;
+                }
+            }
+            frame.set_sample(index, value.round().clamp(0.0, max_value) as u16);
+        }
+
+        Some(info)
+    }
+
+    // Dark signal expected for the exposure of the key, bias + (dark - bias) * scale
+    fn dark_offset(&mut self, key: &CalibrationKey, width: u32, height: u32, sample_count: usize) -> (Vec<f32>, CalibrationInfo) {
+        let bias_name = self.select(MasterKind::Bias, key, width, height).map(|header| header.name.clone());
+        let dark_name = self.select(MasterKind::Dark, key, width, height).map(|header| header.name.clone());
+        for name in bias_name.iter().chain(dark_name.iter()) {
+            self.load(name);
+        }
+        let get = |name: &Option<String>| name.as_ref().and_then(|name| self.loaded.get(name)).filter(|master| master.data.len() == sample_count);
+        let bias = get(&bias_name);
+        let dark = get(&dark_name);
+
+        let mut info = CalibrationInfo { dark_scale: 1.0, ..CalibrationInfo::default() };
+        let mut offset = vec![0f32; sample_count];
+        match (bias, dark) {
+            (None, Some(dark)) => {
+                offset.copy_from_slice(&dark.data);
+                info.dark = Some(dark.header.name.clone());
+            },
+            (Some(_), Some(dark)) if dark.header.key.exposure == key.exposure => {
+                offset.copy_from_slice(&dark.data);
+                info.dark = Some(dark.header.name.clone());
+            },
+            (Some(bias), Some(dark)) => {
+                let scale = key.exposure as f32 / dark.header.key.exposure.max(1) as f32;
+                for ((value, bias), dark) in offset.iter_mut().zip(bias.data.iter()).zip(dark.data.iter()) {
+                    *value = bias + (dark - bias) * scale;
+                }
+                info.bias = Some(bias.header.name.clone());
+                info.dark = Some(dark.header.name.clone());
+                info.dark_scale = scale as f64;
+            },
+            (Some(bias), None) => {
+                offset.copy_from_slice(&bias.data);
+                info.bias = Some(bias.header.name.clone());
+            },
+            (None, None) => {},
+        }
+
+        (offset, info)
+    }
+}
+
+// Takes the frames through the camera with its current settings, bias frames at the shortest exposure;
+// the exposure and stream mode are restored afterwards.
+// Flats have the dark of their exposure removed with the masters already in the library, then are normalised.
+pub fn acquire_master(camera: &mut Camera, library: &mut CalibrationLibrary, kind: MasterKind, count: u32,
+                      timeout: Duration, method: CombineMethod) -> Option<MasterFrame> {
+    let saved_exposure = camera.get_params().exposure;
+    if kind == MasterKind::Bias {
+        camera.set_control(&ControlParam::Exposure, BIAS_EXPOSURE_US as f64, false);
+    }
+    camera.begin_snapshot_run();
+    let frames: Vec<Frame> = (0..count).map_while(|_| camera.snapshot(timeout)).collect();
+    camera.end_snapshot_run();
+    if camera.get_params().exposure != saved_exposure {
+        camera.set_control(&ControlParam::Exposure, saved_exposure as f64, false);
+    }
+    if frames.len() < count as usize {
+        eprintln!("{} master needs {} frames, got {}", kind, count, frames.len());
+        return None
+    }
+
+    let mut master = MasterFrame::combine(kind, &frames, method, library.get_temperature_step())?;
+    if kind == MasterKind::Flat {
+        let (offset, _) = library.dark_offset(&master.header.key, master.header.width, master.header.height, master.data.len());
+        master.normalise_flat(&offset);
+    }
+    if camera.is_debug_info() {
+        println!("{} master {} from {} frames", kind, master.header.name, master.header.frame_count);
+    }
+
+    let res = library.add(master.clone());
+    if res.is_err() {
+        eprintln!("Cannot store calibration master, error: {}", res.unwrap_err());
+    }
+
+    Some(master)
+}
+
+pub fn temperature_bucket(temperature: f64, temperature_step: f64) -> i32 {
+    (temperature / temperature_step.max(0.1)).round() as i32
+}
+
+fn master_name(kind: MasterKind, key: &CalibrationKey, created: SystemTime) -> String {
+    let bin = match key.bin_mode {
+        BinMode::Bin1x1 => 1,
+        BinMode::Bin2x2 => 2,
+        BinMode::Bin3x3 => 3,
+        BinMode::Bin4x4 => 4,
+    };
+    let temperature = key.temperature_bucket.map_or("na".to_string(), |bucket| bucket.to_string());
+    format!("{}_{}_{}us_g{}_o{}_bin{}_{}bit_t{}_{}", kind.to_string().to_lowercase(), key.serial_num, key.exposure, key.gain, key.offset,
+        bin, key.bpp, temperature, created.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs())
+}
+
+fn combine_stack(stack: &mut [f32], method: CombineMethod) -> f32 {
+    match method {
+        CombineMethod::Median => {
+            stack.sort_unstable_by(|a, b| a.total_cmp(b));
+            let middle = stack.len() / 2;
+            if stack.len() % 2 == 0 { (stack[middle - 1] + stack[middle]) / 2.0 } else { stack[middle] }
+        },
+        CombineMethod::SigmaClippedMean { sigma, iterations } => {
+            let mut low = f32::MIN;
+            let mut high = f32::MAX;
+            let mut mean = 0.0;
+            for _ in 0..iterations.max(1) {
+                let kept: Vec<f32> = stack.iter().cloned().filter(|value| *value >= low && *value <= high).collect();
+                if kept.is_empty() {
+                    break;
+                }
+                mean = kept.iter().sum::<f32>() / kept.len() as f32;
+                let deviation = (kept.iter().map(|value| (value - mean).powi(2)).sum::<f32>() / kept.len() as f32).sqrt();
+                low = mean - sigma as f32 * deviation;
+                high = mean + sigma as f32 * deviation;
+            }
+            mean
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 4;
+    const HEIGHT: u32 = 3;
+
+    fn test_key(exposure: u32, gain: u32, temperature_bucket: Option<i32>) -> CalibrationKey {
+        CalibrationKey {
+            serial_num: "test".to_string(),
+            exposure,
+            gain,
+            offset: 0,
+            bin_mode: BinMode::Bin1x1,
+            bpp: 16,
+            temperature_bucket,
+        }
+    }
+
+    fn test_master(kind: MasterKind, key: CalibrationKey, data: Vec<f32>) -> MasterFrame {
+        let name = format!("{}_{}us_g{}_t{:?}", kind, key.exposure, key.gain, key.temperature_bucket);
+        MasterFrame {
+            header: MasterHeader {
+                name,
+                kind,
+                key,
+                width: WIDTH,
+                height: HEIGHT,
+                frame_count: 3,
+                method: CombineMethod::Median,
+                temperature: None,
+                created: SystemTime::now(),
+            },
+            data,
+        }
+    }
+
+    fn test_library(name: &str, masters: Vec<MasterFrame>) -> (PathBuf, CalibrationLibrary) {
+        let dir = std::env::temp_dir().join(format!("qhyccd_calibration_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut library = CalibrationLibrary::open(&dir, 1.0).unwrap();
+        for master in masters {
+            library.add(master).unwrap();
+        }
+        (dir, library)
+    }
+
+    fn flat_data(value: f32) -> Vec<f32> {
+        vec![value; (WIDTH * HEIGHT) as usize]
+    }
+
+    #[test]
+    fn combines_stacks() {
+        assert_eq!(combine_stack(&mut [5.0, 1.0, 3.0], CombineMethod::Median), 3.0);
+        assert_eq!(combine_stack(&mut [4.0, 1.0, 3.0, 2.0], CombineMethod::Median), 2.5);
+        // The outlier is clipped after the first pass
+        let mut stack = [100.0, 101.0, 99.0, 100.0, 102.0, 98.0, 100.0, 1000.0];
+        let clipped = combine_stack(&mut stack, CombineMethod::SigmaClippedMean { sigma: 2.0, iterations: 3 });
+        assert!((clipped - 100.0).abs() < 0.01, "{}", clipped);
+        let mean = combine_stack(&mut stack, CombineMethod::SigmaClippedMean { sigma: 100.0, iterations: 1 });
+        assert!((mean - 212.5).abs() < 0.01, "{}", mean);
+    }
+
+    #[test]
+    fn combine_keeps_the_settings_and_temperature() {
+        let frames: Vec<Frame> = [(10, -9.6), (20, -10.0), (30, -10.4)].iter().map(|&(value, temperature)| {
+            let mut frame = Frame::new(WIDTH, HEIGHT, 16, 1);
+            for index in 0..frame.sample_count() {
+                frame.set_sample(index, value);
+            }
+            frame.metadata.serial_num = "test".to_string();
+            frame.metadata.params.bin_mode = BinMode::Bin1x1;
+            frame.metadata.params.exposure = 5000;
+            frame.metadata.sensor_temperature = Some(temperature);
+            frame
+        }).collect();
+        let master = MasterFrame::combine(MasterKind::Dark, &frames, CombineMethod::Median, 1.0).unwrap();
+        assert_eq!(master.data, flat_data(20.0));
+        assert_eq!(master.header.frame_count, 3);
+        assert_eq!(master.header.key.exposure, 5000);
+        assert_eq!(master.header.key.temperature_bucket, Some(-10));
+        assert!((master.header.temperature.unwrap() + 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn normalises_flats() {
+        let mut flat = test_master(MasterKind::Flat, test_key(1000, 0, None), vec![110.0, 210.0, 310.0, 410.0]);
+        flat.normalise_flat(&[10.0; 4]);
+        assert_eq!(flat.data, vec![0.4, 0.8, 1.2, 1.6]);
+        // Without a matching dark the flat is only scaled
+        let mut flat = test_master(MasterKind::Flat, test_key(1000, 0, None), vec![50.0, 150.0]);
+        flat.normalise_flat(&[]);
+        assert_eq!(flat.data, vec![0.5, 1.5]);
+    }
+
+    #[test]
+    fn selects_the_closest_master() {
+        let (dir, library) = test_library("select", vec![
+            test_master(MasterKind::Dark, test_key(5000, 10, Some(-12)), flat_data(0.0)),
+            test_master(MasterKind::Dark, test_key(5000, 10, Some(-9)), flat_data(0.0)),
+            // Exact temperature, but twice the exposure or another gain score worse than one degree
+            test_master(MasterKind::Dark, test_key(10000, 10, Some(-10)), flat_data(0.0)),
+            test_master(MasterKind::Dark, test_key(5000, 20, Some(-10)), flat_data(0.0)),
+            test_master(MasterKind::Flat, test_key(1000, 50, Some(-10)), flat_data(1.0)),
+            test_master(MasterKind::Flat, test_key(5000, 10, Some(-14)), flat_data(1.0)),
+        ]);
+        let key = test_key(5000, 10, Some(-10));
+        let dark = library.select(MasterKind::Dark, &key, WIDTH, HEIGHT).unwrap();
+        assert_eq!(dark.key.temperature_bucket, Some(-9));
+        // Flats ignore exposure and gain
+        let flat = library.select(MasterKind::Flat, &key, WIDTH, HEIGHT).unwrap();
+        assert_eq!((flat.key.gain, flat.key.temperature_bucket), (50, Some(-10)));
+        assert!(library.select(MasterKind::Bias, &key, WIDTH, HEIGHT).is_none());
+        assert!(library.select(MasterKind::Dark, &key, WIDTH + 2, HEIGHT).is_none());
+        let mut other_camera = key.clone();
+        other_camera.serial_num = "other".to_string();
+        assert!(library.select(MasterKind::Dark, &other_camera, WIDTH, HEIGHT).is_none());
+
+        // Reopened from disk the choice is the same
+        let reopened = CalibrationLibrary::open(&dir, 1.0).unwrap();
+        assert_eq!(reopened.get_masters().len(), 6);
+        assert_eq!(reopened.select(MasterKind::Dark, &key, WIDTH, HEIGHT).unwrap().name, dark.name);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn scales_the_dark_to_the_exposure() {
+        let (dir, mut library) = test_library("dark_offset", vec![
+            test_master(MasterKind::Bias, test_key(BIAS_EXPOSURE_US, 10, Some(-10)), flat_data(100.0)),
+            test_master(MasterKind::Dark, test_key(10000, 10, Some(-10)), flat_data(300.0)),
+        ]);
+        let sample_count = (WIDTH * HEIGHT) as usize;
+        let (offset, info) = library.dark_offset(&test_key(5000, 10, Some(-10)), WIDTH, HEIGHT, sample_count);
+        assert_eq!(offset, flat_data(200.0));
+        assert_eq!(info.dark_scale, 0.5);
+        assert!(info.bias.is_some() && info.dark.is_some());
+
+        // A dark of the same exposure is used as is
+        library.add(test_master(MasterKind::Dark, test_key(5000, 10, Some(-10)), flat_data(180.0))).unwrap();
+        let (offset, info) = library.dark_offset(&test_key(5000, 10, Some(-10)), WIDTH, HEIGHT, sample_count);
+        assert_eq!(offset, flat_data(180.0));
+        assert_eq!((info.bias, info.dark_scale), (None, 1.0));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn calibrates_frames() {
+        let mut flat = flat_data(1.0);
+        flat[0] = 0.5;
+        flat[1] = 1.5;
+        let (dir, mut library) = test_library("calibrate", vec![
+            test_master(MasterKind::Bias, test_key(BIAS_EXPOSURE_US, 10, Some(-10)), flat_data(100.0)),
+            test_master(MasterKind::Dark, test_key(20000, 10, Some(-10)), flat_data(500.0)),
+            test_master(MasterKind::Flat, test_key(1000, 10, Some(-10)), flat),
+        ]);
+        let mut frame = Frame::new(WIDTH, HEIGHT, 16, 1);
+        frame.metadata.serial_num = "test".to_string();
+        frame.metadata.params.bin_mode = BinMode::Bin1x1;
+        frame.metadata.params.bpp = 16;
+        frame.metadata.params.exposure = 5000;
+        frame.metadata.params.gain = 10;
+        frame.metadata.sensor_temperature = Some(-10.2);
+        for index in 0..frame.sample_count() {
+            frame.set_sample(index, 1200);
+        }
+
+        let info = library.calibrate(&mut frame).unwrap();
+        assert_eq!(info.dark_scale, 0.25);
+        assert!(info.flat.is_some());
+        // 1200 - (100 + 400 * 0.25) = 1000, divided by the flat
+        assert_eq!(frame.get_sample(0), 2000);
+        assert_eq!(frame.get_sample(1), 667);
+        assert_eq!(frame.get_sample(2), 1000);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -3,16 +3,38 @@ extern crate opencv;
 #[path = "c_bindings.rs"]
 mod c_bindings;
 
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
 use std::thread;
 use std::time::Duration;
 use std::fmt;
 use std::collections::HashMap;
+use std::path::Path;
+use std::sync::mpsc::Receiver;
 use derive_more::Display;
-use opencv::{core, imgproc::*, prelude::*};
-use crate::sdk::{self, QhyCcd, ControlId, BayerFormat, ParamLimits, CameraArea};
-
-#[derive(Debug, Clone, PartialEq)]
+use serde::{Deserialize, Serialize};
+use opencv::{imgproc::*, prelude::*};
+use crate::sdk::{self, QhyCcd, ControlId, BayerFormat, ParamLimits, CameraArea, CameraStatus, ImageResult, SdkVersion, FirmwareVersion};
+use crate::white_balance::{self, WhiteBalanceGains, WhiteBalanceMethod};
+use crate::scheduler::CaptureProfile;
+use crate::profile::{CameraProfile, ProfileError, ProfileFile};
+use crate::config_watch::{ConfigWatcher, ParamChange, ParamsDiff};
+use crate::frame::{Frame, FrameMetadata};
+use crate::trigger::{self, TriggerMode, TriggerPulse};
+use crate::gps::{self, GpsHeader};
+use crate::guide::GuideDirection;
+use crate::ddr::{DdrMonitor, DdrStatus, DropStats};
+use crate::overscan::{self, OverscanMode};
+use crate::defects::{self, DefectConfig, DefectMap, DefectStore};
+use crate::calibration::CalibrationLibrary;
+use crate::pattern_noise::{self, PatternNoiseConfig, PatternNoiseMode};
+use crate::timing::{ClockStatusSource, FrameTiming, LatencyModel, TimingModel};
+use crate::export::{self, ExportOptions};
+
+// Burst mode patch number from the QHYCCD burst mode example: it pads each burst frame so the USB
+// transfer ends on a whole block, without it the last frame of a burst can stall
+const BURST_PATCH_NUMBER: u32 = 32001;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum BinMode {
     Bin1x1 = 1,
     Bin2x2 = 2,
@@ -20,7 +42,7 @@ pub enum BinMode {
     Bin4x4 = 4,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CameraInfo {
     pub id: String,
     pub model: String,
@@ -48,15 +70,33 @@ pub struct CameraInfo {
     pub has_bin3x3_mode: bool,
     pub has_bin4x4_mode: bool,
 
+    #[serde(default)]
+    pub read_modes: Vec<String>,
+
     pub gain_limits: ParamLimits,
     pub offset_limits: ParamLimits,
     pub usb_traffic_limits: ParamLimits,
     pub red_wb_limits: ParamLimits,
     pub green_wb_limits: ParamLimits,
     pub blue_wb_limits: ParamLimits,
+
+    #[serde(default)]
+    pub versions: CameraVersions,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraVersions {
+    pub sdk: SdkVersion,
+    pub firmware: FirmwareVersion,
+    pub fpga: Vec<String>,
+    // None when the camera does not have the control
+    pub sensor_ulvo_status: Option<u32>,
+    pub init_config_from_flash: Option<u32>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CameraParams {
     pub roi: CameraArea,
 
@@ -78,8 +118,20 @@ pub struct CameraParams {
     pub gain: u32,
     pub offset: u32,
     pub bin_mode: BinMode,
+    pub read_mode: u32,
 
     pub bpp: u32,
+
+    // None keeps the camera default
+    pub ddr_buffer: Option<bool>,
+    // Zero keeps the camera default
+    pub ddr_read_threshold: u32,
+
+    pub overscan_mode: OverscanMode,
+
+    pub row_noise_reduction: bool,
+    pub remove_rbi: bool,
+    pub pattern_noise: PatternNoiseConfig,
 }
 
 #[repr(u32)]
@@ -100,6 +152,14 @@ pub enum ControlParam {
     Channels = ControlId::ControlChannels as u32,
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct ExposureProgress {
+    pub elapsed: Duration,
+    pub remaining: Duration,
+    pub status: Option<CameraStatus>,
+    pub is_done: bool,
+}
+
 impl Camera {
     pub fn new() -> Self {
         QhyCcd::enable_message(false);
@@ -107,6 +167,14 @@ impl Camera {
         Camera::default()
     }
 
+    pub(crate) fn new_shared(cameras: HashMap<String, CameraInfo>) -> Self {
+        let mut camera = Camera::default();
+        camera.cameras = cameras;
+        camera.is_cam_init = true;
+        camera.is_sdk_owner = false;
+        camera
+    }
+
     pub fn init(&mut self) -> bool {
         if !self.is_cam_init {
             let ret = QhyCcd::init_resource();
@@ -146,7 +214,8 @@ impl Camera {
             self.close();
         }
 
-        if self.is_cam_init {
+        // Cameras handed out by CameraManager share the SDK resources, only the manager releases them
+        if self.is_cam_init && self.is_sdk_owner {
             let res = QhyCcd::release_resource();
             if res.is_err() {
                 eprintln!("Cannot release SDK resources, error: {}", res.unwrap_err());
@@ -159,106 +228,1096 @@ impl Camera {
         self.is_debug_info = enable;
     }
 
+    pub fn is_debug_info(&self) -> bool {
+        self.is_debug_info
+    }
+
     pub fn get_cameras(&mut self) -> &HashMap<String, CameraInfo> {
         self.scan_cameras();
         &self.cameras
     }
 
-    pub fn open(&mut self, camera_id: &str) -> bool {
-        if !self.is_cam_init && !self.init() {
+    pub fn cancel_exposure(&mut self) -> bool {
+        if !self.is_cam_open {
+            self.is_exposing = false;
+            return false
+        }
+        // A live stream keeps running after cancel_exposing_and_readout, it has to be stopped
+        let (res, name) = if self.params.stream_mode == sdk::StreamMode::LiveFrame {
+            (QhyCcd::stop_live(self.cam_handle), "stop_live")
+        } else {
+            (QhyCcd::cancel_exposing_and_readout(self.cam_handle), "cancel_exposing_and_readout")
+        };
+        self.is_exposing = false;
+        if res.is_err() {
+            eprintln!("{} failure, error: {}", name, res.unwrap_err());
+            return false
+        }
+
+        true
+    }
+
+    pub fn restart_stream(&mut self) -> bool {
+        if !self.is_cam_open {
+            return false
+        }
+        self.stop_exposing();
+        self.begin_exposing()
+    }
+
+    pub fn reinit_sdk(&mut self) -> bool {
+        if !self.is_sdk_owner {
+            eprintln!("SDK resources are shared, cannot re-init them from camera: {}", self.cam_id);
+            return false
+        }
+        let cam_id = self.cam_id.clone();
+        self.release();
+        if !self.init() {
+            return false
+        }
+        // The SDK forgets every device on release, scan again before opening
+        let _ = self.scan_ids();
+        self.cam_id = cam_id.clone();
+        self.open(&cam_id)
+    }
+
+    pub fn set_fpga_watchdog(&mut self, enable: bool) -> bool {
+        let is_available = QhyCcd::is_control_available(self.cam_handle, &ControlId::CamWatchDogFpga).unwrap_or(false);
+        if !is_available {
+            return false
+        }
+        let res = QhyCcd::set_param(self.cam_handle, &ControlId::CamWatchDogFpga, if enable { 1.0 } else { 0.0 });
+        if res.is_err() {
+            eprintln!("set_fpga_watchdog failure, error: {}", res.unwrap_err());
+            return false
+        }
+
+        true
+    }
+
+    pub fn get_raw_frame(&mut self) -> Option<Frame> {
+        if !self.is_cam_open || !self.get_internal_frame() {
+            return None
+        }
+
+        Some(self.build_frame())
+    }
+
+    // Grabs the next frame and writes it with its metadata sidecar, the format decides the extension
+    pub fn save_snapshot(&mut self, path: &Path, options: &ExportOptions) -> bool {
+        let has_frame = self.get_raw_frame();
+        if has_frame.is_none() {
+            eprintln!("save_snapshot failure, no frame received");
+            return false
+        }
+        let res = export::export(path, &has_frame.unwrap(), options);
+        if res.is_err() {
+            eprintln!("save_snapshot failure, error: {}", res.unwrap_err());
+            return false
+        }
+        if self.is_debug_info {
+            println!("Saved snapshot: {}", res.unwrap().image.display());
+        }
+
+        true
+    }
+
+    pub fn set_timing_model(&mut self, timing_model: TimingModel) {
+        self.timing_model = timing_model;
+    }
+
+    pub fn set_clock_source(&mut self, source: Option<ClockStatusSource>, refresh: Duration) {
+        self.timing_model.set_clock_source(source, refresh);
+    }
+
+    // Snapshots never block between start and finish: a caller sharing the camera through a mutex
+    // can release it while polling snapshot_progress and only hold it again for finish_snapshot.
+    // The stream mode in use before is restored by finish_snapshot or cancel_snapshot.
+    pub fn start_snapshot(&mut self) -> bool {
+        if !self.is_cam_open {
+            return false
+        }
+        self.stop_exposing();
+        if self.params.stream_mode != sdk::StreamMode::SingleFrame {
+            let previous = self.params.stream_mode.clone();
+            if !self.set_stream_mode(&sdk::StreamMode::SingleFrame) {
+                return false
+            }
+            self.snapshot_stream_mode.get_or_insert(previous);
+        }
+
+        self.begin_exposing()
+    }
+
+    // Keeps single frame mode between the snapshots of a run, end_snapshot_run restores the stream mode
+    pub fn begin_snapshot_run(&mut self) {
+        self.is_snapshot_run = true;
+    }
+
+    pub fn end_snapshot_run(&mut self) {
+        self.is_snapshot_run = false;
+        self.restore_stream_mode();
+    }
+
+    pub fn snapshot_progress(&mut self) -> Option<ExposureProgress> {
+        if !self.is_exposing || self.params.stream_mode != sdk::StreamMode::SingleFrame {
+            return None
+        }
+        let elapsed = self.exposure_started?.elapsed();
+        let remaining = self.get_exposure_duration().saturating_sub(elapsed);
+        let status = QhyCcd::get_camera_status(self.cam_handle).ok();
+        if status == Some(CameraStatus::Reading) {
+            self.readout_started_utc.get_or_insert_with(SystemTime::now);
+        }
+
+        Some(ExposureProgress {
+            elapsed,
+            remaining,
+            status,
+            is_done: remaining.is_zero() || self.readout_started_utc.is_some(),
+        })
+    }
+
+    pub fn cancel_snapshot(&mut self) -> bool {
+        if !self.is_exposing {
+            return false
+        }
+        let res = self.cancel_exposure();
+        self.exposure_started = None;
+        if !self.is_snapshot_run {
+            self.restore_stream_mode();
+        }
+
+        res
+    }
+
+    pub fn finish_snapshot(&mut self, timeout: Duration) -> Option<Frame> {
+        if !self.is_exposing || self.params.stream_mode != sdk::StreamMode::SingleFrame {
+            return None
+        }
+        let mut w: u32 = 0;
+        let mut h: u32 = 0;
+        let mut bpp: u32 = 0;
+        let mut channels: u32 = 0;
+
+        let res = self.get_single(&mut w, &mut h, &mut bpp, &mut channels, timeout);
+        self.is_exposing = false;
+        if !res {
+            let _ = QhyCcd::cancel_exposing_and_readout(self.cam_handle);
+            if !self.is_snapshot_run {
+                self.restore_stream_mode();
+            }
+            return None
+        }
+        self.last_image = ImageResult { width: w, height: h, bpp, channels };
+
+        let frame = self.build_frame();
+        self.exposure_started = None;
+        if !self.is_snapshot_run {
+            self.restore_stream_mode();
+        }
+
+        Some(frame)
+    }
+
+    pub fn snapshot(&mut self, timeout: Duration) -> Option<Frame> {
+        if !self.start_snapshot() {
+            return None
+        }
+        self.finish_snapshot(timeout)
+    }
+
+    pub fn set_trigger_mode(&mut self, mode: &TriggerMode) -> bool {
+        let is_available = QhyCcd::is_control_available(self.cam_handle, &ControlId::CamTrigerInterface).unwrap_or(false);
+        if !is_available && *mode != TriggerMode::Off {
+            eprintln!("Trigger not available on camera: {}", self.cam_id);
+            return false
+        }
+        self.stop_exposing();
+
+        let res = match mode {
+            TriggerMode::Off => QhyCcd::set_trigger_function(self.cam_handle, false),
+            TriggerMode::Software => QhyCcd::set_trigger_function(self.cam_handle, true),
+            TriggerMode::External(trigger_mode) => QhyCcd::set_trigger_mode(self.cam_handle, *trigger_mode)
+                .and_then(|_| QhyCcd::set_trigger_function(self.cam_handle, true)),
+        };
+        if res.is_err() {
+            eprintln!("set_trigger_mode failure, error: {}", res.unwrap_err());
+            return false
+        }
+        self.trigger_mode = *mode;
+
+        true
+    }
+
+    pub fn get_trigger_mode(&self) -> TriggerMode {
+        self.trigger_mode
+    }
+
+    // The SDK has no call to turn trigger out off again, it stays on until the camera is re-opened
+    pub fn enable_trigger_out(&mut self) -> bool {
+        let is_available = QhyCcd::is_control_available(self.cam_handle, &ControlId::CamTrigerOut).unwrap_or(false);
+        if !is_available {
+            eprintln!("Trigger out not available on camera: {}", self.cam_id);
+            return false
+        }
+        let res = QhyCcd::enable_trigger_out(self.cam_handle);
+        if res.is_err() {
+            eprintln!("enable_trigger_out failure, error: {}", res.unwrap_err());
+            return false
+        }
+
+        true
+    }
+
+    pub fn send_soft_trigger(&mut self) -> bool {
+        let res = QhyCcd::send_soft_trigger(self.cam_handle);
+        if res.is_err() {
+            eprintln!("send_soft_trigger failure, error: {}", res.unwrap_err());
+            return false
+        }
+
+        true
+    }
+
+    // Arms a trigger-mode exposure, waits for the next pulse and fires the camera with a soft trigger,
+    // so several cameras subscribed to the same source expose together
+    // Waits up to pulse_timeout for the pulse, the readout then gets the usual frame timeout
+    pub fn capture_on_pulse(&mut self, pulses: &Receiver<TriggerPulse>, pulse_timeout: Duration) -> Option<Frame> {
+        if self.trigger_mode == TriggerMode::Off {
+            eprintln!("capture_on_pulse needs a trigger mode, camera: {}", self.cam_id);
+            return None
+        }
+        if !self.is_exposing && !self.start_snapshot() {
+            return None
+        }
+        let pulse = match pulses.recv_timeout(pulse_timeout) {
+            Ok(pulse) => pulse,
+            Err(_) => {
+                // Disarm, otherwise the camera stays in snapshot mode waiting for a trigger
+                eprintln!("No trigger pulse within {:?}, camera: {}", pulse_timeout, self.cam_id);
+                self.cancel_snapshot();
+                return None
+            }
+        };
+        if self.trigger_mode == TriggerMode::Software && !self.send_soft_trigger() {
+            return None
+        }
+
+        let mut frame = self.finish_snapshot(self.get_frame_timeout())?;
+        let known_start = trigger::pulse_exposure_start(self.trigger_mode, &pulse);
+        if frame.metadata.gps.is_none() && known_start.is_some() {
+            frame.metadata.timing = self.estimate_timing(frame.data_len(), known_start);
+            frame.metadata.exposure_start = frame.metadata.timing.as_ref().map(|timing| timing.exposure_start);
+            frame.metadata.exposure_end = frame.metadata.timing.as_ref().map(|timing| timing.exposure_end);
+        }
+        frame.metadata.trigger_sequence = Some(pulse.sequence);
+
+        Some(frame)
+    }
+
+    pub fn capture_burst(&mut self, count: u16, timeout: Duration) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        let is_available = QhyCcd::is_control_available(self.cam_handle, &ControlId::CamBurstMode).unwrap_or(false);
+        if !self.is_cam_open || !is_available || count == 0 {
+            eprintln!("Burst mode not available on camera: {}", self.cam_id);
+            return frames
+        }
+
+        self.stop_exposing();
+        if self.params.stream_mode != sdk::StreamMode::LiveFrame && !self.set_stream_mode(&sdk::StreamMode::LiveFrame) {
+            return frames
+        }
+        let res = QhyCcd::enable_burst_mode(self.cam_handle, true)
+            .and_then(|_| QhyCcd::set_burst_mode_start_end(self.cam_handle, 1, count))
+            .and_then(|_| QhyCcd::enable_burst_count_fun(self.cam_handle, true))
+            .and_then(|_| QhyCcd::reset_frame_counter(self.cam_handle));
+        if res.is_err() {
+            eprintln!("Burst mode setup failure, error: {}", res.unwrap_err());
+            let _ = QhyCcd::enable_burst_mode(self.cam_handle, false);
+            return frames
+        }
+        if self.begin_exposing() {
+            // Leaving the idle state releases one burst of `count` frames
+            let _ = QhyCcd::set_burst_mode_patch_number(self.cam_handle, BURST_PATCH_NUMBER);
+            let _ = QhyCcd::set_burst_idle(self.cam_handle);
+            let _ = QhyCcd::release_burst_idle(self.cam_handle);
+
+            let saved_timeout = self.frame_timeout.replace(timeout);
+            for _ in 0..count {
+                match self.get_raw_frame() {
+                    Some(frame) => frames.push(frame),
+                    None => break,
+                }
+            }
+            self.frame_timeout = saved_timeout;
+        }
+
+        self.stop_exposing();
+        let _ = QhyCcd::enable_burst_mode(self.cam_handle, false);
+        if self.is_debug_info {
+            println!("Burst captured {} of {} frames", frames.len(), count);
+        }
+
+        frames
+    }
+
+    pub fn enable_gps(&mut self, enable: bool) -> bool {
+        let is_available = QhyCcd::is_control_available(self.cam_handle, &ControlId::CamGps).unwrap_or(false);
+        if !is_available {
+            if enable {
+                eprintln!("GPS not available on camera: {}", self.cam_id);
+            }
+            return false
+        }
+        let res = QhyCcd::set_param(self.cam_handle, &ControlId::CamGps, if enable { 1.0 } else { 0.0 });
+        if res.is_err() {
+            eprintln!("enable_gps failure, error: {}", res.unwrap_err());
+            return false
+        }
+        self.is_gps_enabled = enable;
+
+        true
+    }
+
+    // The LED flashes at exposure start and end, used to calibrate the shutter delay against PPS
+    pub fn set_gps_led(&mut self, enable: bool) -> bool {
+        let is_available = QhyCcd::is_control_available(self.cam_handle, &ControlId::CamGlobalSensorGpsLed).unwrap_or(false);
+        if !is_available {
+            return false
+        }
+        QhyCcd::set_param(self.cam_handle, &ControlId::CamGlobalSensorGpsLed, if enable { 1.0 } else { 0.0 }).is_ok()
+    }
+
+    pub fn is_gps_enabled(&self) -> bool {
+        self.is_gps_enabled
+    }
+
+    pub fn has_guide_port(&self) -> bool {
+        QhyCcd::is_control_available(self.cam_handle, &ControlId::ControlSt4Port).unwrap_or(false)
+    }
+
+    // Duration in milliseconds, see guide::Guider for validated and serialised pulses
+    pub fn guide_pulse(&mut self, direction: GuideDirection, duration: u16) -> bool {
+        if !self.is_cam_open || !self.has_guide_port() {
+            eprintln!("ST4 guide port not available on camera: {}", self.cam_id);
+            return false
+        }
+        let res = QhyCcd::control_guide(self.cam_handle, direction as u32, duration);
+        if res.is_err() {
+            eprintln!("guide_pulse failure, direction: {}, error: {}", direction, res.unwrap_err());
+            return false
+        }
+        if self.is_debug_info {
+            println!("Guide pulse {} {}ms, camera: {}", direction, duration, self.cam_id);
+        }
+
+        true
+    }
+
+    pub fn is_cfw_plugged(&mut self) -> bool {
+        if !self.is_cam_open {
+            return false
+        }
+        QhyCcd::is_cfw_plugged(self.cam_handle).unwrap_or(false)
+    }
+
+    pub fn get_cfw_slots(&mut self) -> u32 {
+        let is_available = QhyCcd::is_control_available(self.cam_handle, &ControlId::ControlCfwSlotsNum).unwrap_or(false);
+        if !is_available {
+            return 0
+        }
+        QhyCcd::get_param(self.cam_handle, &ControlId::ControlCfwSlotsNum) as u32
+    }
+
+    // Slots count from 0, the wheel takes the slot as one ASCII character starting at '0'
+    pub fn move_cfw(&mut self, slot: u32) -> bool {
+        let order = ((b'0' + slot.min(u8::MAX as u32 - b'0' as u32) as u8) as char).to_string();
+        let res = QhyCcd::send_order_to_cfw(self.cam_handle, &order);
+        if res.is_err() {
+            eprintln!("move_cfw failure, slot: {}, error: {}", slot, res.unwrap_err());
+            return false
+        }
+
+        true
+    }
+
+    // The wheel reports 'N' while it is moving
+    pub fn get_cfw_position(&mut self) -> Option<u32> {
+        let status = QhyCcd::get_cfw_status(self.cam_handle).ok()?;
+        let position = *status.as_bytes().first()?;
+        if position < b'0' || position == b'N' {
+            return None
+        }
+
+        Some((position - b'0') as u32)
+    }
+
+    // None derives the timeout from the exposure
+    pub fn set_frame_timeout(&mut self, timeout: Option<Duration>) {
+        self.frame_timeout = timeout;
+    }
+
+    pub fn get_frame_timeout(&self) -> Duration {
+        self.frame_timeout.unwrap_or_else(|| self.get_exposure_duration() * 2 + Duration::from_secs(1))
+    }
+
+    pub fn get_cam_id(&self) -> &str {
+        &self.cam_id
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.is_cam_open
+    }
+
+    // False for cameras opened through a manager, the SDK resources belong to it
+    pub fn is_sdk_owner(&self) -> bool {
+        self.is_sdk_owner
+    }
+
+    // Ids currently on the bus, without opening them (unlike get_cameras)
+    pub fn scan_ids(&mut self) -> Vec<String> {
+        if !self.init() {
+            return Vec::new()
+        }
+        let cam_count = QhyCcd::scan();
+        (0..cam_count).filter_map(|index| QhyCcd::get_id(index).ok()).collect()
+    }
+
+    // Closes a handle whose device went away, the id and params are kept for reconnect()
+    pub fn drop_connection(&mut self) {
+        let cam_id = self.cam_id.clone();
+        self.close();
+        self.cam_id = cam_id;
+    }
+
+    pub fn reconnect(&mut self, serial_num: &str) -> bool {
+        if self.is_cam_open {
+            return true
+        }
+        let suffix = format!("-{}", serial_num);
+        let has_id = self.scan_ids().into_iter().find(|id| id.ends_with(&suffix));
+        if has_id.is_none() {
+            return false
+        }
+        let cam_id = has_id.unwrap();
+        if !self.cameras.contains_key(&cam_id) {
+            let mut info = self.current_info.clone();
+            info.id = cam_id.clone();
+            self.cameras.insert(cam_id.clone(), info);
+        }
+
+        // Same id as before the drop, so open() restores the previous params instead of the defaults
+        self.cam_id = cam_id.clone();
+        self.open(&cam_id)
+    }
+
+    pub fn open(&mut self, camera_id: &str) -> bool {
+        if !self.is_cam_init && !self.init() {
+            return false
+        }
+        let mut cam_id = camera_id.to_string();
+        if !self.is_cam_open {
+            if cam_id.is_empty() {
+                if !self.scan_cameras() {
+                    return false
+                }
+                // Lowest id first, so the same camera is picked on every run
+                cam_id = self.cameras.keys().min().unwrap().clone();
+            } else if self.cam_id != cam_id {
+                self.is_default_set = false;
+            }
+            self.cam_id = cam_id.clone();
+            let has_info = self.cameras.get(&cam_id);
+            if has_info.is_none() {
+                return false
+            }
+            self.current_info = has_info.unwrap().clone();
+
+            let has_cam_handle = QhyCcd::open(&cam_id);
+            if has_cam_handle.is_err() {
+                self.cam_handle = std::ptr::null_mut();
+                self.cam_id = String::new();
+                self.current_info = CameraInfo::default();
+                eprintln!("OpenQHYCCD failure, camera id: {}", cam_id);
+                return false
+            }
+            self.cam_handle = has_cam_handle.unwrap();
+
+            self.set_default_params();
+            self.is_cam_open = true;
+        }
+
+        self.is_cam_open
+    }
+
+    pub fn get_params(&self) -> &CameraParams {
+        &self.params
+    }
+
+    pub fn get_current_info(&self) -> &CameraInfo {
+        &self.current_info
+    }
+
+    pub fn load_profile(&mut self, path: &str) -> Result<CameraProfile, ProfileError> {
+        let profile_file = ProfileFile::load(Path::new(path))?;
+        let has_profile = profile_file.find(&self.current_info);
+        if has_profile.is_none() {
+            return Err(ProfileError::NotFound(self.cam_id.clone()))
+        }
+        let profile = has_profile.unwrap().clone();
+        self.apply_params(&profile.params)?;
+        if let Some(latency) = &profile.latency {
+            self.timing_model.set_model_latency(&self.current_info.model, latency.clone());
+        }
+
+        Ok(profile)
+    }
+
+    pub fn apply_params(&mut self, params: &CameraParams) -> Result<(), ProfileError> {
+        if !self.is_cam_open {
+            return Err(ProfileError::NotOpen(self.cam_id.clone()))
+        }
+        let issues = self.validate_params(params);
+        if !issues.is_empty() {
+            return Err(ProfileError::Invalid(issues))
+        }
+
+        self.stop_exposing();
+        self.params = params.clone();
+        self.is_default_set = true;
+        self.set_default_params();
+
+        Ok(())
+    }
+
+    pub fn validate_params(&self, params: &CameraParams) -> Vec<String> {
+        let info = &self.current_info;
+        let mut issues = Vec::new();
+
+        let mut check_limits = |name: &str, value: f64, limits: &ParamLimits| {
+            if limits.max > limits.min && (value < limits.min || value > limits.max) {
+                issues.push(format!("{} {} outside [{}, {}]", name, value, limits.min, limits.max));
+            }
+        };
+        check_limits("gain", params.gain as f64, &info.gain_limits);
+        check_limits("offset", params.offset as f64, &info.offset_limits);
+        check_limits("usb_traffic", params.usb_traffic as f64, &info.usb_traffic_limits);
+        if info.is_color {
+            check_limits("red_wb", params.red_wb, &info.red_wb_limits);
+            check_limits("green_wb", params.green_wb, &info.green_wb_limits);
+            check_limits("blue_wb", params.blue_wb, &info.blue_wb_limits);
+        }
+
+        let has_bin_mode = match params.bin_mode {
+            BinMode::Bin1x1 => info.has_bin1x1_mode,
+            BinMode::Bin2x2 => info.has_bin2x2_mode,
+            BinMode::Bin3x3 => info.has_bin3x3_mode,
+            BinMode::Bin4x4 => info.has_bin4x4_mode,
+        };
+        if !has_bin_mode {
+            issues.push(format!("bin mode {:?} not supported", params.bin_mode));
+        }
+        let has_bpp = params.bpp == 8 || (params.bpp == 16 && info.max_bpp > 8);
+        if !has_bpp {
+            issues.push(format!("bpp {} not supported, camera max bpp: {}", params.bpp, info.max_bpp));
+        }
+        if params.roi.width == 0 || params.roi.height == 0
+            || !params.roi.start_x.checked_add(params.roi.width).is_some_and(|end_x| end_x <= info.max_image_width)
+            || !params.roi.start_y.checked_add(params.roi.height).is_some_and(|end_y| end_y <= info.max_image_height) {
+            issues.push(format!("roi {}x{}+{}+{} outside {}x{}", params.roi.width, params.roi.height,
+                params.roi.start_x, params.roi.start_y, info.max_image_width, info.max_image_height));
+        }
+        if !info.read_modes.is_empty() && params.read_mode as usize >= info.read_modes.len() {
+            issues.push(format!("read mode {} not available, camera has {}", params.read_mode, info.read_modes.len()));
+        }
+        if params.debayer && !info.is_color {
+            issues.push("debayer requested on a mono camera".to_string());
+        }
+        if params.overscan_mode != OverscanMode::Off {
+            if info.overscan_area.width == 0 || info.overscan_area.height == 0 {
+                issues.push("overscan correction requested but the camera has no overscan area".to_string());
+            }
+            if params.bin_mode != BinMode::Bin1x1 || params.debayer {
+                issues.push("overscan correction needs 1x1 binning without debayer".to_string());
+            }
+        }
+
+        issues
+    }
+
+    pub fn dump_profile(&self, path: &str, description: &str) -> Result<(), ProfileError> {
+        let profile_path = Path::new(path);
+        let mut profile_file = if profile_path.exists() {
+            ProfileFile::load(profile_path)?
+        } else {
+            ProfileFile::default()
+        };
+
+        let key = if self.current_info.serial_num.is_empty() { self.cam_id.clone() } else { self.current_info.serial_num.clone() };
+        // Entries the camera does not know about are carried over from the existing profile
+        let existing = profile_file.cameras.remove(&key);
+        let capture_profiles = existing.as_ref().map(|p| p.capture_profiles.clone()).unwrap_or_default();
+        let filter_names = existing.as_ref().map(|p| p.filter_names.clone()).unwrap_or_default();
+        let filter_sequence = existing.as_ref().map(|p| p.filter_sequence.clone()).unwrap_or_default();
+        profile_file.cameras.insert(key, CameraProfile {
+            description: description.to_string(),
+            params: self.params.clone(),
+            capture_profiles,
+            info: Some(self.current_info.clone()),
+            latency: Some(self.timing_model.get_latency(&self.current_info.model).clone()).filter(|latency| *latency != LatencyModel::default()),
+            filter_names,
+            filter_sequence,
+        });
+
+        profile_file.save(profile_path)
+    }
+
+    pub fn watch_profile(&mut self, path: &str) -> bool {
+        match ConfigWatcher::new(Path::new(path)) {
+            Ok(watcher) => {
+                self.config_watcher = Some(watcher);
+                true
+            },
+            Err(err) => {
+                eprintln!("Cannot watch profile {}, error: {}", path, err);
+                false
+            }
+        }
+    }
+
+    // Call between frames, reloads the watched profile when it changed on disk
+    pub fn poll_profile_reload(&mut self) -> Option<ParamsDiff> {
+        let watcher = self.config_watcher.as_mut()?;
+        if !watcher.poll_changed() {
+            return None
+        }
+        let path = watcher.get_path().to_path_buf();
+
+        let profile = match ProfileFile::load(&path) {
+            Ok(profile_file) => profile_file.find(&self.current_info).cloned(),
+            Err(err) => {
+                eprintln!("Profile reload failure, keeping current settings: {}", err);
+                return None
+            }
+        };
+        if profile.is_none() {
+            eprintln!("Profile reload: no entry for camera {}", self.cam_id);
+            return None
+        }
+
+        let diff = self.apply_params_diff(&profile.unwrap().params);
+        if !diff.is_empty() {
+            println!("Profile reload {}: {}", path.display(), diff);
+        }
+
+        Some(diff)
+    }
+
+    pub fn apply_params_diff(&mut self, params: &CameraParams) -> ParamsDiff {
+        let mut diff = ParamsDiff::default();
+
+        let live_controls = [
+            ControlParam::Exposure, ControlParam::Gain, ControlParam::Offset,
+            ControlParam::RedWB, ControlParam::GreenWB, ControlParam::BlueWB,
+            ControlParam::UsbTraffic, ControlParam::UsbSpeed,
+            ControlParam::Contrast, ControlParam::Brightness, ControlParam::Gamma,
+        ];
+        for control_param in live_controls.iter() {
+            let value = Camera::param_value(params, control_param);
+            if !self.check_force(control_param, value, false) {
+                continue;
+            }
+            let change = ParamChange::new(&control_param.to_string(), Camera::param_value(&self.params, control_param), value);
+            let limits = self.control_limits(control_param);
+            if limits.map_or(false, |l| l.max > l.min && (value < l.min || value > l.max)) {
+                diff.rejected.push((change, "outside camera limits".to_string()));
+                continue;
+            }
+            self.set_control(control_param, value, false);
+            if self.check_force(control_param, value, false) {
+                diff.rejected.push((change, "not accepted by camera".to_string()));
+            } else {
+                diff.applied.push(change);
+            }
+        }
+
+        if params.row_noise_reduction != self.params.row_noise_reduction {
+            let change = ParamChange::new("RowNoiseReduction", self.params.row_noise_reduction, params.row_noise_reduction);
+            if self.set_row_noise_reduction(params.row_noise_reduction) {
+                diff.applied.push(change);
+            } else {
+                diff.rejected.push((change, "not accepted by camera".to_string()));
+            }
+        }
+        if params.remove_rbi != self.params.remove_rbi {
+            let change = ParamChange::new("RemoveRbi", self.params.remove_rbi, params.remove_rbi);
+            if self.set_remove_rbi(params.remove_rbi) {
+                diff.applied.push(change);
+            } else {
+                diff.rejected.push((change, "not accepted by camera".to_string()));
+            }
+        }
+        if params.pattern_noise != self.params.pattern_noise {
+            diff.applied.push(ParamChange::new("PatternNoise", format!("{:?}", self.params.pattern_noise), format!("{:?}", params.pattern_noise)));
+            self.params.pattern_noise = params.pattern_noise.clone();
+        }
+
+        // Everything below needs a re-open or a stream restart, it is applied as one batch
+        let mut restart_params = self.params.clone();
+        let mut restart_changes = Vec::new();
+        if params.bpp != self.params.bpp {
+            restart_changes.push(ParamChange::new("TransferBits", self.params.bpp, params.bpp));
+            restart_params.bpp = params.bpp;
+        }
+        if params.channels != self.params.channels {
+            restart_changes.push(ParamChange::new("Channels", self.params.channels, params.channels));
+            restart_params.channels = params.channels;
+        }
+        if params.roi != self.params.roi {
+            restart_changes.push(ParamChange::new("Roi", format!("{:?}", self.params.roi), format!("{:?}", params.roi)));
+            restart_params.roi = params.roi.clone();
+        }
+        if params.bin_mode != self.params.bin_mode {
+            restart_changes.push(ParamChange::new("BinMode", format!("{:?}", self.params.bin_mode), format!("{:?}", params.bin_mode)));
+            restart_params.bin_mode = params.bin_mode.clone();
+        }
+        if params.read_mode != self.params.read_mode {
+            restart_changes.push(ParamChange::new("ReadMode", self.params.read_mode, params.read_mode));
+            restart_params.read_mode = params.read_mode;
+        }
+        if params.stream_mode != self.params.stream_mode {
+            restart_changes.push(ParamChange::new("StreamMode", format!("{:?}", self.params.stream_mode), format!("{:?}", params.stream_mode)));
+            restart_params.stream_mode = params.stream_mode;
+        }
+        if params.ddr_buffer != self.params.ddr_buffer {
+            restart_changes.push(ParamChange::new("DdrBuffer", format!("{:?}", self.params.ddr_buffer), format!("{:?}", params.ddr_buffer)));
+            restart_params.ddr_buffer = params.ddr_buffer;
+        }
+        if params.ddr_read_threshold != self.params.ddr_read_threshold {
+            restart_changes.push(ParamChange::new("DdrReadThreshold", self.params.ddr_read_threshold, params.ddr_read_threshold));
+            restart_params.ddr_read_threshold = params.ddr_read_threshold;
+        }
+        if params.overscan_mode != self.params.overscan_mode {
+            restart_changes.push(ParamChange::new("OverscanMode", self.params.overscan_mode, params.overscan_mode));
+            restart_params.overscan_mode = params.overscan_mode;
+        }
+        if params.debayer != self.params.debayer {
+            restart_changes.push(ParamChange::new("Debayer", self.params.debayer, params.debayer));
+            restart_params.debayer = params.debayer;
+        }
+
+        if !restart_changes.is_empty() {
+            let issues = self.validate_params(&restart_params);
+            if !issues.is_empty() {
+                let reason = issues.join("; ");
+                diff.rejected.extend(restart_changes.into_iter().map(|change| (change, reason.clone())));
+            } else {
+                self.stop_exposing();
+                self.params = restart_params;
+                self.is_default_set = true;
+                diff.restarted = self.reopen();
+                if diff.restarted {
+                    diff.applied.extend(restart_changes);
+                } else {
+                    diff.rejected.extend(restart_changes.into_iter().map(|change| (change, "camera re-open failed".to_string())));
+                }
+            }
+        }
+
+        diff
+    }
+
+    pub fn set_debayer(&mut self, enable: bool) -> bool {
+        let res = QhyCcd::set_debayer_on_off(self.cam_handle, enable);
+        if res.is_err() {
+            eprintln!("set_debayer failure, error: {}", res.unwrap_err());
+            return false
+        }
+        let _ = self.aloc_buffer_memory();
+        self.params.debayer = enable;
+
+        true
+    }
+
+    pub fn set_bin_mode(&mut self, bin_mode: &BinMode) -> bool {
+        let bin_value = bin_mode.clone() as u32;
+        let res = QhyCcd::set_bin_mode(self.cam_handle, bin_value, bin_value);
+        if res.is_err() {
+            eprintln!("set_bin_mode failure, error: {}", res.unwrap_err());
+            return false
+        }
+        let _ = self.aloc_buffer_memory();
+        self.params.bin_mode = bin_mode.clone();
+
+        true
+    }
+
+    pub fn set_resolution(&mut self, start_x: u32, start_y: u32, width: u32, height: u32) -> bool {
+        let res = QhyCcd::set_resolution(self.cam_handle, start_x, start_y, width, height);
+        if res.is_err() {
+            eprintln!("set_resolution failure, error: {}", res.unwrap_err());
+            return false
+        }
+        self.params.roi.start_x = start_x;
+        self.params.roi.start_y = start_y;
+        self.params.roi.width = width;
+        self.params.roi.height = height;
+
+        if self.is_cam_open {
+            let _ = self.aloc_buffer_memory();
+            let _ = self.reopen();
+        }
+
+        true
+    }
+
+    pub fn set_stream_mode(&mut self, mode: &sdk::StreamMode) -> bool {
+        let res = QhyCcd::set_stream_mode(self.cam_handle, mode);
+        if res.is_err() {
+            eprintln!("set_bin_mode failure, error: {}", res.unwrap_err());
+            return false
+        }
+        self.params.stream_mode = mode.clone();
+        // A mode picked by the caller is kept after the snapshots
+        self.snapshot_stream_mode = None;
+
+        let _ = QhyCcd::init(self.cam_handle);
+
+        true
+    }
+
+    pub fn set_read_mode(&mut self, read_mode: u32) -> bool {
+        if read_mode as usize >= self.current_info.read_modes.len() {
+            if self.is_debug_info {
+                eprintln!("Read mode not available: {}", read_mode);
+            }
+            return false
+        }
+        let res = QhyCcd::set_read_mode(self.cam_handle, read_mode);
+        if res.is_err() {
+            eprintln!("set_read_mode failure, error: {}", res.unwrap_err());
+            return false
+        }
+        self.params.read_mode = read_mode;
+
+        let _ = QhyCcd::init(self.cam_handle);
+        let _ = self.aloc_buffer_memory();
+
+        true
+    }
+
+    pub fn get_temperature(&self) -> Option<f64> {
+        let is_available = QhyCcd::is_control_available(self.cam_handle, &ControlId::ControlCurTemp).unwrap_or(false);
+        if !is_available {
+            return None
+        }
+        Some(QhyCcd::get_param(self.cam_handle, &ControlId::ControlCurTemp))
+    }
+
+    // Loads the maps stored for this camera, frames are corrected with the map closest to the sensor temperature
+    pub fn enable_defect_correction(&mut self, dir: &str, config: DefectConfig) -> bool {
+        let store = DefectStore::new(Path::new(dir));
+        let res = store.load(&self.current_info.serial_num);
+        if res.is_err() {
+            eprintln!("Cannot load defect maps from {}, error: {}", dir, res.unwrap_err());
+            return false
+        }
+        self.defect_maps = res.unwrap();
+        self.defect_store = Some(store);
+        self.defect_config = config;
+        self.defect_checked = None;
+        self.is_defect_correction = true;
+        if self.is_debug_info {
+            println!("Loaded {} defect map(s) for camera: {}", self.defect_maps.len(), self.cam_id);
+        }
+
+        true
+    }
+
+    // Frames are calibrated with the closest masters of the library before defect correction
+    pub fn enable_calibration(&mut self, dir: &str, temperature_step: f64) -> bool {
+        let library = match CalibrationLibrary::open(Path::new(dir), temperature_step) {
+            Ok(library) => library,
+            Err(err) => {
+                eprintln!("Cannot open calibration library {}, error: {}", dir, err);
+                return false
+            }
+        };
+        if self.is_debug_info {
+            println!("Calibration library {} with {} master(s), camera: {}", dir, library.get_masters().len(), self.cam_id);
+        }
+        self.calibration = Some(library);
+
+        true
+    }
+
+    // Gives the library back, e.g. to add masters with calibration::acquire_master
+    pub fn disable_calibration(&mut self) -> Option<CalibrationLibrary> {
+        self.calibration.take()
+    }
+
+    pub fn disable_defect_correction(&mut self) {
+        self.is_defect_correction = false;
+    }
+
+    // Takes the darks with the current exposure and gain, the lens has to be covered
+    pub fn capture_defect_map(&mut self, dark_count: u32, timeout: Duration) -> bool {
+        if self.defect_store.is_none() {
+            eprintln!("Defect correction not enabled, camera: {}", self.cam_id);
+            return false
+        }
+        // Raw darks, calibration would subtract the hot pixels before the map sees them
+        let is_defect_correction = self.is_defect_correction;
+        self.is_defect_correction = false;
+        let calibration = self.calibration.take();
+        self.begin_snapshot_run();
+        let darks: Vec<Frame> = (0..dark_count).map_while(|_| self.snapshot(timeout)).collect();
+        self.end_snapshot_run();
+        self.calibration = calibration;
+        self.is_defect_correction = is_defect_correction;
+        if darks.len() < dark_count as usize {
+            eprintln!("Defect map needs {} darks, got {}", dark_count, darks.len());
+            return false
+        }
+
+        let has_map = DefectMap::build(&darks, self.get_temperature(), &self.defect_config);
+        if has_map.is_none() {
+            return false
+        }
+        let map = has_map.unwrap();
+        if self.is_debug_info {
+            println!("Defect map for camera {}: {} hot, {} dead pixels, {} bad columns, temperature: {:?}",
+                self.cam_id, map.hot_pixels.len(), map.dead_pixels.len(), map.bad_columns.len(), map.temperature);
+        }
+        let res = self.defect_store.as_ref().unwrap().save(&map, &self.defect_config);
+        match res {
+            Ok(maps) => self.defect_maps = maps,
+            Err(err) => {
+                eprintln!("Cannot save defect map, error: {}", err);
+                self.defect_maps.push(map);
+            }
+        }
+        self.defect_checked = None;
+
+        true
+    }
+
+    // True when no stored map is within the temperature tolerance of the sensor
+    pub fn needs_defect_map(&self) -> bool {
+        self.is_defect_correction && !self.is_defect_map_current
+    }
+
+    // With overscan correction on frames are read at the full sensor size and cropped to the effective area
+    pub fn set_overscan_mode(&mut self, mode: OverscanMode) -> bool {
+        let has_overscan = self.current_info.overscan_area.width > 0 && self.current_info.overscan_area.height > 0;
+        if mode != OverscanMode::Off && !has_overscan {
+            eprintln!("No overscan area on camera: {}", self.cam_id);
             return false
         }
-        let mut cam_id = camera_id.to_string();
-        if !self.is_cam_open {
-            if cam_id.is_empty() {
-                if !self.scan_cameras() {
-                    return false
-                }
-                let camera_iter = self.cameras.iter().next();
-                cam_id = camera_iter.unwrap().1.id.clone();
-            } else if self.cam_id != cam_id {
-                self.is_default_set = false;
-            }
-            self.cam_id = cam_id.clone();
-            let has_info = self.cameras.get(&cam_id);
-            if has_info.is_none() {
+        let is_available = QhyCcd::is_control_available(self.cam_handle, &ControlId::CamIgnoreOverscanInterface).unwrap_or(false);
+        if is_available && (mode != OverscanMode::Off || self.params.overscan_mode != OverscanMode::Off) {
+            let ignore_overscan = if mode == OverscanMode::Off { 1.0 } else { 0.0 };
+            let res = QhyCcd::set_param(self.cam_handle, &ControlId::CamIgnoreOverscanInterface, ignore_overscan);
+            if res.is_err() {
+                eprintln!("set_overscan_mode failure, error: {}", res.unwrap_err());
                 return false
             }
-            self.current_info = has_info.unwrap().clone();
+        }
+        self.params.overscan_mode = mode;
 
-            let has_cam_handle = QhyCcd::open(&cam_id);
-            if has_cam_handle.is_err() {
-                self.cam_handle = std::ptr::null_mut();
-                self.cam_id = String::new();
-                self.current_info = CameraInfo::default();
-                eprintln!("OpenQHYCCD failure, camera id: {}", cam_id);
-                return false
-            }
-            self.cam_handle = has_cam_handle.unwrap();
+        true
+    }
 
-            self.set_default_params();
-            self.is_cam_open = true;
+    // Sensor side row noise reduction, the camera applies it before readout
+    pub fn set_row_noise_reduction(&mut self, enable: bool) -> bool {
+        if !self.set_switch(&ControlId::ControlRowNoisere, enable, "row noise reduction") {
+            return false
         }
+        self.params.row_noise_reduction = enable;
 
-        self.is_cam_open
+        true
     }
 
-    pub fn set_debayer(&mut self, enable: bool) -> bool {
-        let res = QhyCcd::set_debayer_on_off(self.cam_handle, enable);
-        if res.is_err() {
-            eprintln!("set_debayer failure, error: {}", res.unwrap_err());
+    // Removes the residual bulk image left by the previous exposure
+    pub fn set_remove_rbi(&mut self, enable: bool) -> bool {
+        if !self.set_switch(&ControlId::ControlRemoveRbi, enable, "RBI removal") {
             return false
         }
-        let _ = self.aloc_buffer_memory();
-        self.params.debayer = enable;
+        self.params.remove_rbi = enable;
 
         true
     }
 
-    pub fn set_bin_mode(&mut self, bin_mode: &BinMode) -> bool {
-        let bin_value = bin_mode.clone() as u32;
-        let res = QhyCcd::set_bin_mode(self.cam_handle, bin_value, bin_value);
+    // Starts the camera's fixed pattern noise calibration, the lens has to be covered
+    pub fn calibrate_fpn(&mut self) -> bool {
+        self.stop_exposing();
+        self.set_switch(&ControlId::CamCalibrateFpnInterface, true, "FPN calibration")
+    }
+
+    pub fn set_pattern_noise(&mut self, config: PatternNoiseConfig) {
+        self.params.pattern_noise = config;
+    }
+
+    pub fn has_ddr(&self) -> bool {
+        QhyCcd::is_control_available(self.cam_handle, &ControlId::ControlDdr).unwrap_or(false)
+    }
+
+    // Takes effect on the next stream start
+    pub fn set_ddr_buffer(&mut self, enable: bool) -> bool {
+        if !self.has_ddr() {
+            if self.is_debug_info {
+                eprintln!("DDR buffer not available on camera: {}", self.cam_id);
+            }
+            return false
+        }
+        let res = QhyCcd::set_param(self.cam_handle, &ControlId::ControlDdr, if enable { 1.0 } else { 0.0 });
         if res.is_err() {
-            eprintln!("set_bin_mode failure, error: {}", res.unwrap_err());
+            eprintln!("set_ddr_buffer failure, error: {}", res.unwrap_err());
             return false
         }
-        let _ = self.aloc_buffer_memory();
-        self.params.bin_mode = bin_mode.clone();
+        self.params.ddr_buffer = Some(enable);
 
         true
     }
 
-    pub fn set_resolution(&mut self, start_x: u32, start_y: u32, width: u32, height: u32) -> bool {
-        let res = QhyCcd::set_resolution(self.cam_handle, start_x, start_y, width, height);
-        if res.is_err() {
-            eprintln!("set_resolution failure, error: {}", res.unwrap_err());
+    pub fn set_ddr_read_threshold(&mut self, threshold: u32) -> bool {
+        let is_available = QhyCcd::is_control_available(self.cam_handle, &ControlId::DdrBufferReadThreshold).unwrap_or(false);
+        if !is_available {
+            if self.is_debug_info {
+                eprintln!("DDR read threshold not available on camera: {}", self.cam_id);
+            }
             return false
         }
-        self.params.roi.start_x = start_x;
-        self.params.roi.start_y = start_y;
-        self.params.roi.width = width;
-        self.params.roi.height = height;
-
-        if self.is_cam_open {
-            let _ = self.aloc_buffer_memory();
-            self.close();
-            let _ = self.open(self.cam_id.clone().as_str());
+        let res = QhyCcd::set_param(self.cam_handle, &ControlId::DdrBufferReadThreshold, threshold as f64);
+        if res.is_err() {
+            eprintln!("set_ddr_read_threshold failure, error: {}", res.unwrap_err());
+            return false
         }
+        self.params.ddr_read_threshold = threshold;
 
         true
     }
 
-    pub fn set_stream_mode(&mut self, mode: &sdk::StreamMode) -> bool {
-        let res = QhyCcd::set_stream_mode(self.cam_handle, mode);
-        if res.is_err() {
-            eprintln!("set_bin_mode failure, error: {}", res.unwrap_err());
-            return false
+    pub fn get_ddr_status(&self) -> Option<DdrStatus> {
+        let is_available = QhyCcd::is_control_available(self.cam_handle, &ControlId::DdrBufferCapacity).unwrap_or(false);
+        if self.params.ddr_buffer == Some(false) || !is_available {
+            return None
         }
-        self.params.stream_mode = mode.clone();
+        let capacity = QhyCcd::get_param_min_max_step(self.cam_handle, &ControlId::DdrBufferCapacity).map_or(0.0, |limits| limits.max);
 
-        let _ = QhyCcd::init(self.cam_handle);
+        Some(DdrStatus {
+            fill: QhyCcd::get_param(self.cam_handle, &ControlId::DdrBufferCapacity),
+            capacity,
+        })
+    }
 
-        true
+    pub fn get_drop_stats(&self) -> &DropStats {
+        self.ddr_monitor.get_stats()
+    }
+
+    pub fn reset_drop_stats(&mut self) {
+        self.ddr_monitor.reset_stats();
     }
 
     pub fn set_control(&mut self, control_param: &ControlParam, value: f64, force: bool) -> bool {
@@ -280,24 +1339,17 @@ impl Camera {
         true
     }
 
+    // Same processing as get_raw_frame, the Mat holds the corrected samples
     pub fn get_frame(&mut self, frame: &mut Mat, debayer : bool) -> bool {
-        let ret = self.get_internal_frame();
-        if !ret {
+        let has_frame = self.get_raw_frame();
+        if has_frame.is_none() {
             return false
         }
-
-        let mat_channels = if self.current_info.is_color && self.params.debayer { 3 } else { 1 };
-        let mat_type = if self.params.bpp == 16 { 
-            core::CV_MAKETYPE(core::CV_16U, mat_channels) 
-        } else {
-            core::CV_MAKETYPE(core::CV_8U, mat_channels) 
-        };
-
-        let img_qhy_res = unsafe { Mat::new_rows_cols_with_data(self.params.roi.height as i32, self.params.roi.width as i32, mat_type, self.img_data.as_ptr() as *mut _, core::Mat_AUTO_STEP) };
-        if img_qhy_res.is_err() {
+        let has_mat = has_frame.unwrap().to_mat();
+        if has_mat.is_none() {
             return false
         }
-        let img_qhy = img_qhy_res.unwrap();
+        let img_qhy = has_mat.unwrap();
 
         if self.current_info.is_color && !self.params.debayer && debayer {
             self.debayer_image(&img_qhy, frame);
@@ -305,9 +1357,84 @@ impl Camera {
             let _ = img_qhy.copy_to(frame);
         }
 
+        if frame.channels() == 3 && !self.digital_wb.is_unity() {
+            white_balance::apply_gains(frame, &self.digital_wb);
+        }
+
+        true
+    }
+
+    pub fn apply_capture_profile(&mut self, profile: &CaptureProfile) -> bool {
+        if !self.is_cam_open {
+            return false
+        }
+        // Nothing is in flight while the profile is applied, the next frame starts with all settings in place
+        self.stop_exposing();
+
+        let mut applied = true;
+        if self.params.read_mode != profile.read_mode && !self.current_info.read_modes.is_empty() {
+            applied &= self.set_read_mode(profile.read_mode);
+        }
+        if self.params.stream_mode != profile.stream_mode {
+            applied &= self.set_stream_mode(&profile.stream_mode);
+        }
+        if self.params.bin_mode != profile.bin_mode {
+            applied &= self.set_bin_mode(&profile.bin_mode);
+        }
+        if self.params.debayer != profile.debayer {
+            applied &= self.set_debayer(profile.debayer);
+        }
+        self.set_control(&ControlParam::TransferBits, profile.bpp as f64, false);
+        self.set_control(&ControlParam::Exposure, profile.exposure as f64, false);
+        self.set_control(&ControlParam::Gain, profile.gain as f64, false);
+        self.params.pattern_noise.mode = profile.pattern_noise;
+
+        if self.is_debug_info {
+            println!("Applied capture profile '{}'", profile.name);
+        }
+
+        applied
+    }
+
+    pub fn auto_white_balance(&mut self, frame: &Mat, method: WhiteBalanceMethod) -> bool {
+        if !self.current_info.is_color {
+            return false
+        }
+        let has_gains = white_balance::compute_gains(frame, method);
+        if has_gains.is_none() {
+            if self.is_debug_info {
+                eprintln!("auto_white_balance: not enough unsaturated pixels to estimate gains");
+            }
+            return false
+        }
+        let gains = has_gains.unwrap();
+
+        if self.has_hardware_wb() {
+            let red_wb = self.current_info.red_wb_limits.clamp(self.params.red_wb * gains.red);
+            let green_wb = self.current_info.green_wb_limits.clamp(self.params.green_wb * gains.green);
+            let blue_wb = self.current_info.blue_wb_limits.clamp(self.params.blue_wb * gains.blue);
+            self.set_control(&ControlParam::RedWB, red_wb, false);
+            self.set_control(&ControlParam::GreenWB, green_wb, false);
+            self.set_control(&ControlParam::BlueWB, blue_wb, false);
+        } else {
+            self.digital_wb = self.digital_wb.combine(&gains);
+        }
+
+        if self.is_debug_info {
+            println!("auto_white_balance: gains R: {:.3}, G: {:.3}, B: {:.3}", gains.red, gains.green, gains.blue);
+        }
+
         true
     }
 
+    pub fn get_digital_wb(&self) -> WhiteBalanceGains {
+        self.digital_wb
+    }
+
+    pub fn reset_digital_wb(&mut self) {
+        self.digital_wb = WhiteBalanceGains::unity();
+    }
+
     pub fn debayer_image(&self, image_in: &Mat, image_out: &mut Mat) {
         if image_in.channels() == 1 {
             let bayer_pattern = Camera::convert_bayer_pattern(self.current_info.bayer_format);
@@ -324,6 +1451,7 @@ impl Camera {
             self.set_control(&ControlParam::GreenWB, 128.0, true);
             self.set_control(&ControlParam::BlueWB, 190.0, true);
             self.set_control(&ControlParam::Exposure, 2000.0, true);
+            self.set_read_mode(0);
             self.set_stream_mode(&sdk::StreamMode::LiveFrame);
             self.set_control(&ControlParam::UsbTraffic, 5.0, true);
             self.set_control(&ControlParam::UsbSpeed, 0.0, true);
@@ -344,12 +1472,38 @@ impl Camera {
             self.set_control(&ControlParam::GreenWB, self.params.green_wb, true);
             self.set_control(&ControlParam::BlueWB, self.params.blue_wb, true);
             self.set_control(&ControlParam::Exposure, self.params.exposure as f64, true);
+            self.set_read_mode(self.params.read_mode);
+            if let Some(ddr_buffer) = self.params.ddr_buffer {
+                self.set_ddr_buffer(ddr_buffer);
+            }
+            if self.params.ddr_read_threshold > 0 {
+                self.set_ddr_read_threshold(self.params.ddr_read_threshold);
+            }
             self.set_stream_mode(&self.params.stream_mode.clone());
             self.set_control(&ControlParam::UsbTraffic, self.params.usb_traffic as f64, true);
             self.set_control(&ControlParam::UsbSpeed, self.params.usb_speed as f64, true);
             self.set_control(&ControlParam::Gain, self.params.gain as f64, true);
             self.set_control(&ControlParam::Offset, self.params.offset as f64, true);
-            self.set_resolution(self.params.roi.start_x, self.params.roi.start_y, self.params.roi.width, self.params.roi.height);
+            if self.params.row_noise_reduction {
+                self.set_row_noise_reduction(true);
+            }
+            if self.params.remove_rbi {
+                self.set_remove_rbi(true);
+            }
+            // A reopened camera starts without headers, frames are only parsed for them while they are written
+            if self.is_gps_enabled && !self.enable_gps(true) {
+                self.is_gps_enabled = false;
+            }
+            self.set_overscan_mode(self.params.overscan_mode);
+            if self.params.overscan_mode == OverscanMode::Off {
+                self.set_resolution(self.params.roi.start_x, self.params.roi.start_y, self.params.roi.width, self.params.roi.height);
+            } else {
+                // Full sensor readout, params.roi keeps the user's area for when overscan is turned off
+                let res = QhyCcd::set_resolution(self.cam_handle, 0, 0, self.current_info.max_image_width, self.current_info.max_image_height);
+                if res.is_err() {
+                    eprintln!("set_resolution failure, error: {}", res.unwrap_err());
+                }
+            }
             self.set_control(&ControlParam::TransferBits, self.params.bpp as f64, true);
             self.set_control(&ControlParam::Channels, self.params.channels as f64, true);
             self.set_bin_mode(&self.params.bin_mode.clone());
@@ -359,29 +1513,76 @@ impl Camera {
         }
     }
 
+    fn set_switch(&mut self, control_id: &ControlId, enable: bool, name: &str) -> bool {
+        let is_available = QhyCcd::is_control_available(self.cam_handle, control_id).unwrap_or(false);
+        if !is_available {
+            if self.is_debug_info {
+                eprintln!("{} not available on camera: {}", name, self.cam_id);
+            }
+            return false
+        }
+        let res = QhyCcd::set_param(self.cam_handle, control_id, if enable { 1.0 } else { 0.0 });
+        if res.is_err() {
+            eprintln!("Cannot set {}, error: {}", name, res.unwrap_err());
+            return false
+        }
+
+        true
+    }
+
+    fn has_hardware_wb(&self) -> bool {
+        [ControlId::ControlWbr, ControlId::ControlWbg, ControlId::ControlWbb].iter().all(|control_id| {
+            QhyCcd::is_control_available(self.cam_handle, control_id).unwrap_or(false)
+        })
+    }
+
     fn check_force(&mut self, control_param: &ControlParam, value: f64, force: bool) -> bool {
         if !force {
-            let value_to_check = match control_param {
-                ControlParam::RedWB => self.params.red_wb,
-                ControlParam::GreenWB => self.params.green_wb,
-                ControlParam::BlueWB => self.params.blue_wb,
-                ControlParam::Brightness => self.params.brightness,
-                ControlParam::Channels => self.params.channels as f64,
-                ControlParam::Contrast => self.params.contrast,
-                ControlParam::Exposure => self.params.exposure as f64,
-                ControlParam::UsbTraffic => self.params.usb_traffic as f64,
-                ControlParam::UsbSpeed => self.params.usb_speed as f64,
-                ControlParam::Gain => self.params.gain as f64,
-                ControlParam::Offset => self.params.offset as f64,
-                ControlParam::TransferBits => self.params.bpp as f64,
-                ControlParam::Gamma => self.params.gamma,
-            };
+            let value_to_check = Camera::param_value(&self.params, control_param);
            return  value_to_check != value
         }
 
         true
     }
 
+    fn param_value(params: &CameraParams, control_param: &ControlParam) -> f64 {
+        match control_param {
+            ControlParam::RedWB => params.red_wb,
+            ControlParam::GreenWB => params.green_wb,
+            ControlParam::BlueWB => params.blue_wb,
+            ControlParam::Brightness => params.brightness,
+            ControlParam::Channels => params.channels as f64,
+            ControlParam::Contrast => params.contrast,
+            ControlParam::Exposure => params.exposure as f64,
+            ControlParam::UsbTraffic => params.usb_traffic as f64,
+            ControlParam::UsbSpeed => params.usb_speed as f64,
+            ControlParam::Gain => params.gain as f64,
+            ControlParam::Offset => params.offset as f64,
+            ControlParam::TransferBits => params.bpp as f64,
+            ControlParam::Gamma => params.gamma,
+        }
+    }
+
+    fn control_limits(&self, control_param: &ControlParam) -> Option<&ParamLimits> {
+        match control_param {
+            ControlParam::Gain => Some(&self.current_info.gain_limits),
+            ControlParam::Offset => Some(&self.current_info.offset_limits),
+            ControlParam::UsbTraffic => Some(&self.current_info.usb_traffic_limits),
+            ControlParam::RedWB => Some(&self.current_info.red_wb_limits),
+            ControlParam::GreenWB => Some(&self.current_info.green_wb_limits),
+            ControlParam::BlueWB => Some(&self.current_info.blue_wb_limits),
+            _ => None,
+        }
+    }
+
+    pub fn reopen(&mut self) -> bool {
+        // close() forgets the id, keep it so open() takes the re-apply path for the same camera
+        let cam_id = self.cam_id.clone();
+        self.close();
+        self.cam_id = cam_id.clone();
+        self.open(&cam_id)
+    }
+
     fn change_internal_param(&mut self, control_param: &ControlParam, value: f64) {
         match control_param {
             ControlParam::RedWB => self.params.red_wb = value,
@@ -408,15 +1609,14 @@ impl Camera {
                 },
                 ControlParam::TransferBits => {
                     self.aloc_buffer_memory();
-                    self.close();
-                    self.open(&self.cam_id.clone());
+                    self.reopen();
                 },
                 _ => {}
             };
         }
     }
 
-    fn convert_bayer_pattern(bayer_format: BayerFormat) -> i32 {
+    pub(crate) fn convert_bayer_pattern(bayer_format: BayerFormat) -> i32 {
         match bayer_format {
             BayerFormat::GB => opencv::imgproc::COLOR_BayerGR2BGR,
             BayerFormat::GR => opencv::imgproc::COLOR_BayerGB2BGR,
@@ -439,7 +1639,11 @@ impl Camera {
         let start = Instant::now();
 
         if self.params.stream_mode == sdk::StreamMode::SingleFrame {
-            if !self.get_single(&mut w, &mut h, &mut bpp, &mut channels) {
+            let timeout = self.get_frame_timeout();
+            let res = self.get_single(&mut w, &mut h, &mut bpp, &mut channels, timeout);
+            // Every single frame needs its own exposure, re-arm on the next call
+            self.is_exposing = false;
+            if !res {
                 return false
             }
         } else {
@@ -451,10 +1655,148 @@ impl Camera {
         let stop = Instant::now();
         let duration = stop.duration_since(start);
         self.last_frame_capture_time = duration.as_secs_f64();
+        self.last_image = ImageResult { width: w, height: h, bpp, channels };
 
         true
     }
 
+    fn build_frame(&mut self) -> Frame {
+        let mut frame = Frame::new(self.last_image.width, self.last_image.height, self.last_image.bpp, self.last_image.channels);
+        let len = frame.data_len().min(self.img_data.len());
+        frame.data[..len].copy_from_slice(&self.img_data[..len]);
+        frame.metadata = FrameMetadata {
+            cam_id: self.cam_id.clone(),
+            model: self.current_info.model.clone(),
+            serial_num: self.current_info.serial_num.clone(),
+            bayer_format: self.current_info.bayer_format,
+            params: self.params.clone(),
+            versions: self.current_info.versions.clone(),
+            ..FrameMetadata::default()
+        };
+
+        if self.is_gps_enabled {
+            let has_header = GpsHeader::parse(&frame.data);
+            if has_header.is_some() {
+                let header = has_header.unwrap();
+                let row_bytes = frame.width as usize * frame.channels.max(1) as usize * frame.bytes_per_sample();
+                let is_bayer = frame.is_bayer();
+                gps::strip_header(&mut frame.data, row_bytes, is_bayer);
+                frame.metadata.exposure_start = Some(header.start_time());
+                frame.metadata.exposure_end = Some(header.end_time());
+                frame.metadata.gps = Some(header);
+            } else if self.is_debug_info {
+                eprintln!("GPS mode enabled but frame has no GPS header, camera: {}", self.cam_id);
+            }
+        }
+
+        if self.params.pattern_noise.mode != PatternNoiseMode::Off {
+            let mut regions = self.params.pattern_noise.masks.clone();
+            let is_full_frame = frame.width == self.current_info.max_image_width && frame.height == self.current_info.max_image_height;
+            if self.params.pattern_noise.use_overscan && is_full_frame {
+                regions.push(self.current_info.overscan_area.clone());
+            }
+            pattern_noise::remove(&mut frame, &self.params.pattern_noise, &regions);
+        }
+
+        if self.params.overscan_mode != OverscanMode::Off {
+            let is_full_frame = frame.width == self.current_info.max_image_width && frame.height == self.current_info.max_image_height;
+            if is_full_frame {
+                frame.metadata.overscan_bias = overscan::correct(&mut frame, &self.current_info.overscan_area, &self.current_info.effective_area, self.params.overscan_mode);
+            } else if self.is_debug_info {
+                eprintln!("Overscan correction skipped, frame {}x{} is not the full sensor, camera: {}", frame.width, frame.height, self.cam_id);
+            }
+        }
+
+        // Read for every frame, masters, FITS headers and sidecars all carry it
+        self.refresh_temperature();
+        frame.metadata.sensor_temperature = self.sensor_temperature;
+        if let Some(calibration) = self.calibration.as_mut() {
+            frame.metadata.calibration = calibration.calibrate(&mut frame);
+        }
+        if self.is_defect_correction {
+            self.correct_defects(&mut frame);
+        }
+
+        if frame.metadata.gps.is_none() {
+            // A single frame starts when it is armed, a triggered or streamed one only has its arrival time
+            let known_start = if self.params.stream_mode == sdk::StreamMode::SingleFrame && self.trigger_mode == TriggerMode::Off {
+                self.exposure_started_utc
+            } else {
+                None
+            };
+            frame.metadata.timing = self.estimate_timing(frame.data_len(), known_start);
+            frame.metadata.exposure_start = frame.metadata.timing.as_ref().map(|timing| timing.exposure_start);
+            frame.metadata.exposure_end = frame.metadata.timing.as_ref().map(|timing| timing.exposure_end);
+        }
+
+        frame
+    }
+
+    // The sensor temperature is read at most every 30s
+    fn refresh_temperature(&mut self) -> bool {
+        let is_stale = self.temperature_checked.map_or(true, |checked| checked.elapsed() >= Duration::from_secs(30));
+        if is_stale {
+            self.sensor_temperature = self.get_temperature();
+            self.temperature_checked = Some(Instant::now());
+        }
+
+        is_stale
+    }
+
+    // The map is picked again whenever the temperature was read
+    fn correct_defects(&mut self, frame: &mut Frame) {
+        if self.defect_checked.is_none() || self.temperature_checked != self.defect_checked {
+            self.defect_checked = self.temperature_checked;
+            let has_map = defects::select_map(&self.defect_maps, frame, self.sensor_temperature);
+            let is_current = has_map.map_or(false, |map| match (map.temperature, self.sensor_temperature) {
+                (Some(map_temperature), Some(temperature)) => (map_temperature - temperature).abs() < self.defect_config.temperature_tolerance,
+                _ => true,
+            });
+            if !is_current && self.is_defect_map_current {
+                eprintln!("No defect map for exposure {}us, gain {} within {}C of sensor temperature {:?}, camera: {}",
+                    frame.metadata.params.exposure, frame.metadata.params.gain, self.defect_config.temperature_tolerance,
+                    self.sensor_temperature, self.cam_id);
+            }
+            self.is_defect_map_current = is_current;
+        }
+
+        let has_map = defects::select_map(&self.defect_maps, frame, self.sensor_temperature);
+        if let Some(map) = has_map {
+            if map.correct(frame) {
+                frame.metadata.defects_corrected = map.defect_count();
+            }
+        }
+    }
+
+    fn estimate_timing(&mut self, frame_bytes: usize, known_start: Option<SystemTime>) -> Option<FrameTiming> {
+        let received_utc = self.frame_received_utc?;
+        let received_at = self.frame_received_at?;
+        let exposure = self.get_exposure_duration();
+        let timing = self.timing_model.estimate(&self.current_info.model, exposure, frame_bytes, received_utc, received_at,
+            known_start, self.last_poll_interval);
+
+        Some(timing)
+    }
+
+    fn restore_stream_mode(&mut self) {
+        if let Some(mode) = self.snapshot_stream_mode.take() {
+            if mode != self.params.stream_mode {
+                self.set_stream_mode(&mode);
+            }
+        }
+    }
+
+    fn stop_exposing(&mut self) {
+        if self.is_exposing {
+            if self.params.stream_mode == sdk::StreamMode::SingleFrame {
+                let _ = QhyCcd::cancel_exposing_and_readout(self.cam_handle);
+            } else {
+                let _ = QhyCcd::stop_live(self.cam_handle);
+            }
+            self.is_exposing = false;
+        }
+    }
+
     fn begin_exposing(&mut self) -> bool {
         if self.params.stream_mode == sdk::StreamMode::SingleFrame {
             if self.is_exposing {
@@ -463,9 +1805,8 @@ impl Camera {
             let rc = QhyCcd::exp_single_frame(self.cam_handle);
             if rc.is_err() {                
                 let error = rc.unwrap_err();
-                if error == sdk::SdkError::ReadDirectly {
-                    thread::sleep(Duration::from_micros(10));
-                } else {
+                // ReadDirectly is not a failure, the frame is fetched by get_single as usual
+                if error != sdk::SdkError::ReadDirectly {
                     eprintln!("exp_single_frame failed: {}", error);
                     self.is_exposing = false;
                     return false
@@ -481,18 +1822,29 @@ impl Camera {
                 self.is_exposing = false;
                 return false
             }
+            self.ddr_monitor.restart();
         }
         self.is_exposing = true;
+        self.exposure_started = Some(Instant::now());
+        self.exposure_started_utc = Some(SystemTime::now());
+        self.readout_started_utc = None;
 
         self.is_exposing
     }
 
-    fn get_single(&mut self, w: &mut u32, h: &mut u32, bpp: &mut u32, channels: &mut u32) -> bool {
+    fn get_single(&mut self, w: &mut u32, h: &mut u32, bpp: &mut u32, channels: &mut u32, timeout: Duration) -> bool {
+        let exposure_start = self.exposure_started.unwrap_or_else(Instant::now);
+        self.wait_exposure_done(exposure_start, timeout);
+
+        let mut backoff = PollBackoff::new(Duration::from_micros(500), Duration::from_millis(20));
         let mut tries = 0;
 
         loop {
             let res = QhyCcd::get_single_frame(self.cam_handle, &mut self.img_data[..]);
             if res.is_ok() {
+                self.frame_received_utc = Some(SystemTime::now());
+                self.frame_received_at = Some(Instant::now());
+                self.last_poll_interval = backoff.last;
                 let frame_data = res.unwrap();
                 *w = frame_data.width;
                 *h = frame_data.height;
@@ -504,23 +1856,32 @@ impl Camera {
                 break;
             }
             tries += 1;
-            if tries > 1000 {
+            if exposure_start.elapsed() > timeout {
                 if self.is_debug_info {
-                    eprintln!("get_single_frame failed: {}, tries: {}", res.unwrap_err(), tries);
+                    eprintln!("get_single_frame failed: {}, tries: {}, waited: {:?}", res.unwrap_err(), tries, exposure_start.elapsed());
                 }
                 return false
             }
+            backoff.wait();
         }
 
-        true // Placeholder value
+        true
     }
 
     fn get_live(&mut self, w: &mut u32, h: &mut u32, bpp: &mut u32, channels: &mut u32) -> bool {
+        let start = Instant::now();
+        let timeout = self.get_frame_timeout();
+        // Frames arrive about once per exposure, there is no point polling much faster than that
+        let max_poll = (self.get_exposure_duration() / 4).clamp(Duration::from_millis(1), Duration::from_millis(50));
+        let mut backoff = PollBackoff::new(Duration::from_micros(200), max_poll);
         let mut tries = 0;
 
         loop {
             let res = QhyCcd::get_live_frame(self.cam_handle, &mut self.img_data[..]);
             if res.is_ok() {
+                self.frame_received_utc = Some(SystemTime::now());
+                self.frame_received_at = Some(Instant::now());
+                self.last_poll_interval = backoff.last;
                 let frame_data = res.unwrap();
                 *w = frame_data.width;
                 *h = frame_data.height;
@@ -532,15 +1893,51 @@ impl Camera {
                 break;
             }
             tries += 1;
-            if tries > 1000 {
+            if start.elapsed() > timeout {
+                let cause = self.ddr_monitor.frame_timeout();
                 if self.is_debug_info {
-                    eprintln!("get_live_frame failed: {}, tries: {}", res.unwrap_err(), tries);
+                    eprintln!("get_live_frame failed: {}, tries: {}, waited: {:?}", res.unwrap_err(), tries, start.elapsed());
                 }
+                // Reported like the gaps found on the next frame
+                eprintln!("Dropped frame, camera: {}, cause: {}", self.cam_id, cause);
                 return false
             }
+            backoff.wait();
+        }
+
+        let ddr_status = self.get_ddr_status();
+        self.ddr_monitor.update(ddr_status);
+        if let Some((cause, count)) = self.ddr_monitor.frame_received(self.get_exposure_duration()) {
+            eprintln!("Dropped {} frame(s), camera: {}, cause: {}", count, self.cam_id, cause);
+        }
+        if self.is_debug_info && ddr_status.is_some() {
+            let status = ddr_status.unwrap();
+            println!("DDR buffer: {} of {}", status.fill, status.capacity);
+        }
+
+        true
+    }
+
+    // Sleeps through the exposure in slices, leaving early when the camera reports it is reading out
+    fn wait_exposure_done(&mut self, exposure_start: Instant, timeout: Duration) {
+        let exposure = self.get_exposure_duration();
+        let slice = Duration::from_millis(100);
+
+        loop {
+            let elapsed = exposure_start.elapsed();
+            if elapsed >= exposure || elapsed >= timeout {
+                break;
+            }
+            if let Ok(CameraStatus::Reading) = QhyCcd::get_camera_status(self.cam_handle) {
+                self.readout_started_utc.get_or_insert_with(SystemTime::now);
+                break;
+            }
+            thread::sleep((exposure - elapsed).min(slice));
         }
+    }
 
-        true // Placeholder value
+    fn get_exposure_duration(&self) -> Duration {
+        Duration::from_micros(self.params.exposure as u64)
     }
 
     fn scan_cameras(&mut self) -> bool {
@@ -571,6 +1968,26 @@ impl Camera {
         true
     }
 
+    fn read_versions(handle: *mut c_bindings::QhyCcdHandle) -> CameraVersions {
+        let read_control = |control_id: &ControlId| {
+            let is_available = QhyCcd::is_control_available(handle, control_id).unwrap_or(false);
+            if is_available { Some(QhyCcd::get_param(handle, control_id) as u32) } else { None }
+        };
+        // Cameras have one or two FPGAs, the first index that fails ends the list
+        let fpga = (0..4u8)
+            .map_while(|index| QhyCcd::get_fpga_version(handle, index).ok())
+            .map(|version| format!("{}.{}.{}.{}", version[0], version[1], version[2], version[3]))
+            .collect();
+
+        CameraVersions {
+            sdk: QhyCcd::get_sdk_version().unwrap_or_default(),
+            firmware: QhyCcd::get_fw_version(handle).unwrap_or_default(),
+            fpga,
+            sensor_ulvo_status: read_control(&ControlId::CamSensorUlvoStatus),
+            init_config_from_flash: read_control(&ControlId::CamInitConfigFromFlash),
+        }
+    }
+
     fn fill_camera_info(&mut self, cam_id: &String) -> Option<CameraInfo> {
         let res = QhyCcd::open(cam_id);
         if res.is_err() {
@@ -597,6 +2014,9 @@ impl Camera {
         let red_wb_limits = QhyCcd::get_param_min_max_step(handle, &sdk::ControlId::ControlWbr).unwrap();
         let green_wb_limits = QhyCcd::get_param_min_max_step(handle, &sdk::ControlId::ControlWbg).unwrap();
         let blue_wb_limits = QhyCcd::get_param_min_max_step(handle, &sdk::ControlId::ControlWbb).unwrap();
+        let num_read_modes = QhyCcd::get_number_of_read_modes(handle).unwrap_or(0);
+        let read_modes = (0..num_read_modes).map(|mode| QhyCcd::get_read_mode_name(handle, mode).unwrap_or(format!("Mode {}", mode))).collect();
+        let versions = Camera::read_versions(handle);
 
         let ci = CameraInfo {
             id: cam_id.to_string(),
@@ -617,12 +2037,14 @@ impl Camera {
             has_bin2x2_mode,
             has_bin3x3_mode,
             has_bin4x4_mode,
+            read_modes,
             gain_limits: ParamLimits { max: gain_limits.max, min: gain_limits.min, step: gain_limits.step },
             offset_limits: ParamLimits { max: offset_limits.max, min: offset_limits.min, step: offset_limits.step },
             usb_traffic_limits: ParamLimits { max: usb_traffic_limits.max, min: usb_traffic_limits.min, step: usb_traffic_limits.step },
             red_wb_limits: ParamLimits { max: red_wb_limits.max, min: red_wb_limits.min, step: red_wb_limits.step },
             green_wb_limits: ParamLimits { max: green_wb_limits.max, min: green_wb_limits.min, step: green_wb_limits.step },
             blue_wb_limits: ParamLimits { max: blue_wb_limits.max, min: blue_wb_limits.min, step: blue_wb_limits.step },
+            versions,
         };
 
         let _ = QhyCcd::close(handle);
@@ -659,12 +2081,61 @@ pub struct Camera {
     params: CameraParams,
     current_info: CameraInfo,
     last_frame_capture_time: f64,
+    digital_wb: WhiteBalanceGains,
+    config_watcher: Option<ConfigWatcher>,
+    frame_timeout: Option<Duration>,
+    exposure_started: Option<Instant>,
+    exposure_started_utc: Option<SystemTime>,
+    readout_started_utc: Option<SystemTime>,
+    last_image: ImageResult,
+    trigger_mode: TriggerMode,
+    timing_model: TimingModel,
+    frame_received_utc: Option<SystemTime>,
+    frame_received_at: Option<Instant>,
+    last_poll_interval: Duration,
+    ddr_monitor: DdrMonitor,
+    defect_store: Option<DefectStore>,
+    defect_maps: Vec<DefectMap>,
+    defect_config: DefectConfig,
+    defect_checked: Option<Instant>,
+    sensor_temperature: Option<f64>,
+    temperature_checked: Option<Instant>,
+    calibration: Option<CalibrationLibrary>,
+    // Stream mode start_snapshot switched away from
+    snapshot_stream_mode: Option<sdk::StreamMode>,
 
     is_debug_info: bool,
     is_cam_init: bool,
     is_cam_open: bool,
     is_exposing: bool,
     is_default_set: bool,
+    is_sdk_owner: bool,
+    is_gps_enabled: bool,
+    is_defect_correction: bool,
+    is_defect_map_current: bool,
+    is_snapshot_run: bool,
+}
+
+// The SDK handle is only ever used through &mut Camera, so moving a Camera to its capture thread is sound
+unsafe impl Send for Camera {}
+
+struct PollBackoff {
+    current: Duration,
+    max: Duration,
+    // A frame found on the first poll may have been waiting since up to a full interval
+    last: Duration,
+}
+
+impl PollBackoff {
+    fn new(min: Duration, max: Duration) -> Self {
+        PollBackoff { current: min.min(max), max, last: max }
+    }
+
+    fn wait(&mut self) {
+        thread::sleep(self.current);
+        self.last = self.current;
+        self.current = (self.current * 2).min(self.max);
+    }
 }
 
 impl CameraInfo {
@@ -698,6 +2169,8 @@ impl fmt::Display for CameraInfo {
         Bits per Pixel: {}\n\
         Camera is color: {}, Bayer Pattern: {}\n\
         Available Bin modes:{}\n\
+        Read modes: {}\n\
+        {}\n\
         Gain Limits: Min: {}, Max: {}, Step: {}\n\
         Offset Limits: Min: {}, Max: {}, Step: {}\n\
         Usb Traffic Limits: Min: {}, Max: {}, Step: {}",
@@ -722,6 +2195,8 @@ impl fmt::Display for CameraInfo {
        if self.is_color { "Yes" } else { "No" },
        self.bayer_format_to_string(),
        bin_modes,
+       self.read_modes.join(", "),
+       self.versions,
        self.gain_limits.min,
        self.gain_limits.max,
        self.gain_limits.step,
@@ -734,6 +2209,18 @@ impl fmt::Display for CameraInfo {
     }
 }
 
+impl fmt::Display for CameraVersions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let optional = |value: Option<u32>| value.map_or("n/a".to_string(), |value| value.to_string());
+        write!(f, "SDK version: {}, Firmware version: {}, FPGA versions: {}, ULVO status: {}, Init config from flash: {}",
+            self.sdk,
+            self.firmware,
+            if self.fpga.is_empty() { "n/a".to_string() } else { self.fpga.join(", ") },
+            optional(self.sensor_ulvo_status),
+            optional(self.init_config_from_flash))
+    }
+}
+
 impl Default for CameraParams {
     fn default() -> Self {
         CameraParams {
@@ -757,8 +2244,18 @@ impl Default for CameraParams {
             gain: 0,
             offset: 0,
             bin_mode: BinMode::Bin1x1,
+            read_mode: 0,
 
             bpp: 0,
+
+            ddr_buffer: None,
+            ddr_read_threshold: 0,
+
+            overscan_mode: OverscanMode::Off,
+
+            row_noise_reduction: false,
+            remove_rbi: false,
+            pattern_noise: PatternNoiseConfig::default(),
         }
     }
 }
@@ -774,11 +2271,128 @@ impl Default for Camera {
             params: CameraParams::default(),
             current_info: CameraInfo::default(),
             last_frame_capture_time: 0.0,
+            digital_wb: WhiteBalanceGains::unity(),
+            config_watcher: None,
+            frame_timeout: None,
+            exposure_started: None,
+            exposure_started_utc: None,
+            readout_started_utc: None,
+            last_image: ImageResult::default(),
+            trigger_mode: TriggerMode::Off,
+            timing_model: TimingModel::default(),
+            frame_received_utc: None,
+            frame_received_at: None,
+            last_poll_interval: Duration::ZERO,
+            ddr_monitor: DdrMonitor::default(),
+            defect_store: None,
+            defect_maps: Vec::new(),
+            defect_config: DefectConfig::default(),
+            defect_checked: None,
+            sensor_temperature: None,
+            temperature_checked: None,
+            calibration: None,
+            snapshot_stream_mode: None,
 
             is_cam_init: false,
             is_cam_open: false,
             is_exposing: false,
             is_default_set: false,
+            is_sdk_owner: true,
+            is_gps_enabled: false,
+            is_defect_correction: false,
+            is_defect_map_current: true,
+            is_snapshot_run: false,
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trigger::SimulatedTrigger;
+
+    // Armed for a triggered snapshot without touching the SDK, the stream mode to restore is
+    // the current one so restoring it is a no-op
+    fn armed_camera(mode: TriggerMode) -> Camera {
+        let mut camera = Camera::default();
+        camera.trigger_mode = mode;
+        camera.params.stream_mode = sdk::StreamMode::SingleFrame;
+        camera.snapshot_stream_mode = Some(sdk::StreamMode::SingleFrame);
+        camera.exposure_started = Some(Instant::now());
+        camera.is_exposing = true;
+        camera
+    }
+
+    // 1920x1080 colour sensor with gain 0..500, as read from the SDK on open
+    fn opened_info() -> CameraInfo {
+        CameraInfo {
+            max_image_width: 1920,
+            max_image_height: 1080,
+            max_bpp: 16,
+            is_color: true,
+            has_bin1x1_mode: true,
+            gain_limits: ParamLimits { min: 0.0, max: 500.0, step: 1.0 },
+            offset_limits: ParamLimits { min: 0.0, max: 255.0, step: 1.0 },
+            ..CameraInfo::default()
+        }
+    }
+
+    fn valid_params() -> CameraParams {
+        CameraParams {
+            roi: CameraArea { start_x: 0, start_y: 0, width: 1920, height: 1080 },
+            bin_mode: BinMode::Bin1x1,
+            bpp: 16,
+            ..CameraParams::default()
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn profile_params_within_the_camera_limits() {
+        let mut camera = Camera::default();
+        camera.current_info = opened_info();
+        assert_eq!(camera.validate_params(&valid_params()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn profile_roi_and_gain_out_of_range() {
+        let mut camera = Camera::default();
+        camera.current_info = opened_info();
+
+        let mut params = valid_params();
+        params.roi = CameraArea { start_x: 100, start_y: 0, width: 1920, height: 1080 };
+        params.gain = 600;
+        let issues = camera.validate_params(&params);
+        assert_eq!(issues.len(), 2, "{:?}", issues);
+        assert!(issues[0].starts_with("gain 600 outside"), "{:?}", issues);
+        assert!(issues[1].starts_with("roi 1920x1080+100+0 outside 1920x1080"), "{:?}", issues);
+
+        // An empty roi or one that overflows u32 is just as invalid
+        params = valid_params();
+        params.roi.height = 0;
+        assert_eq!(camera.validate_params(&params).len(), 1);
+        params = valid_params();
+        params.roi.start_y = u32::MAX;
+        assert_eq!(camera.validate_params(&params).len(), 1);
+    }
+
+    #[test]
+    fn pulse_timeout_cancels_the_snapshot() {
+        let trigger = SimulatedTrigger::new();
+        let pulses = trigger.subscribe();
+        let mut camera = armed_camera(TriggerMode::Software);
+        assert!(camera.capture_on_pulse(&pulses, Duration::from_millis(20)).is_none());
+        assert!(!camera.is_exposing);
+        assert!(camera.exposure_started.is_none());
+        assert!(camera.snapshot_stream_mode.is_none());
+    }
+
+    #[test]
+    fn pulse_is_left_queued_without_a_trigger_mode() {
+        let trigger = SimulatedTrigger::new();
+        let pulses = trigger.subscribe();
+        let mut camera = armed_camera(TriggerMode::Off);
+        trigger.fire();
+        assert!(camera.capture_on_pulse(&pulses, Duration::from_millis(20)).is_none());
+        assert!(camera.is_exposing);
+        assert_eq!(pulses.try_recv().map(|pulse| pulse.sequence), Ok(1));
+    }
+}
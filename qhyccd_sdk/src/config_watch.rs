@@ -0,0 +1,109 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use inotify::{Inotify, WatchMask};
+
+// Editors and deploy tools usually replace the file instead of writing it in place,
+// so the parent directory is watched and events are filtered by file name
+pub struct ConfigWatcher {
+    path: PathBuf,
+    file_name: OsString,
+    inotify: Inotify,
+    buffer: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamChange {
+    pub name: String,
+    pub old_value: String,
+    pub new_value: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParamsDiff {
+    pub applied: Vec<ParamChange>,
+    pub rejected: Vec<(ParamChange, String)>,
+    pub restarted: bool,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: &Path) -> io::Result<ConfigWatcher> {
+        let file_name = path.file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "config path has no file name"))?
+            .to_os_string();
+        let parent = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        let inotify = Inotify::init()?;
+        inotify.watches().add(&parent, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE)?;
+
+        Ok(ConfigWatcher { path: path.to_path_buf(), file_name, inotify, buffer: vec![0; 4096] })
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    // Non blocking, drains all pending events and reports whether the watched file changed
+    pub fn poll_changed(&mut self) -> bool {
+        let mut changed = false;
+        loop {
+            match self.inotify.read_events(&mut self.buffer) {
+                Ok(events) => {
+                    let mut has_events = false;
+                    for event in events {
+                        has_events = true;
+                        if event.name.map_or(false, |name| name == self.file_name.as_os_str()) {
+                            changed = true;
+                        }
+                    }
+                    if !has_events {
+                        break;
+                    }
+                },
+                Err(err) => {
+                    if err.kind() != io::ErrorKind::WouldBlock {
+                        eprintln!("Config watcher read failure: {}", err);
+                    }
+                    break;
+                }
+            }
+        }
+
+        changed
+    }
+}
+
+impl ParamChange {
+    pub fn new<T: fmt::Display>(name: &str, old_value: T, new_value: T) -> Self {
+        ParamChange { name: name.to_string(), old_value: old_value.to_string(), new_value: new_value.to_string() }
+    }
+}
+
+impl ParamsDiff {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.rejected.is_empty()
+    }
+}
+
+impl fmt::Display for ParamChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.name, self.old_value, self.new_value)
+    }
+}
+
+impl fmt::Display for ParamsDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Applied {} change(s){}", self.applied.len(), if self.restarted { " (stream restarted)" } else { "" })?;
+        for change in self.applied.iter() {
+            write!(f, "\n  applied  {}", change)?;
+        }
+        for (change, reason) in self.rejected.iter() {
+            write!(f, "\n  rejected {} ({})", change, reason)?;
+        }
+        Ok(())
+    }
+}
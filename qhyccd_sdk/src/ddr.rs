@@ -0,0 +1,150 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+use derive_more::Display;
+
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropCause {
+    // No frame within the frame timeout
+    Timeout,
+    // Frames missing while the on-board buffer was full, the camera overwrote them
+    DdrOverflow,
+    // Frames missing with room left in the buffer, lost on the USB side
+    FrameGap,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DdrStatus {
+    pub fill: f64,
+    // Zero when the camera does not report the buffer size
+    pub capacity: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DropStats {
+    pub frames: u64,
+    pub timeouts: u64,
+    pub ddr_overflows: u64,
+    pub frame_gaps: u64,
+    pub last_cause: Option<DropCause>,
+}
+
+pub struct DdrMonitor {
+    overflow_ratio: f64,
+    status: Option<DdrStatus>,
+    is_full: bool,
+    last_frame: Option<Instant>,
+    frame_interval: Option<Duration>,
+    stats: DropStats,
+}
+
+// A frame arriving this many frame intervals after the previous one means frames went missing
+const GAP_FACTOR: f64 = 1.8;
+
+impl DdrStatus {
+    pub fn fill_ratio(&self) -> Option<f64> {
+        if self.capacity > 0.0 { Some(self.fill / self.capacity) } else { None }
+    }
+}
+
+impl DropStats {
+    pub fn get_dropped(&self) -> u64 {
+        self.timeouts + self.ddr_overflows + self.frame_gaps
+    }
+
+    fn record(&mut self, cause: DropCause, count: u64) {
+        match cause {
+            DropCause::Timeout => self.timeouts += count,
+            DropCause::DdrOverflow => self.ddr_overflows += count,
+            DropCause::FrameGap => self.frame_gaps += count,
+        }
+        self.last_cause = Some(cause);
+    }
+}
+
+impl DdrMonitor {
+    pub fn new(overflow_ratio: f64) -> Self {
+        DdrMonitor {
+            overflow_ratio,
+            status: None,
+            is_full: false,
+            last_frame: None,
+            frame_interval: None,
+            stats: DropStats::default(),
+        }
+    }
+
+    pub fn get_status(&self) -> Option<DdrStatus> {
+        self.status
+    }
+
+    pub fn get_stats(&self) -> &DropStats {
+        &self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = DropStats::default();
+    }
+
+    // A new stream has no previous frame to measure a gap against
+    pub fn restart(&mut self) {
+        self.last_frame = None;
+        self.frame_interval = None;
+        self.is_full = false;
+    }
+
+    // The full flag stays set until a frame is received with the buffer below the overflow level,
+    // so a gap right after the buffer drained is still put down to the overflow
+    pub fn update(&mut self, status: Option<DdrStatus>) {
+        self.status = status;
+        let is_full = status.and_then(|status| status.fill_ratio()).map_or(false, |ratio| ratio >= self.overflow_ratio);
+        self.is_full |= is_full;
+    }
+
+    // Returns the frames missing since the previous one, the interval never drops below the exposure
+    pub fn frame_received(&mut self, exposure: Duration) -> Option<(DropCause, u64)> {
+        let now = Instant::now();
+        let mut dropped = None;
+
+        if let Some(last_frame) = self.last_frame {
+            let gap = now.duration_since(last_frame);
+            let interval = self.frame_interval.unwrap_or(gap).max(exposure);
+            if !interval.is_zero() && gap.as_secs_f64() > interval.as_secs_f64() * GAP_FACTOR {
+                let count = (gap.as_secs_f64() / interval.as_secs_f64()).round() as u64 - 1;
+                let cause = if self.is_full { DropCause::DdrOverflow } else { DropCause::FrameGap };
+                self.stats.record(cause, count);
+                dropped = Some((cause, count));
+            } else {
+                // Slow moving average so a burst of gaps does not stretch the expected interval
+                self.frame_interval = Some(match self.frame_interval {
+                    Some(average) => average.mul_f64(0.9) + gap.mul_f64(0.1),
+                    None => gap,
+                });
+            }
+        }
+
+        self.stats.frames += 1;
+        self.last_frame = Some(now);
+        self.is_full = self.status.and_then(|status| status.fill_ratio()).map_or(false, |ratio| ratio >= self.overflow_ratio);
+
+        dropped
+    }
+
+    pub fn frame_timeout(&mut self) -> DropCause {
+        let cause = if self.is_full { DropCause::DdrOverflow } else { DropCause::Timeout };
+        self.stats.record(cause, 1);
+        cause
+    }
+}
+
+impl Default for DdrMonitor {
+    fn default() -> Self {
+        DdrMonitor::new(0.9)
+    }
+}
+
+impl fmt::Display for DropStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "frames: {}, dropped: {} (timeouts: {}, DDR overflows: {}, frame gaps: {})",
+            self.frames, self.get_dropped(), self.timeouts, self.ddr_overflows, self.frame_gaps)
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+use serde::{Deserialize, Serialize};
+use crate::frame::Frame;
+
+#[derive(Debug, Clone)]
+pub struct DefectConfig {
+    // Pixels further than this many robust sigmas above or below their plane median
+    pub hot_sigma: f64,
+    pub dead_sigma: f64,
+    // Columns whose median is further than this many robust sigmas from the other columns
+    pub column_sigma: f64,
+    // A map is replaced once the sensor is this many degrees away from where it was taken
+    pub temperature_tolerance: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DefectMap {
+    pub serial_num: String,
+    pub width: u32,
+    pub height: u32,
+    pub is_bayer: bool,
+    pub exposure: u32,
+    pub gain: u32,
+    pub temperature: Option<f64>,
+    pub created: SystemTime,
+    pub hot_pixels: Vec<(u32, u32)>,
+    pub dead_pixels: Vec<(u32, u32)>,
+    pub bad_columns: Vec<u32>,
+    // One flag per pixel, built on the first correction
+    #[serde(skip)]
+    bad_mask: OnceLock<Vec<bool>>,
+}
+
+// Every map of a camera lives in one file named after its serial number
+pub struct DefectStore {
+    dir: PathBuf,
+}
+
+impl DefectMap {
+    // Darks have to be single channel frames of the same size, taken with the lens covered
+    pub fn build(darks: &[Frame], temperature: Option<f64>, config: &DefectConfig) -> Option<DefectMap> {
+        let first = darks.first()?;
+        if first.channels > 1 || darks.iter().any(|dark| dark.width != first.width || dark.height != first.height) {
+            eprintln!("Defect map needs single channel darks of the same size");
+            return None
+        }
+        let (width, height) = (first.width, first.height);
+        let is_bayer = first.is_bayer();
+        let step = if is_bayer { 2 } else { 1 };
+
+        // Per pixel median of the darks removes cosmic ray hits and read noise outliers
+        let mut stack = vec![0u16; darks.len()];
+        let master: Vec<u16> = (0..first.sample_count()).map(|index| {
+            for (sample, dark) in stack.iter_mut().zip(darks) {
+                *sample = dark.get_sample(index);
+            }
+            stack.sort_unstable();
+            stack[stack.len() / 2]
+        }).collect();
+
+        let mut hot_pixels = Vec::new();
+        let mut dead_pixels = Vec::new();
+        for plane_y in 0..step {
+            for plane_x in 0..step {
+                let plane: Vec<(u32, u32)> = (plane_y..height).step_by(step as usize)
+                    .flat_map(|y| (plane_x..width).step_by(step as usize).map(move |x| (x, y)))
+                    .collect();
+                let mut values: Vec<f64> = plane.iter().map(|&(x, y)| master[(y * width + x) as usize] as f64).collect();
+                let (median, sigma) = robust_stats(&mut values);
+                for &(x, y) in plane.iter() {
+                    let value = master[(y * width + x) as usize] as f64;
+                    if value > median + config.hot_sigma * sigma {
+                        hot_pixels.push((x, y));
+                    } else if value < median - config.dead_sigma * sigma {
+                        dead_pixels.push((x, y));
+                    }
+                }
+            }
+        }
+
+        let mut column_medians: Vec<f64> = (0..width).map(|x| {
+            let mut column: Vec<f64> = (0..height).map(|y| master[(y * width + x) as usize] as f64).collect();
+            robust_stats(&mut column).0
+        }).collect();
+        let columns = column_medians.clone();
+        let (median, sigma) = robust_stats(&mut column_medians);
+        let bad_columns: Vec<u32> = columns.iter().enumerate()
+            .filter(|(_, value)| (*value - median).abs() > config.column_sigma * sigma)
+            .map(|(x, _)| x as u32)
+            .collect();
+
+        // Pixels of a bad column are corrected with the column
+        let bad_column_set: HashSet<u32> = bad_columns.iter().cloned().collect();
+        hot_pixels.retain(|(x, _)| !bad_column_set.contains(x));
+        dead_pixels.retain(|(x, _)| !bad_column_set.contains(x));
+
+        Some(DefectMap {
+            serial_num: first.metadata.serial_num.clone(),
+            width,
+            height,
+            is_bayer,
+            exposure: first.metadata.params.exposure,
+            gain: first.metadata.params.gain,
+            temperature,
+            created: SystemTime::now(),
+            hot_pixels,
+            dead_pixels,
+            bad_columns,
+            bad_mask: OnceLock::new(),
+        })
+    }
+
+    pub fn defect_count(&self) -> usize {
+        self.hot_pixels.len() + self.dead_pixels.len() + self.bad_columns.len() * self.height as usize
+    }
+
+    pub fn matches(&self, frame: &Frame) -> bool {
+        frame.width == self.width && frame.height == self.height && frame.channels <= 1 && frame.is_bayer() == self.is_bayer
+    }
+
+    // Hot pixels grow with exposure and gain, a map only applies to the settings it was taken with
+    pub fn matches_settings(&self, frame: &Frame) -> bool {
+        frame.metadata.params.exposure == self.exposure && frame.metadata.params.gain == self.gain
+    }
+
+    // Replaces every defect with the median of its nearest good neighbours of the same colour
+    pub fn correct(&self, frame: &mut Frame) -> bool {
+        if !self.matches(frame) {
+            return false
+        }
+        let step = if self.is_bayer { 2i64 } else { 1 };
+        let bad_mask = self.bad_mask.get_or_init(|| {
+            let mut mask = vec![false; (self.width * self.height) as usize];
+            for &(x, y) in self.hot_pixels.iter().chain(self.dead_pixels.iter()) {
+                mask[(y * self.width + x) as usize] = true;
+            }
+            for &x in self.bad_columns.iter() {
+                for y in 0..self.height {
+                    mask[(y * self.width + x) as usize] = true;
+                }
+            }
+            mask
+        });
+        let is_bad = |x: u32, y: u32| bad_mask[(y * self.width + x) as usize];
+
+        let mut fixes = Vec::with_capacity(self.defect_count());
+        let columns = self.bad_columns.iter().flat_map(|&x| (0..self.height).map(move |y| (x, y)));
+        for (x, y) in self.hot_pixels.iter().chain(self.dead_pixels.iter()).cloned().chain(columns) {
+            let mut neighbours = Vec::with_capacity(8);
+            for dy in [-step, 0, step] {
+                for dx in [-step, 0, step] {
+                    let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                    if (dx == 0 && dy == 0) || nx < 0 || ny < 0 || nx >= self.width as i64 || ny >= self.height as i64 {
+                        continue;
+                    }
+                    if !is_bad(nx as u32, ny as u32) {
+                        neighbours.push(frame.get_pixel(nx as u32, ny as u32));
+                    }
+                }
+            }
+            if !neighbours.is_empty() {
+                neighbours.sort_unstable();
+                fixes.push((x, y, neighbours[neighbours.len() / 2]));
+            }
+        }
+        for (x, y, value) in fixes {
+            frame.set_pixel(x, y, value);
+        }
+
+        true
+    }
+}
+
+impl DefectStore {
+    pub fn new(dir: &Path) -> Self {
+        DefectStore { dir: dir.to_path_buf() }
+    }
+
+    pub fn load(&self, serial_num: &str) -> io::Result<Vec<DefectMap>> {
+        let path = self.path(serial_num);
+        if !path.exists() {
+            return Ok(Vec::new())
+        }
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    // Replaces a map taken at the same size, exposure, gain and temperature, keeps the others
+    pub fn save(&self, map: &DefectMap, config: &DefectConfig) -> io::Result<Vec<DefectMap>> {
+        let mut maps = self.load(&map.serial_num)?;
+        maps.retain(|other| {
+            let is_same_temperature = match (other.temperature, map.temperature) {
+                (Some(other), Some(current)) => (other - current).abs() < config.temperature_tolerance,
+                _ => true,
+            };
+            other.width != map.width || other.height != map.height || other.exposure != map.exposure || other.gain != map.gain
+                || !is_same_temperature
+        });
+        maps.push(map.clone());
+
+        fs::create_dir_all(&self.dir)?;
+        let content = serde_json::to_string(&maps).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(self.path(&map.serial_num), content)?;
+
+        Ok(maps)
+    }
+
+    fn path(&self, serial_num: &str) -> PathBuf {
+        self.dir.join(format!("{}.defects.json", serial_num))
+    }
+}
+
+// The map closest in temperature among those matching the frame and its settings, maps without a temperature come last
+pub fn select_map<'a>(maps: &'a [DefectMap], frame: &Frame, temperature: Option<f64>) -> Option<&'a DefectMap> {
+    maps.iter().filter(|map| map.matches(frame) && map.matches_settings(frame)).min_by(|a, b| {
+        let distance = |map: &DefectMap| match (map.temperature, temperature) {
+            (Some(map_temperature), Some(temperature)) => (map_temperature - temperature).abs(),
+            _ => f64::MAX,
+        };
+        distance(a).total_cmp(&distance(b))
+    })
+}
+
+// Median and MAD based sigma, the values are reordered
+fn robust_stats(values: &mut [f64]) -> (f64, f64) {
+    if values.is_empty() {
+        return (0.0, 0.0)
+    }
+    values.sort_unstable_by(|a, b| a.total_cmp(b));
+    let median = values[values.len() / 2];
+    let mut deviations: Vec<f64> = values.iter().map(|value| (value - median).abs()).collect();
+    deviations.sort_unstable_by(|a, b| a.total_cmp(b));
+    // A MAD of zero happens on quantised dark frames, one ADU keeps the thresholds meaningful
+    let sigma = (1.4826 * deviations[deviations.len() / 2]).max(1.0);
+
+    (median, sigma)
+}
+
+impl Default for DefectConfig {
+    fn default() -> Self {
+        DefectConfig {
+            hot_sigma: 8.0,
+            dead_sigma: 8.0,
+            column_sigma: 5.0,
+            temperature_tolerance: 5.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdk::BayerFormat;
+
+    const WIDTH: u32 = 20;
+    const HEIGHT: u32 = 16;
+
+    // Dark level 100 plus a few ADU of pattern, every column has the same median
+    fn test_darks(count: u32) -> Vec<Frame> {
+        (0..count).map(|index| {
+            let mut dark = Frame::new(WIDTH, HEIGHT, 16, 1);
+            dark.metadata.bayer_format = BayerFormat::Mono;
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    dark.set_pixel(x, y, 100 + ((x * 3 + y * 5 + index) % 5) as u16);
+                }
+            }
+            dark.set_pixel(5, 5, 4000);
+            dark.set_pixel(8, 3, 0);
+            // Hot pixel inside the bad column
+            dark.set_pixel(12, 4, 4000);
+            for y in 0..HEIGHT {
+                dark.set_pixel(12, y, dark.get_pixel(12, y) + 50);
+            }
+            dark
+        }).collect()
+    }
+
+    fn test_map(exposure: u32, gain: u32, temperature: Option<f64>) -> DefectMap {
+        DefectMap {
+            serial_num: "test".to_string(),
+            width: WIDTH,
+            height: HEIGHT,
+            is_bayer: false,
+            exposure,
+            gain,
+            temperature,
+            created: SystemTime::now(),
+            hot_pixels: Vec::new(),
+            dead_pixels: Vec::new(),
+            bad_columns: Vec::new(),
+            bad_mask: OnceLock::new(),
+        }
+    }
+
+    #[test]
+    fn finds_hot_dead_pixels_and_bad_columns() {
+        let mut darks = test_darks(3);
+        // A cosmic ray in one dark only is removed by the median
+        darks[1].set_pixel(2, 2, 3000);
+        let map = DefectMap::build(&darks, Some(-10.0), &DefectConfig::default()).unwrap();
+        assert_eq!(map.hot_pixels, vec![(5, 5)]);
+        assert_eq!(map.dead_pixels, vec![(8, 3)]);
+        assert_eq!(map.bad_columns, vec![12]);
+        assert_eq!(map.defect_count(), 2 + HEIGHT as usize);
+        assert_eq!(map.temperature, Some(-10.0));
+    }
+
+    #[test]
+    fn rejects_darks_of_different_sizes() {
+        let mut darks = test_darks(2);
+        darks.push(Frame::new(WIDTH, HEIGHT + 2, 16, 1));
+        assert!(DefectMap::build(&darks, None, &DefectConfig::default()).is_none());
+        assert!(DefectMap::build(&[], None, &DefectConfig::default()).is_none());
+    }
+
+    #[test]
+    fn corrects_bayer_pixels_from_the_same_colour() {
+        let mut frame = Frame::new(WIDTH, HEIGHT, 16, 1);
+        frame.metadata.bayer_format = BayerFormat::RG;
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                frame.set_pixel(x, y, if x % 2 == 0 && y % 2 == 0 { 1000 } else { 200 });
+            }
+        }
+        frame.set_pixel(4, 4, 5000);
+        frame.set_pixel(6, 4, 5000);
+        frame.set_pixel(9, 9, 0);
+        let mut map = test_map(0, 0, None);
+        map.is_bayer = true;
+        map.hot_pixels = vec![(4, 4), (6, 4)];
+        map.dead_pixels = vec![(9, 9)];
+
+        assert!(map.correct(&mut frame));
+        // Neighbours two pixels away share the colour, the other hot pixel is not one of them
+        assert_eq!(frame.get_pixel(4, 4), 1000);
+        assert_eq!(frame.get_pixel(6, 4), 1000);
+        assert_eq!(frame.get_pixel(9, 9), 200);
+        assert_eq!(frame.get_pixel(5, 4), 200);
+    }
+
+    #[test]
+    fn corrects_bad_columns_from_the_neighbours() {
+        let darks = test_darks(3);
+        let map = DefectMap::build(&darks, None, &DefectConfig::default()).unwrap();
+        let mut frame = darks[0].clone();
+        assert!(map.correct(&mut frame));
+        assert!((0..HEIGHT).all(|y| frame.get_pixel(12, y) < 110), "{:?}", (0..HEIGHT).map(|y| frame.get_pixel(12, y)).collect::<Vec<u16>>());
+        assert!(frame.get_pixel(5, 5) < 110);
+        assert!(frame.get_pixel(8, 3) >= 100);
+
+        // Bayer maps do not apply to mono frames
+        let mut bayer_map = map.clone();
+        bayer_map.is_bayer = true;
+        assert!(!bayer_map.correct(&mut frame));
+    }
+
+    #[test]
+    fn selects_the_closest_temperature_with_the_same_settings() {
+        let maps = vec![
+            test_map(1000, 10, Some(0.0)),
+            test_map(1000, 10, Some(18.0)),
+            test_map(2000, 10, Some(20.0)),
+            test_map(1000, 5, Some(20.0)),
+            test_map(1000, 10, None),
+        ];
+        let mut frame = Frame::new(WIDTH, HEIGHT, 16, 1);
+        frame.metadata.params.exposure = 1000;
+        frame.metadata.params.gain = 10;
+        let selected = select_map(&maps, &frame, Some(20.0)).unwrap();
+        assert_eq!(selected.temperature, Some(18.0));
+        let selected = select_map(&maps, &frame, Some(-5.0)).unwrap();
+        assert_eq!(selected.temperature, Some(0.0));
+
+        // Only a map without a temperature matches
+        let selected = select_map(&maps[4..], &frame, Some(20.0)).unwrap();
+        assert_eq!(selected.temperature, None);
+
+        frame.metadata.params.gain = 20;
+        assert!(select_map(&maps, &frame, Some(20.0)).is_none());
+    }
+
+    #[test]
+    fn save_replaces_maps_with_the_same_settings() {
+        let dir = std::env::temp_dir().join(format!("qhyccd_defects_save_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let store = DefectStore::new(&dir);
+        let config = DefectConfig::default();
+
+        store.save(&test_map(1000, 10, Some(20.0)), &config).unwrap();
+        // Within the temperature tolerance, replaces the first one
+        let mut replacement = test_map(1000, 10, Some(22.0));
+        replacement.hot_pixels = vec![(1, 1)];
+        store.save(&replacement, &config).unwrap();
+        store.save(&test_map(1000, 10, Some(30.0)), &config).unwrap();
+        let maps = store.save(&test_map(2000, 10, Some(22.0)), &config).unwrap();
+        assert_eq!(maps.len(), 3);
+
+        let loaded = store.load("test").unwrap();
+        let temperatures: Vec<(u32, Option<f64>)> = loaded.iter().map(|map| (map.exposure, map.temperature)).collect();
+        assert_eq!(temperatures, vec![(1000, Some(22.0)), (1000, Some(30.0)), (2000, Some(22.0))]);
+        assert_eq!(loaded[0].hot_pixels, vec![(1, 1)]);
+        assert!(store.load("other").unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+extern crate opencv;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use derive_more::Display;
+use opencv::{core, imgcodecs};
+use serde::{Deserialize, Serialize};
+use crate::astro;
+use crate::frame::{Frame, FrameMetadata};
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(io::Error),
+    Encode(String),
+}
+
+#[derive(Display, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExportFormat {
+    #[default]
+    Tiff,
+    Png,
+}
+
+#[derive(Display, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExportMode {
+    // Samples as read from the sensor, Bayer frames stay a mosaic
+    #[default]
+    Raw,
+    Debayered,
+    // Debayered when the frame is Bayer, then linearly stretched between two percentiles to the
+    // full 16 bit range; for viewing only
+    Stretched,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    pub mode: ExportMode,
+    // Percentiles mapped to black and white in stretched previews
+    pub stretch_low: f64,
+    pub stretch_high: f64,
+    pub sidecar: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StretchRange {
+    pub low: u16,
+    pub high: u16,
+}
+
+// Written next to the image as <name>.json; the size and depth are the ones of the written
+// image, the capture settings are in the metadata of the frame; the exposure times are repeated
+// as readable UTC strings, the metadata holds them as raw SystemTime
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportInfo {
+    pub image: PathBuf,
+    pub format: ExportFormat,
+    pub mode: ExportMode,
+    pub width: u32,
+    pub height: u32,
+    pub bpp: u32,
+    pub channels: u32,
+    pub exposure_start: Option<String>,
+    pub exposure_end: Option<String>,
+    pub stretch: Option<StretchRange>,
+    pub metadata: FrameMetadata,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Tiff => "tiff",
+            ExportFormat::Png => "png",
+        }
+    }
+}
+
+// The extension of the path is replaced by the one of the format. 16 bit frames and stretched
+// previews are written with 16 bit samples, 8 bit frames keep 8 bits.
+pub fn export(path: &Path, frame: &Frame, options: &ExportOptions) -> Result<ExportInfo, ExportError> {
+    if frame.channels != 1 && frame.channels != 3 {
+        return Err(ExportError::Encode(format!("unsupported channel count: {}", frame.channels)))
+    }
+    let image_path = path.with_extension(options.format.extension());
+
+    let needs_debayer = options.mode != ExportMode::Raw && frame.is_bayer();
+    let debayered = if needs_debayer {
+        Some(frame.debayer().ok_or_else(|| ExportError::Encode("debayer failed".to_string()))?)
+    } else {
+        None
+    };
+    let mut image = debayered.unwrap_or_else(|| frame.clone());
+    let mut stretch = None;
+    if options.mode == ExportMode::Stretched {
+        let range = stretch_range(&image, options.stretch_low, options.stretch_high);
+        image = apply_stretch(&image, range);
+        stretch = Some(range);
+    }
+
+    let mat = image.to_mat().ok_or_else(|| ExportError::Encode("frame has no image data".to_string()))?;
+    let mut params = core::Vector::<i32>::new();
+    match options.format {
+        ExportFormat::Png => {
+            params.push(imgcodecs::IMWRITE_PNG_COMPRESSION);
+            params.push(3);
+        },
+        ExportFormat::Tiff => {
+            // LZW, lossless and readable everywhere
+            params.push(imgcodecs::IMWRITE_TIFF_COMPRESSION);
+            params.push(5);
+        },
+    }
+    let path_str = image_path.to_str().ok_or_else(|| ExportError::Encode(format!("invalid path: {}", image_path.display())))?;
+    match imgcodecs::imwrite(path_str, &mat, &params) {
+        Ok(true) => {},
+        Ok(false) => return Err(ExportError::Encode(format!("could not write: {}", image_path.display()))),
+        Err(err) => return Err(ExportError::Encode(err.to_string())),
+    }
+
+    let info = ExportInfo {
+        image: image_path.clone(),
+        format: options.format,
+        mode: options.mode,
+        width: image.width,
+        height: image.height,
+        bpp: image.bpp,
+        channels: image.channels,
+        exposure_start: frame.metadata.exposure_start.map(astro::format_utc),
+        exposure_end: frame.metadata.exposure_end.map(astro::format_utc),
+        stretch,
+        metadata: frame.metadata.clone(),
+    };
+    if options.sidecar {
+        let content = serde_json::to_string_pretty(&info).map_err(|err| ExportError::Encode(err.to_string()))?;
+        fs::write(image_path.with_extension("json"), content).map_err(ExportError::Io)?;
+    }
+
+    Ok(info)
+}
+
+// Sample values at the two percentiles, over all channels
+pub fn stretch_range(frame: &Frame, low: f64, high: f64) -> StretchRange {
+    let mut histogram = vec![0u64; frame.max_value() as usize + 1];
+    let count = frame.sample_count();
+    for index in 0..count {
+        histogram[frame.get_sample(index) as usize] += 1;
+    }
+    let find = |percentile: f64| {
+        let target = (percentile.clamp(0.0, 100.0) / 100.0 * count as f64) as u64;
+        let mut total = 0;
+        for (value, samples) in histogram.iter().enumerate() {
+            total += samples;
+            if total > target {
+                return value as u16
+            }
+        }
+        frame.max_value()
+    };
+    let low = find(low).min(frame.max_value() - 1);
+    StretchRange { low, high: find(high).max(low + 1) }
+}
+
+fn apply_stretch(frame: &Frame, range: StretchRange) -> Frame {
+    let mut stretched = Frame::new(frame.width, frame.height, 16, frame.channels);
+    stretched.metadata = frame.metadata.clone();
+    let scale = u16::MAX as f64 / (range.high - range.low) as f64;
+    for index in 0..frame.sample_count() {
+        let value = (frame.get_sample(index).saturating_sub(range.low) as f64 * scale).min(u16::MAX as f64);
+        stretched.set_sample(index, value.round() as u16);
+    }
+    stretched
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportError::Io(err) => write!(f, "Export I/O error: {}", err),
+            ExportError::Encode(message) => write!(f, "Export error: {}", message),
+        }
+    }
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            format: ExportFormat::Tiff,
+            mode: ExportMode::Raw,
+            stretch_low: 0.5,
+            stretch_high: 99.9,
+            sidecar: true,
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::camera::{Camera, ControlParam};
+use crate::frame::Frame;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FilterStep {
+    pub filter: String,
+    pub frames: u32,
+    // Exposure in microseconds, None keeps the current one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exposure: Option<u32>,
+}
+
+// Wheel plugged into the camera's CFW port, all commands go through the camera handle
+pub struct FilterWheel {
+    slot_count: u32,
+    slot_names: Vec<String>,
+    target: Option<u32>,
+    move_timeout: Duration,
+}
+
+impl FilterWheel {
+    pub fn detect(camera: &mut Camera) -> Option<FilterWheel> {
+        if !camera.is_cfw_plugged() {
+            return None
+        }
+        let slot_count = camera.get_cfw_slots();
+        if slot_count == 0 {
+            eprintln!("Filter wheel on camera {} reports no slots", camera.get_cam_id());
+            return None
+        }
+        if camera.is_debug_info() {
+            println!("Filter wheel found on camera {}, slots: {}", camera.get_cam_id(), slot_count);
+        }
+
+        Some(FilterWheel {
+            slot_count,
+            slot_names: (0..slot_count).map(|slot| format!("Slot {}", slot + 1)).collect(),
+            target: None,
+            move_timeout: Duration::from_secs(30),
+        })
+    }
+
+    pub fn get_slot_count(&self) -> u32 {
+        self.slot_count
+    }
+
+    // Missing names keep their default, extra names are ignored
+    pub fn set_slot_names(&mut self, names: &[String]) {
+        if names.len() > self.slot_count as usize {
+            eprintln!("Filter wheel has {} slots, ignoring {} extra filter names", self.slot_count, names.len() - self.slot_count as usize);
+        }
+        for (slot_name, name) in self.slot_names.iter_mut().zip(names) {
+            *slot_name = name.clone();
+        }
+    }
+
+    pub fn get_slot_names(&self) -> &[String] {
+        &self.slot_names
+    }
+
+    pub fn get_slot_name(&self, slot: u32) -> Option<&str> {
+        self.slot_names.get(slot as usize).map(|name| name.as_str())
+    }
+
+    pub fn find_slot(&self, name: &str) -> Option<u32> {
+        self.slot_names.iter().position(|slot_name| slot_name.eq_ignore_ascii_case(name)).map(|slot| slot as u32)
+    }
+
+    pub fn set_move_timeout(&mut self, timeout: Duration) {
+        self.move_timeout = timeout;
+    }
+
+    // None while the wheel is moving
+    pub fn get_position(&self, camera: &mut Camera) -> Option<u32> {
+        camera.get_cfw_position()
+    }
+
+    pub fn start_move(&mut self, camera: &mut Camera, slot: u32) -> bool {
+        if slot >= self.slot_count {
+            eprintln!("Filter slot {} out of range, slots: {}", slot, self.slot_count);
+            return false
+        }
+        if !camera.move_cfw(slot) {
+            return false
+        }
+        self.target = Some(slot);
+
+        true
+    }
+
+    pub fn is_moving(&self, camera: &mut Camera) -> bool {
+        match self.target {
+            Some(target) => camera.get_cfw_position() != Some(target),
+            None => camera.get_cfw_position().is_none(),
+        }
+    }
+
+    pub fn wait(&mut self, camera: &mut Camera, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while self.is_moving(camera) {
+            if start.elapsed() > timeout {
+                eprintln!("Filter wheel did not reach slot {:?} within {:?}", self.target, timeout);
+                return false
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+
+        true
+    }
+
+    pub fn move_to(&mut self, camera: &mut Camera, slot: u32) -> bool {
+        if camera.get_cfw_position() == Some(slot) {
+            self.target = Some(slot);
+            return true
+        }
+        self.start_move(camera, slot) && self.wait(camera, self.move_timeout)
+    }
+
+    pub fn move_to_name(&mut self, camera: &mut Camera, name: &str) -> bool {
+        let has_slot = self.find_slot(name);
+        if has_slot.is_none() {
+            eprintln!("Unknown filter: {}", name);
+            return false
+        }
+        self.move_to(camera, has_slot.unwrap())
+    }
+
+    // Runs the steps in order, on_frame returns false to abort; the exposure and stream mode are restored afterwards
+    pub fn run_sequence<F>(&mut self, camera: &mut Camera, steps: &[FilterStep], frame_timeout: Duration, mut on_frame: F) -> bool
+        where F: FnMut(&FilterStep, Frame) -> bool {
+        let saved_exposure = camera.get_params().exposure;
+        let mut res = true;
+        camera.begin_snapshot_run();
+
+        'steps: for step in steps {
+            if !self.move_to_name(camera, &step.filter) {
+                res = false;
+                break;
+            }
+            if let Some(exposure) = step.exposure {
+                camera.set_control(&ControlParam::Exposure, exposure as f64, false);
+            }
+            for _ in 0..step.frames {
+                let has_frame = camera.snapshot(frame_timeout);
+                if has_frame.is_none() {
+                    eprintln!("No frame received through filter: {}", step.filter);
+                    res = false;
+                    break 'steps;
+                }
+                let mut frame = has_frame.unwrap();
+                frame.metadata.filter = Some(step.filter.clone());
+                if !on_frame(step, frame) {
+                    res = false;
+                    break 'steps;
+                }
+            }
+        }
+
+        camera.end_snapshot_run();
+        if camera.get_params().exposure != saved_exposure {
+            camera.set_control(&ControlParam::Exposure, saved_exposure as f64, false);
+        }
+
+        res
+    }
+}
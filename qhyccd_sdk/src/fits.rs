@@ -0,0 +1,515 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use crate::astro::{self, SiteLocation};
+use crate::camera::{BinMode, CameraInfo};
+use crate::frame::Frame;
+use crate::sdk::BayerFormat;
+
+const BLOCK_LEN: usize = 2880;
+const CARD_LEN: usize = 80;
+const MAX_AXIS_LEN: i64 = 65536;
+
+#[derive(Debug)]
+pub enum FitsError {
+    Io(io::Error),
+    Format(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FitsOptions {
+    pub site: Option<SiteLocation>,
+    pub object: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FitsValue {
+    Logical(bool),
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FitsCard {
+    pub key: String,
+    pub value: FitsValue,
+    pub comment: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct FitsImage {
+    pub frame: Frame,
+    pub cards: Vec<FitsCard>,
+}
+
+// Mono and raw Bayer frames are written as a 2D image, debayered ones as three planes R, G, B.
+// 16 bit samples are stored signed with BZERO 32768 as the standard requires.
+pub fn write(path: &Path, frame: &Frame, info: Option<&CameraInfo>, options: &FitsOptions) -> Result<(), FitsError> {
+    let bytes = to_bytes(frame, info, options)?;
+    fs::write(path, bytes).map_err(FitsError::Io)
+}
+
+pub fn read(path: &Path) -> Result<FitsImage, FitsError> {
+    let bytes = fs::read(path).map_err(FitsError::Io)?;
+    from_bytes(&bytes)
+}
+
+pub fn header_cards(frame: &Frame, info: Option<&CameraInfo>, options: &FitsOptions) -> Vec<FitsCard> {
+    let metadata = &frame.metadata;
+    let params = &metadata.params;
+    let is_rgb = frame.channels == 3;
+    let mut cards = vec![
+        FitsCard::new("SIMPLE", FitsValue::Logical(true), "conforms to FITS standard"),
+        FitsCard::new("BITPIX", FitsValue::Integer(if frame.bytes_per_sample() == 2 { 16 } else { 8 }), "bits per data value"),
+        FitsCard::new("NAXIS", FitsValue::Integer(if is_rgb { 3 } else { 2 }), "number of data axes"),
+        FitsCard::new("NAXIS1", FitsValue::Integer(frame.width as i64), "image width"),
+        FitsCard::new("NAXIS2", FitsValue::Integer(frame.height as i64), "image height"),
+    ];
+    if is_rgb {
+        cards.push(FitsCard::new("NAXIS3", FitsValue::Integer(3), "colour planes R, G, B"));
+    }
+    if frame.bytes_per_sample() == 2 {
+        cards.push(FitsCard::new("BZERO", FitsValue::Real(32768.0), "offset for unsigned 16 bit data"));
+        cards.push(FitsCard::new("BSCALE", FitsValue::Real(1.0), "data scaling"));
+    }
+    cards.push(FitsCard::new("ROWORDER", FitsValue::Text("TOP-DOWN".to_string()), "first row is the top of the image"));
+
+    if let Some(start) = metadata.exposure_start {
+        cards.push(FitsCard::new("DATE-OBS", FitsValue::Text(astro::format_utc(start)), "UTC start of exposure"));
+    }
+    let exposure = match (metadata.exposure_start, metadata.exposure_end) {
+        (Some(start), Some(end)) if end > start => end.duration_since(start).unwrap_or_default(),
+        _ => Duration::from_micros(params.exposure as u64),
+    };
+    cards.push(FitsCard::new("EXPTIME", FitsValue::Real(exposure.as_secs_f64()), "[s] exposure time"));
+    if let Some(timing) = &metadata.timing {
+        cards.push(FitsCard::new("DATE-UNC", FitsValue::Real(timing.uncertainty.as_secs_f64()), "[s] timestamp uncertainty"));
+    }
+    cards.push(FitsCard::new("GAIN", FitsValue::Integer(params.gain as i64), "camera gain setting"));
+    cards.push(FitsCard::new("OFFSET", FitsValue::Integer(params.offset as i64), "camera offset setting"));
+    let bin = bin_factor(&params.bin_mode);
+    cards.push(FitsCard::new("XBINNING", FitsValue::Integer(bin as i64), "horizontal binning"));
+    cards.push(FitsCard::new("YBINNING", FitsValue::Integer(bin as i64), "vertical binning"));
+    if let Some(temperature) = metadata.sensor_temperature {
+        cards.push(FitsCard::new("CCD-TEMP", FitsValue::Real(temperature), "[C] sensor temperature"));
+    }
+    if frame.is_bayer() {
+        cards.push(FitsCard::new("BAYERPAT", FitsValue::Text(bayer_pattern(metadata.bayer_format).to_string()), "colour filter array pattern"));
+        cards.push(FitsCard::new("XBAYROFF", FitsValue::Integer(0), "Bayer pattern x offset"));
+        cards.push(FitsCard::new("YBAYROFF", FitsValue::Integer(0), "Bayer pattern y offset"));
+    }
+    if let Some(info) = info {
+        cards.push(FitsCard::new("XPIXSZ", FitsValue::Real(info.pixel_width_um * bin as f64), "[um] binned pixel width"));
+        cards.push(FitsCard::new("YPIXSZ", FitsValue::Real(info.pixel_height_um * bin as f64), "[um] binned pixel height"));
+    }
+    let model = if metadata.model.is_empty() { info.map(|info| info.model.clone()).unwrap_or_default() } else { metadata.model.clone() };
+    cards.push(FitsCard::new("INSTRUME", FitsValue::Text(model), "camera model"));
+    if !metadata.serial_num.is_empty() {
+        cards.push(FitsCard::new("SERIALNO", FitsValue::Text(metadata.serial_num.clone()), "camera serial number"));
+    }
+    if metadata.versions.firmware.year > 0 {
+        cards.push(FitsCard::new("FIRMWARE", FitsValue::Text(metadata.versions.firmware.to_string()), "camera firmware version"));
+    }
+    if let Some(filter) = &metadata.filter {
+        cards.push(FitsCard::new("FILTER", FitsValue::Text(filter.clone()), "filter name"));
+    }
+    if let Some(object) = &options.object {
+        cards.push(FitsCard::new("OBJECT", FitsValue::Text(object.clone()), "target"));
+    }
+
+    // A GPS fix beats the configured site
+    let site = metadata.gps.map(|gps| (gps.latitude, gps.longitude, None))
+        .or(options.site.map(|site| (site.latitude, site.longitude, Some(site.elevation))));
+    if let Some((latitude, longitude, elevation)) = site {
+        cards.push(FitsCard::new("SITELAT", FitsValue::Real(latitude), "[deg] site latitude, north positive"));
+        cards.push(FitsCard::new("SITELONG", FitsValue::Real(longitude), "[deg] site longitude, east positive"));
+        if let Some(elevation) = elevation {
+            cards.push(FitsCard::new("SITEELEV", FitsValue::Real(elevation), "[m] site elevation"));
+        }
+    }
+
+    cards
+}
+
+pub fn to_bytes(frame: &Frame, info: Option<&CameraInfo>, options: &FitsOptions) -> Result<Vec<u8>, FitsError> {
+    if frame.channels != 1 && frame.channels != 3 {
+        return Err(FitsError::Format(format!("unsupported channel count: {}", frame.channels)))
+    }
+    if frame.data.len() < frame.data_len() {
+        return Err(FitsError::Format(format!("frame has {} bytes, expected {}", frame.data.len(), frame.data_len())))
+    }
+
+    let mut bytes = Vec::with_capacity(frame.data_len() + 2 * BLOCK_LEN);
+    for card in header_cards(frame, info, options) {
+        bytes.extend_from_slice(card.to_string().as_bytes());
+    }
+    bytes.extend_from_slice(format!("{:<80}", "END").as_bytes());
+    pad_block(&mut bytes, b' ');
+
+    // Debayered frames are interleaved BGR, FITS wants one plane per colour
+    let channels = frame.channels as usize;
+    let pixels = frame.width as usize * frame.height as usize;
+    let planes: Vec<usize> = if channels == 3 { vec![2, 1, 0] } else { vec![0] };
+    for channel in planes {
+        for pixel in 0..pixels {
+            let value = frame.get_sample(pixel * channels + channel);
+            if frame.bytes_per_sample() == 2 {
+                bytes.extend_from_slice(&((value as i32 - 32768) as i16).to_be_bytes());
+            } else {
+                bytes.push(value as u8);
+            }
+        }
+    }
+    pad_block(&mut bytes, 0);
+
+    Ok(bytes)
+}
+
+pub fn from_bytes(bytes: &[u8]) -> Result<FitsImage, FitsError> {
+    let mut cards = Vec::new();
+    let mut offset = 0;
+    loop {
+        if offset + CARD_LEN > bytes.len() {
+            return Err(FitsError::Format("header has no END card".to_string()))
+        }
+        let line = String::from_utf8_lossy(&bytes[offset..offset + CARD_LEN]).to_string();
+        offset += CARD_LEN;
+        if line.trim_end() == "END" {
+            break;
+        }
+        if let Some(card) = FitsCard::parse(&line) {
+            cards.push(card);
+        }
+    }
+    let data_start = offset.div_ceil(BLOCK_LEN) * BLOCK_LEN;
+
+    let get_integer = |key: &str| cards.iter().find(|card| card.key == key).and_then(|card| card.value.as_f64()).map(|value| value as i64);
+    let bitpix = get_integer("BITPIX").ok_or_else(|| FitsError::Format("missing BITPIX".to_string()))?;
+    let naxis = get_integer("NAXIS").unwrap_or(0);
+    let axis = |key: &str| get_integer(key).filter(|len| (1..=MAX_AXIS_LEN).contains(len)).map(|len| len as u32);
+    let (width, height) = match (axis("NAXIS1"), axis("NAXIS2")) {
+        (Some(width), Some(height)) => (width, height),
+        _ => return Err(FitsError::Format(format!("image axes out of range 1..{}", MAX_AXIS_LEN))),
+    };
+    let channels = if naxis == 3 { axis("NAXIS3").unwrap_or(0) } else { 1 };
+    if (bitpix != 8 && bitpix != 16) || !(naxis == 2 || (naxis == 3 && channels == 3)) {
+        return Err(FitsError::Format(format!("unsupported image, BITPIX: {}, NAXIS: {}", bitpix, naxis)))
+    }
+    let bzero = cards.iter().find(|card| card.key == "BZERO").and_then(|card| card.value.as_f64()).unwrap_or(0.0);
+
+    // Checked before allocating, the header of a broken file can claim anything
+    let bytes_per_sample = (bitpix / 8) as usize;
+    let data_len = (width as usize).checked_mul(height as usize)
+        .and_then(|len| len.checked_mul(channels as usize))
+        .and_then(|len| len.checked_mul(bytes_per_sample));
+    if !data_len.is_some_and(|data_len| data_len <= bytes.len().saturating_sub(data_start)) {
+        return Err(FitsError::Format("data shorter than the header says".to_string()))
+    }
+    let mut frame = Frame::new(width, height, bitpix as u32, channels);
+    let pixels = width as usize * height as usize;
+    let planes: Vec<usize> = if channels == 3 { vec![2, 1, 0] } else { vec![0] };
+    for (plane, channel) in planes.into_iter().enumerate() {
+        for pixel in 0..pixels {
+            let position = data_start + (plane * pixels + pixel) * bytes_per_sample;
+            let value = if bytes_per_sample == 2 {
+                (i16::from_be_bytes([bytes[position], bytes[position + 1]]) as f64 + bzero) as u16
+            } else {
+                bytes[position] as u16
+            };
+            frame.set_sample(pixel * channels as usize + channel, value);
+        }
+    }
+
+    restore_metadata(&mut frame, &cards);
+
+    Ok(FitsImage { frame, cards })
+}
+
+impl FitsImage {
+    pub fn get_card(&self, key: &str) -> Option<&FitsValue> {
+        self.cards.iter().find(|card| card.key == key).map(|card| &card.value)
+    }
+}
+
+impl FitsCard {
+    pub fn new(key: &str, value: FitsValue, comment: &str) -> Self {
+        FitsCard { key: key.to_string(), value, comment: comment.to_string() }
+    }
+
+    fn parse(line: &str) -> Option<FitsCard> {
+        let key = line.get(..8)?.trim().to_string();
+        if key.is_empty() || line.get(8..10) != Some("= ") {
+            return None
+        }
+        let rest = line.get(10..)?.trim();
+
+        let (value, comment) = if let Some(quoted) = rest.strip_prefix('\'') {
+            // Two quotes in a row stand for one quote inside the string
+            let mut text = String::new();
+            let mut chars = quoted.char_indices().peekable();
+            let mut end = quoted.len();
+            while let Some((index, c)) = chars.next() {
+                if c == '\'' {
+                    if chars.peek().map(|(_, next)| *next) == Some('\'') {
+                        text.push('\'');
+                        chars.next();
+                        continue;
+                    }
+                    end = index + 1;
+                    break;
+                }
+                text.push(c);
+            }
+            let comment = quoted[end..].trim().trim_start_matches('/').trim();
+            (FitsValue::Text(text.trim_end().to_string()), comment)
+        } else {
+            let (value, comment) = rest.split_once('/').unwrap_or((rest, ""));
+            let value = value.trim();
+            let value = match value {
+                "T" => FitsValue::Logical(true),
+                "F" => FitsValue::Logical(false),
+                _ if value.parse::<i64>().is_ok() => FitsValue::Integer(value.parse().unwrap()),
+                _ => FitsValue::Real(value.replace('D', "E").parse().ok()?),
+            };
+            (value, comment.trim())
+        };
+
+        Some(FitsCard { key, value, comment: comment.to_string() })
+    }
+}
+
+impl FitsValue {
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            FitsValue::Integer(value) => Some(*value as f64),
+            FitsValue::Real(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            FitsValue::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+// Fixed format: values right aligned to column 30, strings quoted from column 11
+impl fmt::Display for FitsCard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = match &self.value {
+            FitsValue::Logical(value) => format!("{:>20}", if *value { "T" } else { "F" }),
+            FitsValue::Integer(value) => format!("{:>20}", value),
+            FitsValue::Real(value) => format!("{:>20}", format_real(*value)),
+            FitsValue::Text(text) => format!("'{:<8}'", text.replace('\'', "''")),
+        };
+        let mut card = format!("{:<8}= {}", self.key, value);
+        if !self.comment.is_empty() {
+            card.push_str(" / ");
+            card.push_str(&self.comment);
+        }
+        let card: String = card.chars().filter(|c| c.is_ascii() && !c.is_ascii_control()).take(CARD_LEN).collect();
+        write!(f, "{:<80}", card)
+    }
+}
+
+impl fmt::Display for FitsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FitsError::Io(err) => write!(f, "FITS I/O error: {}", err),
+            FitsError::Format(message) => write!(f, "FITS format error: {}", message),
+        }
+    }
+}
+
+pub fn bayer_pattern(bayer_format: BayerFormat) -> &'static str {
+    match bayer_format {
+        BayerFormat::GB => "GBRG",
+        BayerFormat::GR => "GRBG",
+        BayerFormat::BG => "BGGR",
+        BayerFormat::RG => "RGGB",
+        BayerFormat::Mono => "",
+    }
+}
+
+fn bayer_format_from_pattern(pattern: &str) -> BayerFormat {
+    match pattern {
+        "GBRG" => BayerFormat::GB,
+        "GRBG" => BayerFormat::GR,
+        "BGGR" => BayerFormat::BG,
+        "RGGB" => BayerFormat::RG,
+        _ => BayerFormat::Mono,
+    }
+}
+
+fn bin_factor(bin_mode: &BinMode) -> u32 {
+    match bin_mode {
+        BinMode::Bin1x1 => 1,
+        BinMode::Bin2x2 => 2,
+        BinMode::Bin3x3 => 3,
+        BinMode::Bin4x4 => 4,
+    }
+}
+
+// Always with a decimal point or an exponent, as FITS readers expect of real values
+fn format_real(value: f64) -> String {
+    let text = format!("{}", value);
+    if text.contains('.') || text.contains('e') || text.contains("inf") || text.contains("NaN") {
+        text.to_uppercase()
+    } else {
+        format!("{}.0", text)
+    }
+}
+
+fn pad_block(bytes: &mut Vec<u8>, fill: u8) {
+    let len = bytes.len().div_ceil(BLOCK_LEN) * BLOCK_LEN;
+    bytes.resize(len, fill);
+}
+
+fn restore_metadata(frame: &mut Frame, cards: &[FitsCard]) {
+    let find = |key: &str| cards.iter().find(|card| card.key == key).map(|card| &card.value);
+    let metadata = &mut frame.metadata;
+    if let Some(start) = find("DATE-OBS").and_then(|value| value.as_str()).and_then(astro::parse_utc) {
+        metadata.exposure_start = Some(start);
+        if let Some(exposure) = find("EXPTIME").and_then(|value| value.as_f64()) {
+            metadata.exposure_end = Some(start + Duration::from_secs_f64(exposure.max(0.0)));
+        }
+    }
+    if let Some(exposure) = find("EXPTIME").and_then(|value| value.as_f64()) {
+        metadata.params.exposure = (exposure * 1_000_000.0).round() as u32;
+    }
+    if let Some(gain) = find("GAIN").and_then(|value| value.as_f64()) {
+        metadata.params.gain = gain as u32;
+    }
+    if let Some(offset) = find("OFFSET").and_then(|value| value.as_f64()) {
+        metadata.params.offset = offset as u32;
+    }
+    metadata.params.bin_mode = match find("XBINNING").and_then(|value| value.as_f64()).unwrap_or(1.0) as u32 {
+        2 => BinMode::Bin2x2,
+        3 => BinMode::Bin3x3,
+        4 => BinMode::Bin4x4,
+        _ => BinMode::Bin1x1,
+    };
+    metadata.params.bpp = frame.bpp;
+    metadata.params.channels = frame.channels;
+    metadata.sensor_temperature = find("CCD-TEMP").and_then(|value| value.as_f64());
+    metadata.bayer_format = bayer_format_from_pattern(find("BAYERPAT").and_then(|value| value.as_str()).unwrap_or(""));
+    metadata.model = find("INSTRUME").and_then(|value| value.as_str()).unwrap_or("").to_string();
+    metadata.serial_num = find("SERIALNO").and_then(|value| value.as_str()).unwrap_or("").to_string();
+    metadata.filter = find("FILTER").and_then(|value| value.as_str()).map(|filter| filter.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn test_frame(bpp: u32, channels: u32, bayer_format: BayerFormat) -> Frame {
+        let mut frame = Frame::new(9, 7, bpp, channels);
+        for index in 0..frame.sample_count() {
+            frame.set_sample(index, (index * 7919 % (frame.max_value() as usize + 1)) as u16);
+        }
+        let metadata = &mut frame.metadata;
+        metadata.bayer_format = bayer_format;
+        metadata.model = "QHY5III178C".to_string();
+        metadata.exposure_start = Some(UNIX_EPOCH + Duration::from_micros(1_718_971_200_250_000));
+        metadata.params.exposure = 2_500_000;
+        metadata.params.gain = 42;
+        metadata.params.offset = 17;
+        metadata.params.bin_mode = BinMode::Bin2x2;
+        metadata.sensor_temperature = Some(-12.5);
+        frame
+    }
+
+    fn options() -> FitsOptions {
+        FitsOptions { site: Some(SiteLocation { latitude: 52.25, longitude: -4.5, elevation: 120.0 }), object: None }
+    }
+
+    fn round_trip(frame: &Frame) -> FitsImage {
+        let bytes = to_bytes(frame, None, &options()).unwrap();
+        assert_eq!(bytes.len() % BLOCK_LEN, 0);
+        let image = from_bytes(&bytes).unwrap();
+        assert_eq!((image.frame.width, image.frame.height, image.frame.channels), (frame.width, frame.height, frame.channels));
+        assert_eq!(image.frame.data, frame.data);
+        image
+    }
+
+    fn check_headers(image: &FitsImage, bayer_pattern: Option<&str>) {
+        assert_eq!(image.get_card("DATE-OBS").and_then(|value| value.as_str()), Some("2024-06-21T12:00:00.250000"));
+        assert_eq!(image.get_card("EXPTIME").and_then(|value| value.as_f64()), Some(2.5));
+        assert_eq!(image.get_card("GAIN"), Some(&FitsValue::Integer(42)));
+        assert_eq!(image.get_card("OFFSET"), Some(&FitsValue::Integer(17)));
+        assert_eq!(image.get_card("XBINNING"), Some(&FitsValue::Integer(2)));
+        assert_eq!(image.get_card("CCD-TEMP").and_then(|value| value.as_f64()), Some(-12.5));
+        assert_eq!(image.get_card("BAYERPAT").and_then(|value| value.as_str()), bayer_pattern);
+        assert_eq!(image.get_card("SITELAT").and_then(|value| value.as_f64()), Some(52.25));
+        assert_eq!(image.get_card("SITELONG").and_then(|value| value.as_f64()), Some(-4.5));
+
+        let metadata = &image.frame.metadata;
+        assert_eq!(metadata.params.exposure, 2_500_000);
+        assert_eq!(metadata.params.gain, 42);
+        assert_eq!(metadata.params.offset, 17);
+        assert_eq!(metadata.params.bin_mode, BinMode::Bin2x2);
+        assert_eq!(metadata.sensor_temperature, Some(-12.5));
+        assert_eq!(metadata.exposure_start, Some(UNIX_EPOCH + Duration::from_micros(1_718_971_200_250_000)));
+    }
+
+    #[test]
+    fn mono_8bit_round_trip() {
+        let image = round_trip(&test_frame(8, 1, BayerFormat::Mono));
+        assert_eq!(image.get_card("BITPIX"), Some(&FitsValue::Integer(8)));
+        check_headers(&image, None);
+    }
+
+    #[test]
+    fn bayer_16bit_round_trip() {
+        let image = round_trip(&test_frame(16, 1, BayerFormat::RG));
+        assert_eq!(image.get_card("BZERO").and_then(|value| value.as_f64()), Some(32768.0));
+        check_headers(&image, Some("RGGB"));
+        assert_eq!(image.frame.metadata.bayer_format, BayerFormat::RG);
+    }
+
+    #[test]
+    fn bgr_planes_round_trip() {
+        let frame = test_frame(16, 3, BayerFormat::Mono);
+        let image = round_trip(&frame);
+        assert_eq!(image.get_card("NAXIS3"), Some(&FitsValue::Integer(3)));
+        check_headers(&image, None);
+
+        // The first plane is red, the last channel of the interleaved BGR samples
+        let bytes = to_bytes(&frame, None, &options()).unwrap();
+        let data_start = bytes.len() - (frame.data_len().div_ceil(BLOCK_LEN) * BLOCK_LEN);
+        let first = i16::from_be_bytes([bytes[data_start], bytes[data_start + 1]]) as i32 + 32768;
+        assert_eq!(first as u16, frame.get_sample(2));
+    }
+
+    #[test]
+    fn rejects_bad_axes() {
+        let mut bytes = to_bytes(&test_frame(8, 1, BayerFormat::Mono), None, &options()).unwrap();
+        let header = String::from_utf8_lossy(&bytes[..BLOCK_LEN]).to_string();
+        let position = header.find("NAXIS1").unwrap();
+        let card = FitsCard::new("NAXIS1", FitsValue::Integer(-1), "").to_string();
+        bytes[position..position + CARD_LEN].copy_from_slice(card.as_bytes());
+        assert!(matches!(from_bytes(&bytes), Err(FitsError::Format(_))));
+
+        let card = FitsCard::new("NAXIS1", FitsValue::Integer(60000), "").to_string();
+        bytes[position..position + CARD_LEN].copy_from_slice(card.as_bytes());
+        assert!(matches!(from_bytes(&bytes), Err(FitsError::Format(_))));
+    }
+
+    #[test]
+    fn utc_round_trip() {
+        let time = UNIX_EPOCH + Duration::from_micros(1_709_210_096_123_456);
+        let text = astro::format_utc(time);
+        assert_eq!(text, "2024-02-29T12:34:56.123456");
+        assert_eq!(astro::parse_utc(&text), Some(time));
+        assert_eq!(astro::parse_utc("2024-02-29T12:34:56Z"), Some(UNIX_EPOCH + Duration::from_secs(1_709_210_096)));
+        for bad in ["2023-02-29T00:00:00", "2024-13-01T00:00:00", "2024-01-01T24:00:00", "2024-01-01T00:00:00.5é", "2024-01-01T-1:00:00", "2024-01-01"] {
+            assert_eq!(astro::parse_utc(bad), None, "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+extern crate opencv;
+
+use std::time::SystemTime;
+use opencv::{core, imgproc, prelude::*};
+use serde::{Deserialize, Serialize};
+use crate::camera::{Camera, CameraParams, CameraVersions};
+use crate::gps::GpsHeader;
+use crate::calibration::CalibrationInfo;
+use crate::overscan::OverscanBias;
+use crate::sdk::BayerFormat;
+use crate::timing::FrameTiming;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FrameMetadata {
+    pub cam_id: String,
+    pub model: String,
+    pub serial_num: String,
+    pub bayer_format: BayerFormat,
+    pub params: CameraParams,
+    #[serde(default)]
+    pub versions: CameraVersions,
+
+    pub exposure_start: Option<SystemTime>,
+    pub exposure_end: Option<SystemTime>,
+    pub trigger_sequence: Option<u64>,
+    pub gps: Option<GpsHeader>,
+    pub timing: Option<FrameTiming>,
+    pub filter: Option<String>,
+    pub overscan_bias: Option<OverscanBias>,
+    pub sensor_temperature: Option<f64>,
+    pub defects_corrected: usize,
+    pub calibration: Option<CalibrationInfo>,
+}
+
+// Raw frame as delivered by the SDK, 16 bit samples are stored in native byte order
+#[derive(Debug, Clone, Default)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub bpp: u32,
+    pub channels: u32,
+    pub data: Vec<u8>,
+    pub metadata: FrameMetadata,
+}
+
+impl Frame {
+    pub fn new(width: u32, height: u32, bpp: u32, channels: u32) -> Self {
+        let mut frame = Frame { width, height, bpp, channels, ..Frame::default() };
+        frame.data = vec![0; frame.data_len()];
+        frame
+    }
+
+    pub fn bytes_per_sample(&self) -> usize {
+        if self.bpp > 8 { 2 } else { 1 }
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.width as usize * self.height as usize * self.channels.max(1) as usize
+    }
+
+    pub fn data_len(&self) -> usize {
+        self.sample_count() * self.bytes_per_sample()
+    }
+
+    pub fn max_value(&self) -> u16 {
+        if self.bpp > 8 { u16::MAX } else { u8::MAX as u16 }
+    }
+
+    pub fn get_sample(&self, index: usize) -> u16 {
+        if self.bytes_per_sample() == 2 {
+            u16::from_ne_bytes([self.data[2 * index], self.data[2 * index + 1]])
+        } else {
+            self.data[index] as u16
+        }
+    }
+
+    pub fn set_sample(&mut self, index: usize, value: u16) {
+        if self.bytes_per_sample() == 2 {
+            self.data[2 * index..2 * index + 2].copy_from_slice(&value.to_ne_bytes());
+        } else {
+            self.data[index] = value.min(u8::MAX as u16) as u8;
+        }
+    }
+
+    // Single channel frames only, x and y in pixels
+    pub fn get_pixel(&self, x: u32, y: u32) -> u16 {
+        self.get_sample(y as usize * self.width as usize + x as usize)
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, value: u16) {
+        let index = y as usize * self.width as usize + x as usize;
+        self.set_sample(index, value);
+    }
+
+    pub fn samples(&self) -> Vec<u16> {
+        (0..self.sample_count()).map(|index| self.get_sample(index)).collect()
+    }
+
+    pub fn is_bayer(&self) -> bool {
+        self.channels <= 1 && self.metadata.bayer_format != BayerFormat::Mono
+    }
+
+    // Start of the exposure, or when the frame arrived if the start is unknown
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        self.metadata.exposure_start.or(self.metadata.timing.as_ref().map(|timing| timing.received_utc))
+    }
+
+    pub fn to_mat(&self) -> Option<Mat> {
+        let depth = if self.bytes_per_sample() == 2 { core::CV_16U } else { core::CV_8U };
+        let mat_type = core::CV_MAKETYPE(depth, self.channels.max(1) as i32);
+        let img_res = unsafe { Mat::new_rows_cols_with_data(self.height as i32, self.width as i32, mat_type, self.data.as_ptr() as *mut _, core::Mat_AUTO_STEP) };
+        img_res.and_then(|img| img.try_clone()).ok()
+    }
+
+    // Raw Bayer frames only, the result is BGR like the live debayered frames
+    pub fn debayer(&self) -> Option<Frame> {
+        if !self.is_bayer() {
+            return None
+        }
+        let raw = self.to_mat()?;
+        let mut bgr = Mat::default();
+        let bayer_pattern = Camera::convert_bayer_pattern(self.metadata.bayer_format);
+        imgproc::cvt_color(&raw, &mut bgr, bayer_pattern, 0).ok()?;
+
+        let mut frame = Frame::new(self.width, self.height, self.bpp, 3);
+        let bytes = bgr.data_bytes().ok()?;
+        let len = frame.data_len();
+        if bytes.len() < len {
+            return None
+        }
+        frame.data.copy_from_slice(&bytes[..len]);
+        frame.metadata = self.metadata.clone();
+        frame.metadata.params.channels = 3;
+        Some(frame)
+    }
+}
@@ -0,0 +1,202 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+
+pub const GPS_HEADER_LEN: usize = 44;
+
+// GPS seconds count from JD 2450000.5 (1995-10-10 00:00 UTC)
+const GPS_EPOCH_UNIX_SECS: u64 = 813_283_200;
+const GPS_TICKS_PER_SECOND: f64 = 10_000_000.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct GpsTimestamp {
+    pub flag: u8,
+    pub seconds: u32,
+    pub ticks: u32,
+}
+
+// Header QHY GPS cameras write over the first bytes of every frame, all fields big endian
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct GpsHeader {
+    pub sequence: u32,
+    pub image_id: u8,
+    pub width: u16,
+    pub height: u16,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub exposure_start: GpsTimestamp,
+    pub exposure_end: GpsTimestamp,
+    pub now: GpsTimestamp,
+    pub pps_counter: u32,
+}
+
+impl GpsHeader {
+    pub fn parse(data: &[u8]) -> Option<GpsHeader> {
+        if data.len() < GPS_HEADER_LEN {
+            return None
+        }
+        let header = GpsHeader {
+            sequence: read_u32(&data[0..4]),
+            image_id: data[4],
+            width: u16::from_be_bytes([data[5], data[6]]),
+            height: u16::from_be_bytes([data[7], data[8]]),
+            latitude: decode_latitude(read_u32(&data[9..13])),
+            longitude: decode_longitude(read_u32(&data[13..17])),
+            exposure_start: read_timestamp(&data[17..25]),
+            exposure_end: read_timestamp(&data[25..33]),
+            now: read_timestamp(&data[33..41]),
+            pps_counter: read_u24(&data[41..44]),
+        };
+
+        // A header of zeros means GPS mode is off or the receiver has not started yet
+        if header.exposure_start.seconds == 0 && header.exposure_end.seconds == 0 {
+            return None
+        }
+
+        Some(header)
+    }
+
+    pub fn start_time(&self) -> SystemTime {
+        self.to_system_time(&self.exposure_start)
+    }
+
+    pub fn end_time(&self) -> SystemTime {
+        self.to_system_time(&self.exposure_end)
+    }
+
+    pub fn exposure(&self) -> Duration {
+        self.end_time().duration_since(self.start_time()).unwrap_or_default()
+    }
+
+    // The tick counter runs from a free clock, the PPS counter says how many ticks made the last second
+    fn to_system_time(&self, timestamp: &GpsTimestamp) -> SystemTime {
+        let ticks_per_second = if self.pps_counter > 0 { self.pps_counter as f64 } else { GPS_TICKS_PER_SECOND };
+        let fraction = (timestamp.ticks as f64 / ticks_per_second).min(1.0);
+        UNIX_EPOCH + Duration::from_secs(GPS_EPOCH_UNIX_SECS + timestamp.seconds as u64) + Duration::from_secs_f64(fraction)
+    }
+}
+
+// The header is written over image pixels, replace it with the same bytes of the next row of the
+// same colour: two rows down on Bayer frames, so the CFA phase stays right for debayering
+pub fn strip_header(data: &mut [u8], row_bytes: usize, is_bayer: bool) {
+    let len = GPS_HEADER_LEN.min(data.len());
+    let source = if is_bayer { 2 * row_bytes } else { row_bytes };
+    if row_bytes >= len && data.len() >= source + len {
+        data.copy_within(source..source + len, 0);
+    } else {
+        data[..len].fill(0);
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn read_u24(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]])
+}
+
+fn read_timestamp(bytes: &[u8]) -> GpsTimestamp {
+    GpsTimestamp {
+        flag: bytes[0],
+        seconds: read_u32(&bytes[1..5]),
+        ticks: read_u24(&bytes[5..8]),
+    }
+}
+
+// Latitude is encoded as [1]DDMMmmmmm, a leading 1 means south, minutes carry 5 decimals
+fn decode_latitude(raw: u32) -> f64 {
+    let is_south = raw >= 1_000_000_000;
+    let value = raw % 1_000_000_000;
+    let degrees = (value / 10_000_000) as f64 + (value % 10_000_000) as f64 / 100_000.0 / 60.0;
+    if is_south { -degrees } else { degrees }
+}
+
+// Longitude is encoded as [1]DDDMMmmmm, a leading 1 means west, minutes carry 4 decimals
+fn decode_longitude(raw: u32) -> f64 {
+    let is_west = raw >= 1_000_000_000;
+    let value = raw % 1_000_000_000;
+    let degrees = (value / 1_000_000) as f64 + (value % 1_000_000) as f64 / 10_000.0 / 60.0;
+    if is_west { -degrees } else { degrees }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_timestamp(data: &mut [u8], seconds: u32, ticks: u32) {
+        data[0] = 1;
+        data[1..5].copy_from_slice(&seconds.to_be_bytes());
+        data[5..8].copy_from_slice(&ticks.to_be_bytes()[1..]);
+    }
+
+    fn test_header(pps_counter: u32) -> Vec<u8> {
+        let mut data = vec![0u8; 64];
+        data[0..4].copy_from_slice(&1234u32.to_be_bytes());
+        data[4] = 7;
+        data[5..7].copy_from_slice(&3056u16.to_be_bytes());
+        data[7..9].copy_from_slice(&2048u16.to_be_bytes());
+        // 52 deg 15.00000' N and 4 deg 30.0000' W
+        data[9..13].copy_from_slice(&521_500_000u32.to_be_bytes());
+        data[13..17].copy_from_slice(&1_004_300_000u32.to_be_bytes());
+        put_timestamp(&mut data[17..25], 900_000_000, 5_000_000);
+        put_timestamp(&mut data[25..33], 900_000_001, 7_500_000);
+        put_timestamp(&mut data[33..41], 900_000_002, 0);
+        data[41..44].copy_from_slice(&pps_counter.to_be_bytes()[1..]);
+        data
+    }
+
+    #[test]
+    fn parses_header() {
+        let header = GpsHeader::parse(&test_header(10_000_000)).unwrap();
+        assert_eq!((header.sequence, header.image_id, header.width, header.height), (1234, 7, 3056, 2048));
+        assert!((header.latitude - 52.25).abs() < 1e-9);
+        assert!((header.longitude + 4.5).abs() < 1e-9);
+        assert_eq!(header.exposure_start, GpsTimestamp { flag: 1, seconds: 900_000_000, ticks: 5_000_000 });
+        assert_eq!(header.pps_counter, 10_000_000);
+    }
+
+    #[test]
+    fn decodes_south_and_east() {
+        assert!((decode_latitude(1_333_000_000) + 33.5).abs() < 1e-9);
+        assert!((decode_longitude(151_120_000) - 151.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rejects_empty_or_short_headers() {
+        assert!(GpsHeader::parse(&[0u8; 64]).is_none());
+        assert!(GpsHeader::parse(&test_header(10_000_000)[..GPS_HEADER_LEN - 1]).is_none());
+    }
+
+    #[test]
+    fn start_and_end_times() {
+        let header = GpsHeader::parse(&test_header(10_000_000)).unwrap();
+        let second = UNIX_EPOCH + Duration::from_secs(GPS_EPOCH_UNIX_SECS + 900_000_000);
+        assert_eq!(header.start_time(), second + Duration::from_millis(500));
+        assert_eq!(header.end_time(), second + Duration::from_millis(1750));
+        assert_eq!(header.exposure(), Duration::from_millis(1250));
+
+        // The PPS counter scales the ticks of a clock running fast or slow
+        let header = GpsHeader::parse(&test_header(12_500_000)).unwrap();
+        assert_eq!(header.start_time(), second + Duration::from_millis(400));
+        // Without a PPS count the nominal tick rate is used
+        let header = GpsHeader::parse(&test_header(0)).unwrap();
+        assert_eq!(header.start_time(), second + Duration::from_millis(500));
+    }
+
+    #[test]
+    fn strips_header_with_the_same_colour() {
+        let row_bytes = 64;
+        let mut data: Vec<u8> = (0..4 * row_bytes).map(|index| (index / row_bytes) as u8 + 1).collect();
+        strip_header(&mut data, row_bytes, false);
+        assert!(data[..GPS_HEADER_LEN].iter().all(|value| *value == 2));
+        assert!(data[GPS_HEADER_LEN..row_bytes].iter().all(|value| *value == 1));
+
+        let mut data: Vec<u8> = (0..4 * row_bytes).map(|index| (index / row_bytes) as u8 + 1).collect();
+        strip_header(&mut data, row_bytes, true);
+        assert!(data[..GPS_HEADER_LEN].iter().all(|value| *value == 3));
+
+        let mut data = vec![9u8; 2 * GPS_HEADER_LEN];
+        strip_header(&mut data, GPS_HEADER_LEN, true);
+        assert!(data[..GPS_HEADER_LEN].iter().all(|value| *value == 0));
+    }
+}
@@ -0,0 +1,190 @@
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use derive_more::Display;
+use crate::camera::Camera;
+
+// Values are the SDK's ST4 direction codes
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuideDirection {
+    East = 0,
+    North = 1,
+    South = 2,
+    West = 3,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GuideError {
+    NotAvailable(String),
+    InvalidDuration(Duration),
+    Failed(GuideDirection),
+    Stopped,
+}
+
+#[derive(Debug, Clone)]
+pub struct GuideLimits {
+    pub min_pulse: Duration,
+    pub max_pulse: Duration,
+}
+
+struct GuideCommand {
+    direction: GuideDirection,
+    duration: Duration,
+    done: Sender<Result<(), GuideError>>,
+}
+
+// Runs pulses one at a time on its own thread. The camera lock is only held for the SDK call, so
+// a pulse waits at most for the frame the capture thread is busy with.
+pub struct Guider {
+    limits: GuideLimits,
+    commands: Option<Sender<GuideCommand>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Guider {
+    pub fn start(camera: Arc<Mutex<Camera>>, limits: GuideLimits) -> Result<Guider, GuideError> {
+        let cam_id = {
+            let camera = camera.lock().map_err(|_| GuideError::Stopped)?;
+            if !camera.is_open() || !camera.has_guide_port() {
+                return Err(GuideError::NotAvailable(camera.get_cam_id().to_string()))
+            }
+            camera.get_cam_id().to_string()
+        };
+
+        let (sender, receiver) = mpsc::channel::<GuideCommand>();
+        let handle = thread::Builder::new().name(format!("guide-{}", cam_id)).spawn(move || {
+            for command in receiver {
+                let started = Instant::now();
+                let is_sent = match camera.lock() {
+                    Ok(mut camera) => camera.guide_pulse(command.direction, command.duration.as_millis() as u16),
+                    Err(_) => false,
+                };
+                // The next pulse must not start before the mount finished this one
+                if is_sent {
+                    thread::sleep(command.duration.saturating_sub(started.elapsed()));
+                }
+                let _ = command.done.send(if is_sent { Ok(()) } else { Err(GuideError::Failed(command.direction)) });
+            }
+        }).map_err(|_| GuideError::NotAvailable(cam_id))?;
+
+        Ok(Guider {
+            limits,
+            commands: Some(sender),
+            handle: Some(handle),
+        })
+    }
+
+    pub fn get_limits(&self) -> &GuideLimits {
+        &self.limits
+    }
+
+    pub fn check_duration(&self, duration: Duration) -> Result<u16, GuideError> {
+        let max_pulse = self.limits.max_pulse.min(Duration::from_millis(u16::MAX as u64));
+        if duration < self.limits.min_pulse || duration > max_pulse || duration.as_millis() == 0 {
+            return Err(GuideError::InvalidDuration(duration))
+        }
+
+        Ok(duration.as_millis() as u16)
+    }
+
+    // Queues the pulse behind any pending ones, the receiver reports when it has finished
+    pub fn queue_pulse(&self, direction: GuideDirection, duration: Duration) -> Result<Receiver<Result<(), GuideError>>, GuideError> {
+        let millis = self.check_duration(duration)?;
+        let (done, finished) = mpsc::channel();
+        let command = GuideCommand { direction, duration: Duration::from_millis(millis as u64), done };
+        match &self.commands {
+            Some(commands) => commands.send(command).map_err(|_| GuideError::Stopped)?,
+            None => return Err(GuideError::Stopped),
+        }
+
+        Ok(finished)
+    }
+
+    pub fn pulse(&self, direction: GuideDirection, duration: Duration) -> Result<(), GuideError> {
+        let finished = self.queue_pulse(direction, duration)?;
+        finished.recv().unwrap_or(Err(GuideError::Stopped))
+    }
+
+    // Pending pulses still run, the call returns once the last one finished
+    pub fn stop(&mut self) {
+        self.commands = None;
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                eprintln!("Guide thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for Guider {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Default for GuideLimits {
+    fn default() -> Self {
+        GuideLimits {
+            min_pulse: Duration::from_millis(1),
+            max_pulse: Duration::from_secs(5),
+        }
+    }
+}
+
+impl fmt::Display for GuideError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GuideError::NotAvailable(cam_id) => write!(f, "ST4 guide port not available on camera: {}", cam_id),
+            GuideError::InvalidDuration(duration) => write!(f, "invalid guide pulse duration: {:?}", duration),
+            GuideError::Failed(direction) => write!(f, "guide pulse {} failed", direction),
+            GuideError::Stopped => write!(f, "guider stopped"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Never started, only the checks before a pulse is queued are exercised
+    fn test_guider(limits: GuideLimits) -> Guider {
+        Guider { limits, commands: None, handle: None }
+    }
+
+    #[test]
+    fn durations_within_the_limits() {
+        let guider = test_guider(GuideLimits::default());
+        assert_eq!(guider.check_duration(Duration::from_millis(1)), Ok(1));
+        assert_eq!(guider.check_duration(Duration::from_millis(250)), Ok(250));
+        assert_eq!(guider.check_duration(Duration::from_secs(5)), Ok(5000));
+        // Sub-millisecond parts are dropped, the SDK takes whole milliseconds
+        assert_eq!(guider.check_duration(Duration::from_micros(1500)), Ok(1));
+    }
+
+    #[test]
+    fn durations_outside_the_limits() {
+        let guider = test_guider(GuideLimits::default());
+        for duration in [Duration::ZERO, Duration::from_micros(999), Duration::from_millis(5001)] {
+            assert_eq!(guider.check_duration(duration), Err(GuideError::InvalidDuration(duration)));
+        }
+    }
+
+    #[test]
+    fn durations_fit_the_sdk_range() {
+        let guider = test_guider(GuideLimits { min_pulse: Duration::ZERO, max_pulse: Duration::from_secs(3600) });
+        assert_eq!(guider.check_duration(Duration::from_millis(u16::MAX as u64)), Ok(u16::MAX));
+        let too_long = Duration::from_millis(u16::MAX as u64 + 1);
+        assert_eq!(guider.check_duration(too_long), Err(GuideError::InvalidDuration(too_long)));
+        // Rounds down to no pulse at all
+        assert_eq!(guider.check_duration(Duration::from_micros(500)), Err(GuideError::InvalidDuration(Duration::from_micros(500))));
+    }
+
+    #[test]
+    fn stopped_guider_rejects_pulses() {
+        let guider = test_guider(GuideLimits::default());
+        assert!(matches!(guider.queue_pulse(GuideDirection::North, Duration::from_millis(100)), Err(GuideError::Stopped)));
+        assert!(matches!(guider.queue_pulse(GuideDirection::North, Duration::ZERO), Err(GuideError::InvalidDuration(_))));
+    }
+}
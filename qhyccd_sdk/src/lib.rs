@@ -1,3 +1,27 @@
 
 pub mod sdk;
 pub mod camera;
+pub mod white_balance;
+pub mod astro;
+pub mod scheduler;
+pub mod profile;
+pub mod config_watch;
+pub mod manager;
+pub mod supervisor;
+pub mod watchdog;
+pub mod frame;
+pub mod trigger;
+pub mod gps;
+pub mod timing;
+pub mod filter_wheel;
+pub mod guide;
+pub mod ddr;
+pub mod overscan;
+pub mod defects;
+pub mod calibration;
+pub mod pattern_noise;
+pub mod fits;
+pub mod ser;
+pub mod export;
+pub mod archive;
+pub mod recorder;
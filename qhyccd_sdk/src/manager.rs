@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crate::camera::{Camera, CameraInfo};
+use crate::sdk::QhyCcd;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CameraSelector {
+    Id(String),
+    Serial(String),
+    Model(String),
+}
+
+struct CaptureThread {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+// Pause of the capture thread after each iteration with the camera unlocked; the mutex is not fair,
+// without it the thread takes the lock straight back and the other threads never get it
+const CAPTURE_LOCK_PAUSE: Duration = Duration::from_millis(1);
+
+// Owns the global SDK resources for every camera it opens, so a camera going away never
+// releases them under the others
+pub struct CameraManager {
+    cameras: BTreeMap<String, CameraInfo>,
+    open_cameras: BTreeMap<String, Arc<Mutex<Camera>>>,
+    capture_threads: HashMap<String, CaptureThread>,
+
+    is_debug_info: bool,
+    is_sdk_init: bool,
+}
+
+impl CameraManager {
+    pub fn new() -> Self {
+        QhyCcd::enable_message(false);
+        QhyCcd::enable_log_file(false);
+        CameraManager::default()
+    }
+
+    pub fn init(&mut self) -> bool {
+        if !self.is_sdk_init {
+            match QhyCcd::init_resource() {
+                Ok(()) => self.is_sdk_init = true,
+                Err(err) => eprintln!("Cannot initialize SDK resources: {}", err),
+            }
+        }
+
+        self.is_sdk_init
+    }
+
+    pub fn set_debug_info(&mut self, enable: bool) {
+        self.is_debug_info = enable;
+    }
+
+    // Scanning opens every camera to read its info, so it is only allowed while none is in use
+    pub fn scan(&mut self) -> usize {
+        if !self.open_cameras.is_empty() {
+            eprintln!("Cannot rescan while {} camera(s) are open", self.open_cameras.len());
+            return self.cameras.len()
+        }
+        if !self.init() {
+            return 0
+        }
+
+        let mut scanner = Camera::new_shared(HashMap::new());
+        scanner.set_debug_info(self.is_debug_info);
+        self.cameras = scanner.get_cameras().iter().map(|(id, info)| (id.clone(), info.clone())).collect();
+
+        self.cameras.len()
+    }
+
+    pub fn get_cameras(&self) -> &BTreeMap<String, CameraInfo> {
+        &self.cameras
+    }
+
+    // For serial and model selectors the first camera in id order that is not open yet wins
+    pub fn find(&self, selector: &CameraSelector) -> Option<&CameraInfo> {
+        let candidates: Vec<&CameraInfo> = self.cameras.values().filter(|info| match selector {
+            CameraSelector::Id(id) => &info.id == id,
+            CameraSelector::Serial(serial) => &info.serial_num == serial,
+            CameraSelector::Model(model) => &info.model == model,
+        }).collect();
+
+        candidates.iter()
+            .find(|info| !self.open_cameras.contains_key(&info.id))
+            .or(candidates.first())
+            .copied()
+    }
+
+    pub fn open(&mut self, selector: &CameraSelector) -> Option<Arc<Mutex<Camera>>> {
+        if self.cameras.is_empty() && self.scan() == 0 {
+            return None
+        }
+        let has_info = self.find(selector);
+        if has_info.is_none() {
+            eprintln!("No camera matches {:?}", selector);
+            return None
+        }
+        let cam_id = has_info.unwrap().id.clone();
+
+        if let Some(camera) = self.open_cameras.get(&cam_id) {
+            return Some(camera.clone())
+        }
+
+        let known = self.cameras.iter().map(|(id, info)| (id.clone(), info.clone())).collect();
+        let mut camera = Camera::new_shared(known);
+        camera.set_debug_info(self.is_debug_info);
+        if !camera.open(&cam_id) {
+            return None
+        }
+
+        let camera = Arc::new(Mutex::new(camera));
+        self.open_cameras.insert(cam_id, camera.clone());
+
+        Some(camera)
+    }
+
+    pub fn get_camera(&self, cam_id: &str) -> Option<Arc<Mutex<Camera>>> {
+        self.open_cameras.get(cam_id).cloned()
+    }
+
+    pub fn get_open_ids(&self) -> Vec<String> {
+        self.open_cameras.keys().cloned().collect()
+    }
+
+    // The handler runs once per loop with the camera locked, returning false ends the thread.
+    // Other threads get the lock in a short pause between iterations, i.e. between frames.
+    pub fn start_capture<F>(&mut self, cam_id: &str, mut handler: F) -> bool
+    where
+        F: FnMut(&mut Camera) -> bool + Send + 'static,
+    {
+        if self.capture_threads.contains_key(cam_id) {
+            eprintln!("Capture thread already running for camera: {}", cam_id);
+            return false
+        }
+        let has_camera = self.open_cameras.get(cam_id);
+        if has_camera.is_none() {
+            eprintln!("Camera not open: {}", cam_id);
+            return false
+        }
+        let camera = has_camera.unwrap().clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread_name = format!("capture-{}", cam_id);
+
+        let spawned = thread::Builder::new().name(thread_name).spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                let keep_running = match camera.lock() {
+                    Ok(mut camera) => handler(&mut camera),
+                    Err(_) => false,
+                };
+                if !keep_running {
+                    break;
+                }
+                thread::sleep(CAPTURE_LOCK_PAUSE);
+            }
+        });
+
+        match spawned {
+            Ok(handle) => {
+                self.capture_threads.insert(cam_id.to_string(), CaptureThread { stop, handle });
+                true
+            },
+            Err(err) => {
+                eprintln!("Cannot start capture thread for camera {}: {}", cam_id, err);
+                false
+            }
+        }
+    }
+
+    pub fn stop_capture(&mut self, cam_id: &str) {
+        if let Some(capture) = self.capture_threads.remove(cam_id) {
+            capture.stop.store(true, Ordering::Relaxed);
+            if capture.handle.join().is_err() {
+                eprintln!("Capture thread for camera {} panicked", cam_id);
+            }
+        }
+    }
+
+    pub fn close(&mut self, cam_id: &str) {
+        self.stop_capture(cam_id);
+        if let Some(camera) = self.open_cameras.remove(cam_id) {
+            if let Ok(mut camera) = camera.lock() {
+                camera.close();
+            }
+        }
+    }
+
+    pub fn release(&mut self) {
+        let cam_ids: Vec<String> = self.open_cameras.keys().cloned().collect();
+        for cam_id in cam_ids.iter() {
+            self.close(cam_id);
+        }
+
+        if self.is_sdk_init {
+            let res = QhyCcd::release_resource();
+            if res.is_err() {
+                eprintln!("Cannot release SDK resources, error: {}", res.unwrap_err());
+            }
+            self.is_sdk_init = false;
+        }
+        self.cameras.clear();
+    }
+}
+
+impl Default for CameraManager {
+    fn default() -> Self {
+        CameraManager {
+            cameras: BTreeMap::new(),
+            open_cameras: BTreeMap::new(),
+            capture_threads: HashMap::new(),
+
+            is_debug_info: false,
+            is_sdk_init: false,
+        }
+    }
+}
+
+impl Drop for CameraManager {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
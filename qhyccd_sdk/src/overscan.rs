@@ -0,0 +1,83 @@
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use crate::frame::Frame;
+use crate::sdk::CameraArea;
+
+#[derive(Display, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OverscanMode {
+    #[default]
+    Off,
+    // One bias level from the whole overscan strip
+    Frame,
+    // One bias level per row, needs an overscan strip beside the image; rows outside it use the frame level
+    Row,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct OverscanBias {
+    pub mode: OverscanMode,
+    pub level: f64,
+    pub row_min: f64,
+    pub row_max: f64,
+}
+
+// Frame and areas in sensor pixels: the frame has to be the full 1x1 readout including the overscan.
+// Subtracts the bias, clamping at zero, and crops the frame to the effective area.
+pub fn correct(frame: &mut Frame, overscan: &CameraArea, effective: &CameraArea, mode: OverscanMode) -> Option<OverscanBias> {
+    if mode == OverscanMode::Off || frame.channels > 1 || overscan.width == 0 || overscan.height == 0 {
+        return None
+    }
+    let fits = |area: &CameraArea| area.start_x + area.width <= frame.width && area.start_y + area.height <= frame.height;
+    if !fits(overscan) || !fits(effective) || effective.width == 0 || effective.height == 0 {
+        return None
+    }
+
+    let row_samples = |y: u32| (overscan.start_x..overscan.start_x + overscan.width).map(move |x| (x, y));
+    let mut all_samples: Vec<u16> = (overscan.start_y..overscan.start_y + overscan.height)
+        .flat_map(row_samples)
+        .map(|(x, y)| frame.get_pixel(x, y))
+        .collect();
+    let frame_level = median(&mut all_samples);
+
+    let row_levels: Vec<f64> = (effective.start_y..effective.start_y + effective.height).map(|y| {
+        let is_covered = y >= overscan.start_y && y < overscan.start_y + overscan.height;
+        if mode == OverscanMode::Row && is_covered {
+            let mut samples: Vec<u16> = row_samples(y).map(|(x, y)| frame.get_pixel(x, y)).collect();
+            median(&mut samples)
+        } else {
+            frame_level
+        }
+    }).collect();
+
+    let mut cropped = Frame::new(effective.width, effective.height, frame.bpp, frame.channels);
+    cropped.metadata = frame.metadata.clone();
+    for (row, level) in row_levels.iter().enumerate() {
+        for column in 0..effective.width {
+            let value = frame.get_pixel(effective.start_x + column, effective.start_y + row as u32) as f64;
+            cropped.set_pixel(column, row as u32, (value - level).round().max(0.0) as u16);
+        }
+    }
+    *frame = cropped;
+
+    let level = row_levels.iter().sum::<f64>() / row_levels.len() as f64;
+    Some(OverscanBias {
+        mode,
+        level,
+        row_min: row_levels.iter().cloned().fold(f64::MAX, f64::min),
+        row_max: row_levels.iter().cloned().fold(f64::MIN, f64::max),
+    })
+}
+
+// Averages the two middle samples of an even count, the samples are reordered
+pub(crate) fn median(samples: &mut [u16]) -> f64 {
+    if samples.is_empty() {
+        return 0.0
+    }
+    samples.sort_unstable();
+    let middle = samples.len() / 2;
+    if samples.len() % 2 == 0 {
+        (samples[middle - 1] as f64 + samples[middle] as f64) / 2.0
+    } else {
+        samples[middle] as f64
+    }
+}
@@ -0,0 +1,262 @@
+use std::time::{Duration, Instant};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use crate::frame::Frame;
+use crate::overscan::median;
+use crate::sdk::{BayerFormat, CameraArea};
+
+// Lines of the same Bayer parity a line is compared with, the interquartile mean of their medians
+// is the baseline; wide enough to ignore a few bad lines, narrow enough to follow sky gradients
+const BASELINE_WINDOW: usize = 61;
+
+#[derive(Display, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PatternNoiseMode {
+    #[default]
+    Off,
+    Rows,
+    Columns,
+    RowsAndColumns,
+}
+
+// Reference regions hold no sky signal: the overscan strip or masked parts such as the corners
+// outside an all-sky lens circle. Without any, whole rows and columns are used; the median ignores
+// the few bright stars and structure wider than BASELINE_WINDOW lines (horizon glow, moonlight
+// and twilight gradients) is kept, but narrower bright features such as a cloud edge lose some
+// of their contrast.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PatternNoiseConfig {
+    pub mode: PatternNoiseMode,
+    pub use_overscan: bool,
+    pub masks: Vec<CameraArea>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PatternNoiseBenchmark {
+    pub width: u32,
+    pub height: u32,
+    pub banding_rms_before: f64,
+    pub banding_rms_after: f64,
+    pub elapsed: Duration,
+}
+
+// Per row and per column offsets against a running baseline of the nearby lines of the same Bayer parity
+pub fn estimate(frame: &Frame, mode: PatternNoiseMode, regions: &[CameraArea]) -> (Vec<f64>, Vec<f64>) {
+    let step = if frame.is_bayer() { 2 } else { 1 };
+    let rows = if matches!(mode, PatternNoiseMode::Rows | PatternNoiseMode::RowsAndColumns) {
+        line_offsets(frame.height, step, |y| {
+            let mut samples: Vec<u16> = regions.iter()
+                .filter(|area| y >= area.start_y && y < area.start_y + area.height)
+                .flat_map(|area| (area.start_x..(area.start_x + area.width).min(frame.width)).map(move |x| (x, y)))
+                .map(|(x, y)| frame.get_pixel(x, y))
+                .collect();
+            let is_region = !samples.is_empty();
+            if !is_region {
+                samples = (0..frame.width).map(|x| frame.get_pixel(x, y)).collect();
+            }
+            (samples, is_region)
+        })
+    } else {
+        Vec::new()
+    };
+    let columns = if matches!(mode, PatternNoiseMode::Columns | PatternNoiseMode::RowsAndColumns) {
+        line_offsets(frame.width, step, |x| {
+            let mut samples: Vec<u16> = regions.iter()
+                .filter(|area| x >= area.start_x && x < area.start_x + area.width)
+                .flat_map(|area| (area.start_y..(area.start_y + area.height).min(frame.height)).map(move |y| (x, y)))
+                .map(|(x, y)| frame.get_pixel(x, y))
+                .collect();
+            let is_region = !samples.is_empty();
+            if !is_region {
+                samples = (0..frame.height).map(|y| frame.get_pixel(x, y)).collect();
+            }
+            (samples, is_region)
+        })
+    } else {
+        Vec::new()
+    };
+
+    (rows, columns)
+}
+
+// Single channel frames only, the regions are in frame pixels. Columns are estimated after the
+// rows are removed, otherwise strong banding leaks into them.
+pub fn remove(frame: &mut Frame, config: &PatternNoiseConfig, regions: &[CameraArea]) -> bool {
+    if config.mode == PatternNoiseMode::Off || frame.channels > 1 {
+        return false
+    }
+    if matches!(config.mode, PatternNoiseMode::Rows | PatternNoiseMode::RowsAndColumns) {
+        let (rows, _) = estimate(frame, PatternNoiseMode::Rows, regions);
+        apply_offsets(frame, &rows, &[]);
+    }
+    if matches!(config.mode, PatternNoiseMode::Columns | PatternNoiseMode::RowsAndColumns) {
+        let (_, columns) = estimate(frame, PatternNoiseMode::Columns, regions);
+        apply_offsets(frame, &[], &columns);
+    }
+
+    true
+}
+
+// Synthetic frame with a flat sky, read noise, row banding and column offsets of the given amplitude
+// in ADU; the banding RMS is the spread of the row and/or column means before and after removal
+pub fn benchmark(width: u32, height: u32, amplitude: f64, mode: PatternNoiseMode) -> PatternNoiseBenchmark {
+    let mut random = Lcg(0x2545_f491_4f6c_dd1d);
+    let row_bands: Vec<f64> = (0..height).map(|_| random.gaussian() * amplitude).collect();
+    let column_bands: Vec<f64> = (0..width).map(|_| random.gaussian() * amplitude * 0.5).collect();
+    let level = 1000.0;
+
+    let mut frame = Frame::new(width, height, 16, 1);
+    frame.metadata.bayer_format = BayerFormat::Mono;
+    for y in 0..height {
+        for x in 0..width {
+            let value = level + row_bands[y as usize] + column_bands[x as usize] + random.gaussian() * 5.0;
+            frame.set_pixel(x, y, value.round().clamp(0.0, u16::MAX as f64) as u16);
+        }
+    }
+
+    // Spread of the row means, of the column means or both, matching what the mode corrects
+    let spread = |means: Vec<f64>| {
+        let mean = means.iter().sum::<f64>() / means.len() as f64;
+        means.iter().map(|line_mean| (line_mean - mean).powi(2)).sum::<f64>() / means.len() as f64
+    };
+    let banding_rms = |frame: &Frame| {
+        let mut variance = 0.0;
+        if matches!(mode, PatternNoiseMode::Rows | PatternNoiseMode::RowsAndColumns) {
+            variance += spread((0..height).map(|y| (0..width).map(|x| frame.get_pixel(x, y) as f64).sum::<f64>() / width as f64).collect());
+        }
+        if matches!(mode, PatternNoiseMode::Columns | PatternNoiseMode::RowsAndColumns) {
+            variance += spread((0..width).map(|x| (0..height).map(|y| frame.get_pixel(x, y) as f64).sum::<f64>() / height as f64).collect());
+        }
+        variance.sqrt()
+    };
+    let banding_rms_before = banding_rms(&frame);
+    let start = Instant::now();
+    remove(&mut frame, &PatternNoiseConfig { mode, ..PatternNoiseConfig::default() }, &[]);
+    let elapsed = start.elapsed();
+
+    PatternNoiseBenchmark {
+        width,
+        height,
+        banding_rms_before,
+        banding_rms_after: banding_rms(&frame),
+        elapsed,
+    }
+}
+
+// Lines measured in a reference region and whole lines sit at different levels (a dark mask
+// against the sky), each line is only compared with lines measured the same way
+fn line_offsets<F>(count: u32, step: u32, mut line_samples: F) -> Vec<f64> where F: FnMut(u32) -> (Vec<u16>, bool) {
+    let (medians, is_region): (Vec<f64>, Vec<bool>) = (0..count)
+        .map(|line| {
+            let (mut samples, is_region) = line_samples(line);
+            (median(&mut samples), is_region)
+        })
+        .unzip();
+    let mut offsets = vec![0.0; count as usize];
+    let half_window = BASELINE_WINDOW / 2;
+    for parity in 0..step {
+        for source in [true, false] {
+            let lines: Vec<usize> = (parity as usize..count as usize).step_by(step as usize)
+                .filter(|&line| is_region[line] == source)
+                .collect();
+            let mut window = Vec::with_capacity(BASELINE_WINDOW);
+            for (position, &line) in lines.iter().enumerate() {
+                let neighbours = &lines[position.saturating_sub(half_window)..(position + half_window + 1).min(lines.len())];
+                window.clear();
+                window.extend(neighbours.iter().map(|&neighbour| medians[neighbour]));
+                window.sort_unstable_by(|a, b| a.total_cmp(b));
+                let quartile = window.len() / 4;
+                let middle = &window[quartile..window.len() - quartile];
+                offsets[line] = medians[line] - middle.iter().sum::<f64>() / middle.len() as f64;
+            }
+        }
+    }
+
+    offsets
+}
+
+fn apply_offsets(frame: &mut Frame, rows: &[f64], columns: &[f64]) {
+    let max_value = frame.max_value() as f64;
+    for y in 0..frame.height {
+        let row = rows.get(y as usize).cloned().unwrap_or(0.0);
+        for x in 0..frame.width {
+            let column = columns.get(x as usize).cloned().unwrap_or(0.0);
+            let value = frame.get_pixel(x, y) as f64 - row - column;
+            frame.set_pixel(x, y, value.round().clamp(0.0, max_value) as u16);
+        }
+    }
+}
+
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> f64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((self.0 >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+    }
+
+    fn gaussian(&mut self) -> f64 {
+        (-2.0 * self.next().ln()).sqrt() * (2.0 * std::f64::consts::PI * self.next()).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn benchmark_removes_banding() {
+        for mode in [PatternNoiseMode::Rows, PatternNoiseMode::Columns, PatternNoiseMode::RowsAndColumns] {
+            let result = benchmark(320, 240, 20.0, mode);
+            assert!(result.banding_rms_before > 5.0, "{}: {:?}", mode, result);
+            assert!(result.banding_rms_after < result.banding_rms_before / 5.0, "{}: {:?}", mode, result);
+        }
+    }
+
+    #[test]
+    fn keeps_wide_gradients() {
+        let (width, height) = (200u32, 300u32);
+        let mut frame = Frame::new(width, height, 16, 1);
+        frame.metadata.bayer_format = BayerFormat::Mono;
+        let mut random = Lcg(7);
+        for y in 0..height {
+            // Horizon glow: 600 ADU brighter at the bottom, plus banding on every tenth row
+            let glow = 1000.0 + 2.0 * y as f64;
+            let band = if y % 10 == 0 { 40.0 } else { 0.0 };
+            for x in 0..width {
+                frame.set_pixel(x, y, (glow + band + random.gaussian() * 3.0).round() as u16);
+            }
+        }
+        remove(&mut frame, &PatternNoiseConfig { mode: PatternNoiseMode::Rows, ..PatternNoiseConfig::default() }, &[]);
+
+        let row_mean = |y: u32| (0..width).map(|x| frame.get_pixel(x, y) as f64).sum::<f64>() / width as f64;
+        assert!((row_mean(250) - row_mean(50) - 400.0).abs() < 5.0, "gradient lost: {} {}", row_mean(50), row_mean(250));
+        assert!((row_mean(150) - (row_mean(149) + row_mean(151)) / 2.0).abs() < 3.0, "band left: {}", row_mean(150));
+    }
+
+    #[test]
+    fn partial_mask_keeps_the_sky_level() {
+        let (width, height) = (200u32, 200u32);
+        let mask = CameraArea { start_x: 0, start_y: 0, width: 20, height: 100 };
+        let mut frame = Frame::new(width, height, 16, 1);
+        frame.metadata.bayer_format = BayerFormat::Mono;
+        let mut random = Lcg(11);
+        for y in 0..height {
+            // The masked corner is dark, the banding runs across the whole row
+            let band = random.gaussian() * 20.0;
+            for x in 0..width {
+                let level = if x < 20 && y < 100 { 100.0 } else { 1000.0 };
+                frame.set_pixel(x, y, (level + band + random.gaussian() * 3.0).round() as u16);
+            }
+        }
+        remove(&mut frame, &PatternNoiseConfig { mode: PatternNoiseMode::Rows, ..PatternNoiseConfig::default() }, &[mask]);
+
+        // Left over banding is a few ADU, a row compared with the other source would be off by ~900
+        let errors: Vec<f64> = (0..height)
+            .map(|y| (20..width).map(|x| frame.get_pixel(x, y) as f64).sum::<f64>() / (width - 20) as f64 - 1000.0)
+            .collect();
+        let rms = (errors.iter().map(|error| error * error).sum::<f64>() / errors.len() as f64).sqrt();
+        assert!(rms < 6.0, "rms: {}", rms);
+        assert!(errors.iter().all(|error| error.abs() < 20.0), "errors: {:?}", errors);
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::camera::{CameraInfo, CameraParams};
+use crate::scheduler::CaptureProfile;
+use crate::timing::LatencyModel;
+use crate::filter_wheel::FilterStep;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProfileFormat {
+    Toml,
+    Json,
+}
+
+#[derive(Debug)]
+pub enum ProfileError {
+    Io(io::Error),
+    Parse(String),
+    NotFound(String),
+    NotOpen(String),
+    Invalid(Vec<String>),
+}
+
+// One file per station, each entry is keyed by camera serial number, camera id or camera model
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProfileFile {
+    #[serde(default)]
+    pub cameras: HashMap<String, CameraProfile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraProfile {
+    #[serde(default)]
+    pub description: String,
+    pub params: CameraParams,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capture_profiles: Vec<CaptureProfile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info: Option<CameraInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency: Option<LatencyModel>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filter_names: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filter_sequence: Vec<FilterStep>,
+}
+
+impl ProfileFormat {
+    pub fn from_path(path: &Path) -> Result<ProfileFormat, ProfileError> {
+        match path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_ascii_lowercase()) {
+            Some(ext) if ext == "toml" => Ok(ProfileFormat::Toml),
+            Some(ext) if ext == "json" => Ok(ProfileFormat::Json),
+            _ => Err(ProfileError::Parse(format!("unsupported profile file extension: {}", path.display()))),
+        }
+    }
+}
+
+impl ProfileFile {
+    pub fn load(path: &Path) -> Result<ProfileFile, ProfileError> {
+        let format = ProfileFormat::from_path(path)?;
+        let content = fs::read_to_string(path).map_err(ProfileError::Io)?;
+        ProfileFile::parse(&content, format)
+    }
+
+    pub fn parse(content: &str, format: ProfileFormat) -> Result<ProfileFile, ProfileError> {
+        match format {
+            ProfileFormat::Toml => toml::from_str(content).map_err(|err| ProfileError::Parse(err.to_string())),
+            ProfileFormat::Json => serde_json::from_str(content).map_err(|err| ProfileError::Parse(err.to_string())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ProfileError> {
+        let content = match ProfileFormat::from_path(path)? {
+            ProfileFormat::Toml => toml::to_string_pretty(self).map_err(|err| ProfileError::Parse(err.to_string()))?,
+            ProfileFormat::Json => serde_json::to_string_pretty(self).map_err(|err| ProfileError::Parse(err.to_string()))?,
+        };
+        fs::write(path, content).map_err(ProfileError::Io)
+    }
+
+    // The most specific key wins: serial number, then camera id, then model
+    pub fn find(&self, info: &CameraInfo) -> Option<&CameraProfile> {
+        [&info.serial_num, &info.id, &info.model]
+            .iter()
+            .filter(|key| !key.is_empty())
+            .find_map(|key| self.cameras.get(key.as_str()))
+    }
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::Io(err) => write!(f, "profile io error: {}", err),
+            ProfileError::Parse(err) => write!(f, "profile parse error: {}", err),
+            ProfileError::NotFound(key) => write!(f, "no profile for camera: {}", key),
+            ProfileError::NotOpen(cam_id) => write!(f, "camera not open: {}", cam_id),
+            ProfileError::Invalid(issues) => write!(f, "profile not valid for camera: {}", issues.join("; ")),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::BinMode;
+    use crate::pattern_noise::PatternNoiseMode;
+    use crate::sdk::{CameraArea, StreamMode};
+
+    fn test_file() -> ProfileFile {
+        let mut params = CameraParams::default();
+        params.roi = CameraArea { start_x: 16, start_y: 8, width: 1920, height: 1080 };
+        params.exposure = 20_000;
+        params.gain = 120;
+        params.bin_mode = BinMode::Bin2x2;
+        params.bpp = 16;
+        params.ddr_buffer = Some(true);
+        let night = CaptureProfile {
+            name: "night".to_string(),
+            exposure: 30_000_000,
+            gain: 300,
+            bin_mode: BinMode::Bin1x1,
+            bpp: 16,
+            read_mode: 1,
+            debayer: true,
+            stream_mode: StreamMode::SingleFrame,
+            pattern_noise: PatternNoiseMode::Rows,
+        };
+        let all_sky = CameraProfile {
+            description: "all-sky camera".to_string(),
+            params,
+            capture_profiles: vec![night],
+            info: None,
+            latency: Some(LatencyModel { readout_latency_us: 1500, transfer_us_per_mb: 2500, jitter_us: 300 }),
+            filter_names: vec!["L".to_string(), "Ha".to_string()],
+            filter_sequence: vec![FilterStep { filter: "Ha".to_string(), frames: 3, exposure: Some(60_000_000) }],
+        };
+        let minimal = CameraProfile {
+            description: String::new(),
+            params: CameraParams::default(),
+            capture_profiles: Vec::new(),
+            info: None,
+            latency: None,
+            filter_names: Vec::new(),
+            filter_sequence: Vec::new(),
+        };
+
+        let mut file = ProfileFile::default();
+        file.cameras.insert("QHY5III462C-1234".to_string(), all_sky);
+        file.cameras.insert("QHY174M".to_string(), minimal);
+        file
+    }
+
+    fn round_trip(extension: &str) {
+        let path = std::env::temp_dir().join(format!("qhyccd_profile_round_trip_{}.{}", std::process::id(), extension));
+        let file = test_file();
+        file.save(&path).unwrap();
+        let loaded = ProfileFile::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&file).unwrap(), "{}", extension);
+        let profile = &loaded.cameras["QHY5III462C-1234"];
+        assert_eq!(profile.params.roi, CameraArea { start_x: 16, start_y: 8, width: 1920, height: 1080 });
+        assert_eq!(profile.params.ddr_buffer, Some(true));
+        assert_eq!(profile.capture_profiles[0].pattern_noise, PatternNoiseMode::Rows);
+        assert_eq!(profile.filter_sequence[0].exposure, Some(60_000_000));
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        round_trip("toml");
+    }
+
+    #[test]
+    fn json_round_trip() {
+        round_trip("json");
+    }
+
+    #[test]
+    fn unknown_extensions_are_rejected() {
+        let path = std::env::temp_dir().join("qhyccd_profile.yaml");
+        assert!(matches!(ProfileFormat::from_path(&path), Err(ProfileError::Parse(_))));
+        assert!(matches!(test_file().save(&path), Err(ProfileError::Parse(_))));
+        assert_eq!(ProfileFormat::from_path(Path::new("station.TOML")).unwrap(), ProfileFormat::Toml);
+    }
+
+    #[test]
+    fn missing_params_take_the_defaults() {
+        let file = ProfileFile::parse("[cameras.QHY174M.params]\ngain = 5\n", ProfileFormat::Toml).unwrap();
+        let params = &file.cameras["QHY174M"].params;
+        assert_eq!(params.gain, 5);
+        assert_eq!(params.exposure, CameraParams::default().exposure);
+        assert!(file.cameras["QHY174M"].capture_profiles.is_empty());
+    }
+
+    #[test]
+    fn finds_the_most_specific_profile() {
+        let file = test_file();
+        let mut info = CameraInfo { serial_num: "QHY5III462C-1234".to_string(), id: "QHY174M-abcd".to_string(), model: "QHY174M".to_string(), ..CameraInfo::default() };
+        assert_eq!(file.find(&info).map(|profile| profile.description.as_str()), Some("all-sky camera"));
+        info.serial_num = "other".to_string();
+        assert_eq!(file.find(&info).map(|profile| profile.description.as_str()), Some(""));
+        info.model = "QHY600M".to_string();
+        assert!(file.find(&info).is_none());
+    }
+}
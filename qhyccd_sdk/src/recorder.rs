@@ -0,0 +1,502 @@
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use serde::{Deserialize, Serialize};
+use crate::archive::{self, ArchiveError, ArchiveOptions, ArchiveReader, ArchiveWriter};
+use crate::camera::Camera;
+use crate::frame::Frame;
+
+const SEGMENT_PREFIX: &str = "segment_";
+const PINS_FILE: &str = "pins.json";
+
+#[derive(Debug)]
+pub enum RecorderError {
+    Io(io::Error),
+    Archive(ArchiveError),
+}
+
+#[derive(Debug, Clone)]
+pub struct RecorderConfig {
+    pub dir: PathBuf,
+    // A new segment is started when the current one spans this long or grows this big,
+    // eviction works on whole segments
+    pub segment_duration: Duration,
+    pub segment_max_bytes: u64,
+    pub max_age: Option<Duration>,
+    pub max_bytes: Option<u64>,
+    // Oldest unpinned segments are evicted while the disk has less free space than this
+    pub min_free_bytes: u64,
+    // Below this nothing is written, frames are dropped until space is back
+    pub critical_free_bytes: u64,
+    pub retention_interval: Duration,
+    // A pin is dropped once its end is older than this, None keeps pins until they are unpinned
+    pub pin_max_age: Option<Duration>,
+    pub archive: ArchiveOptions,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PinnedRange {
+    pub id: u64,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub label: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub path: PathBuf,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub size: u64,
+    pub frame_count: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecorderStats {
+    pub segments: usize,
+    pub total_bytes: u64,
+    pub frames_written: u64,
+    pub frames_dropped: u64,
+    pub segments_evicted: u64,
+    pub free_bytes: Option<u64>,
+    pub is_disk_low: bool,
+}
+
+// Continuous recording into a directory of archive segments, the oldest segments are evicted by
+// age, total size and free disk space unless they overlap a pinned range. Pins are kept in the
+// directory and survive restarts until they expire; a pin may reach into the future to keep what is
+// still to come.
+pub struct RollingRecorder {
+    config: RecorderConfig,
+    segments: Vec<Segment>,
+    current: Option<ArchiveWriter>,
+    pins: Vec<PinnedRange>,
+    next_pin_id: u64,
+    last_retention: Option<Instant>,
+    stats: RecorderStats,
+}
+
+impl RollingRecorder {
+    // Picks up the segments already in the directory, recording always starts a new segment
+    pub fn open(config: RecorderConfig) -> Result<Self, RecorderError> {
+        fs::create_dir_all(&config.dir).map_err(RecorderError::Io)?;
+
+        let mut segments = Vec::new();
+        for dir_entry in fs::read_dir(&config.dir).map_err(RecorderError::Io)? {
+            let path = dir_entry.map_err(RecorderError::Io)?.path();
+            let is_segment = path.extension().is_some_and(|extension| extension == "qra")
+                && path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(SEGMENT_PREFIX));
+            if !is_segment {
+                continue;
+            }
+            match read_segment(&path) {
+                Ok(Some(segment)) => segments.push(segment),
+                Ok(None) => remove_segment(&path),
+                Err(err) => {
+                    eprintln!("Unreadable segment: {}, error: {}", path.display(), err);
+                    segments.push(unreadable_segment(&path).map_err(RecorderError::Io)?);
+                },
+            }
+        }
+        segments.sort_by_key(|segment| segment.start);
+
+        let pins = load_pins(&config.dir)?;
+        let next_pin_id = pins.iter().map(|pin| pin.id + 1).max().unwrap_or(1);
+        let stats = RecorderStats { segments: segments.len(), total_bytes: segments.iter().map(|segment| segment.size).sum(), ..RecorderStats::default() };
+
+        Ok(RollingRecorder { config, segments, current: None, pins, next_pin_id, last_retention: None, stats })
+    }
+
+    pub fn get_config(&self) -> &RecorderConfig {
+        &self.config
+    }
+
+    pub fn get_segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn get_stats(&self) -> RecorderStats {
+        self.stats.clone()
+    }
+
+    // Returns false when the frame was dropped because the disk is nearly full
+    pub fn record(&mut self, frame: &Frame) -> Result<bool, RecorderError> {
+        // Rate limited even while the disk is low, statvfs and the scan are not done for every frame
+        let is_due = self.last_retention.map_or(true, |last| last.elapsed() >= self.config.retention_interval);
+        if is_due {
+            self.enforce_retention(SystemTime::now())?;
+        }
+        if self.stats.free_bytes.is_some_and(|free| free < self.config.critical_free_bytes) {
+            self.stats.frames_dropped += 1;
+            return Ok(false)
+        }
+
+        let needs_rotation = match (&self.current, self.segments.last()) {
+            (Some(_), Some(segment)) => {
+                let span = segment.end.duration_since(segment.start).unwrap_or_default();
+                span >= self.config.segment_duration || segment.size >= self.config.segment_max_bytes
+            },
+            _ => true,
+        };
+        if needs_rotation {
+            self.current = None;
+            let path = self.config.dir.join(format!("{}{}", SEGMENT_PREFIX, archive::to_micros(SystemTime::now())));
+            let writer = ArchiveWriter::open(&path, self.config.archive.clone()).map_err(RecorderError::Archive)?;
+            self.segments.push(Segment { path: writer.get_path().to_path_buf(), start: SystemTime::now(), end: SystemTime::now(), size: 0, frame_count: 0 });
+            self.current = Some(writer);
+            self.stats.segments = self.segments.len();
+        }
+
+        let writer = self.current.as_mut().unwrap();
+        let entry = writer.append(frame).map_err(RecorderError::Archive)?;
+        let segment = self.segments.last_mut().unwrap();
+        if segment.frame_count == 0 {
+            segment.start = entry.get_time();
+        }
+        segment.end = entry.get_time().max(segment.start);
+        segment.size = writer.get_size();
+        segment.frame_count += 1;
+        self.stats.total_bytes += entry.record_len;
+        self.stats.frames_written += 1;
+
+        Ok(true)
+    }
+
+    // Records frames from the camera until on_frame returns false or no frame arrives
+    pub fn run<F>(&mut self, camera: &mut Camera, mut on_frame: F) -> Result<(), RecorderError>
+        where F: FnMut(&Frame, bool) -> bool {
+        loop {
+            let has_frame = camera.get_raw_frame();
+            if has_frame.is_none() {
+                eprintln!("No frame received, recording stopped");
+                break;
+            }
+            let frame = has_frame.unwrap();
+            let is_recorded = self.record(&frame)?;
+            if !on_frame(&frame, is_recorded) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Drops expired pins and evicts the oldest unpinned segments over the limits, the one being written is never evicted.
+    // Returns the evicted segments.
+    pub fn enforce_retention(&mut self, now: SystemTime) -> Result<Vec<PathBuf>, RecorderError> {
+        self.last_retention = Some(Instant::now());
+        if let Some(pin_max_age) = self.config.pin_max_age {
+            let count = self.pins.len();
+            self.pins.retain(|pin| now.duration_since(pin.end).unwrap_or_default() <= pin_max_age);
+            if self.pins.len() != count {
+                save_pins(&self.config.dir, &self.pins)?;
+            }
+        }
+        let mut free_bytes = free_space(&self.config.dir).ok();
+        let mut total_bytes: u64 = self.segments.iter().map(|segment| segment.size).sum();
+        let closed_count = if self.current.is_some() { self.segments.len().saturating_sub(1) } else { self.segments.len() };
+
+        let mut evicted = Vec::new();
+        let mut kept = Vec::with_capacity(self.segments.len());
+        for (index, segment) in self.segments.drain(..).enumerate() {
+            let is_too_old = self.config.max_age.is_some_and(|max_age| now.duration_since(segment.end).unwrap_or_default() > max_age);
+            let is_over_size = self.config.max_bytes.is_some_and(|max_bytes| total_bytes > max_bytes);
+            let is_disk_low = free_bytes.is_some_and(|free| free < self.config.min_free_bytes);
+            let is_pinned = self.pins.iter().any(|pin| segment.start <= pin.end && segment.end >= pin.start);
+            if index >= closed_count || is_pinned || !(is_too_old || is_over_size || is_disk_low) {
+                kept.push(segment);
+                continue;
+            }
+
+            remove_segment(&segment.path);
+            total_bytes -= segment.size;
+            free_bytes = free_bytes.map(|free| free + segment.size);
+            self.stats.segments_evicted += 1;
+            evicted.push(segment.path);
+        }
+        self.segments = kept;
+
+        // Measured again, the estimate above ignores index files and other writers on the disk
+        if !evicted.is_empty() {
+            free_bytes = free_space(&self.config.dir).ok();
+        }
+        let is_disk_low = free_bytes.is_some_and(|free| free < self.config.min_free_bytes);
+        if is_disk_low && !self.stats.is_disk_low {
+            eprintln!("Low disk space for recording: {} MB free, only pinned segments left to evict", free_bytes.unwrap_or(0) / 1_000_000);
+        }
+        self.stats.is_disk_low = is_disk_low;
+        self.stats.free_bytes = free_bytes;
+        self.stats.total_bytes = total_bytes;
+        self.stats.segments = self.segments.len();
+
+        Ok(evicted)
+    }
+
+    pub fn pin(&mut self, start: SystemTime, end: SystemTime, label: &str) -> Result<u64, RecorderError> {
+        let id = self.next_pin_id;
+        self.next_pin_id += 1;
+        self.pins.push(PinnedRange { id, start: start.min(end), end: end.max(start), label: label.to_string() });
+        save_pins(&self.config.dir, &self.pins)?;
+        Ok(id)
+    }
+
+    // Pins around an event, e.g. a detection with a few minutes of context on both sides
+    pub fn pin_around(&mut self, time: SystemTime, before: Duration, after: Duration, label: &str) -> Result<u64, RecorderError> {
+        self.pin(time - before, time + after, label)
+    }
+
+    pub fn unpin(&mut self, id: u64) -> Result<bool, RecorderError> {
+        let count = self.pins.len();
+        self.pins.retain(|pin| pin.id != id);
+        if self.pins.len() == count {
+            return Ok(false)
+        }
+        save_pins(&self.config.dir, &self.pins)?;
+        Ok(true)
+    }
+
+    pub fn get_pins(&self) -> &[PinnedRange] {
+        &self.pins
+    }
+
+    // Segments holding frames between start and end, oldest first
+    pub fn find_segments(&self, start: SystemTime, end: SystemTime) -> Vec<&Segment> {
+        self.segments.iter().filter(|segment| segment.frame_count > 0 && segment.start <= end && segment.end >= start).collect()
+    }
+
+    pub fn read_range(&mut self, start: SystemTime, end: SystemTime) -> Result<Vec<Frame>, RecorderError> {
+        if let Some(writer) = self.current.as_mut() {
+            writer.sync().map_err(RecorderError::Archive)?;
+        }
+        let mut frames = Vec::new();
+        for segment in self.find_segments(start, end) {
+            let mut reader = ArchiveReader::open(&segment.path).map_err(RecorderError::Archive)?;
+            for index in reader.find_range(start, end) {
+                frames.push(reader.read_frame(index).map_err(RecorderError::Archive)?);
+            }
+        }
+        Ok(frames)
+    }
+}
+
+impl fmt::Display for RecorderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecorderError::Io(err) => write!(f, "Recorder I/O error: {}", err),
+            RecorderError::Archive(err) => write!(f, "Recorder {}", err),
+        }
+    }
+}
+
+impl fmt::Display for RecorderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "segments: {}, stored: {} MB, written: {}, dropped: {}, evicted: {}",
+            self.segments, self.total_bytes / 1_000_000, self.frames_written, self.frames_dropped, self.segments_evicted)?;
+        if let Some(free_bytes) = self.free_bytes {
+            write!(f, ", free: {} MB", free_bytes / 1_000_000)?;
+        }
+        if self.is_disk_low {
+            write!(f, " (low)")?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        RecorderConfig {
+            dir: PathBuf::from("recording"),
+            segment_duration: Duration::from_secs(60),
+            segment_max_bytes: 512 * 1_000_000,
+            max_age: Some(Duration::from_secs(3600)),
+            max_bytes: None,
+            min_free_bytes: 2_000_000_000,
+            critical_free_bytes: 500_000_000,
+            retention_interval: Duration::from_secs(5),
+            pin_max_age: Some(Duration::from_secs(7 * 24 * 3600)),
+            archive: ArchiveOptions::default(),
+        }
+    }
+}
+
+// Space available to unprivileged users on the file system holding the path
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+fn read_segment(path: &Path) -> Result<Option<Segment>, ArchiveError> {
+    let reader = ArchiveReader::open(path)?;
+    let entries = reader.get_entries();
+    if entries.is_empty() {
+        return Ok(None)
+    }
+    Ok(Some(Segment {
+        path: path.to_path_buf(),
+        start: entries[0].get_time(),
+        end: entries[entries.len() - 1].get_time(),
+        size: entries.iter().map(|entry| entry.record_len).sum(),
+        frame_count: entries.len(),
+    }))
+}
+
+// Kept without frames so it still counts against the quota with its size on disk and is evicted
+// by age from its modification time
+fn unreadable_segment(path: &Path) -> io::Result<Segment> {
+    let metadata = fs::metadata(path)?;
+    let index_size = fs::metadata(archive::index_path(path)).map_or(0, |metadata| metadata.len());
+    let modified = metadata.modified()?;
+    Ok(Segment { path: path.to_path_buf(), start: modified, end: modified, size: metadata.len() + index_size, frame_count: 0 })
+}
+
+fn remove_segment(path: &Path) {
+    for file in [path.to_path_buf(), archive::index_path(path)] {
+        if let Err(err) = fs::remove_file(&file) {
+            if err.kind() != io::ErrorKind::NotFound {
+                eprintln!("Failed to remove segment file: {}, error: {}", file.display(), err);
+            }
+        }
+    }
+}
+
+fn load_pins(dir: &Path) -> Result<Vec<PinnedRange>, RecorderError> {
+    let path = dir.join(PINS_FILE);
+    if !path.exists() {
+        return Ok(Vec::new())
+    }
+    let content = fs::read_to_string(path).map_err(RecorderError::Io)?;
+    serde_json::from_str(&content).map_err(|err| RecorderError::Io(io::Error::new(io::ErrorKind::InvalidData, err)))
+}
+
+// Written to a temporary file first, a crash never leaves a half written pin list behind
+fn save_pins(dir: &Path, pins: &[PinnedRange]) -> Result<(), RecorderError> {
+    let content = serde_json::to_string_pretty(pins).map_err(|err| RecorderError::Io(io::Error::new(io::ErrorKind::InvalidData, err)))?;
+    let temp_path = dir.join(format!("{}.tmp", PINS_FILE));
+    fs::write(&temp_path, content).map_err(RecorderError::Io)?;
+    fs::rename(temp_path, dir.join(PINS_FILE)).map_err(RecorderError::Io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn test_config(name: &str) -> RecorderConfig {
+        let dir = std::env::temp_dir().join(format!("qhyccd_recorder_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        RecorderConfig { dir, max_age: None, min_free_bytes: 0, critical_free_bytes: 0, ..RecorderConfig::default() }
+    }
+
+    // Segments of `size` bytes ending the given number of hours before now, oldest first
+    fn with_fake_segments(config: RecorderConfig, hours_ago: &[u64], size: u64, now: SystemTime) -> RollingRecorder {
+        let mut recorder = RollingRecorder::open(config).unwrap();
+        for (index, hours) in hours_ago.iter().enumerate() {
+            let path = recorder.config.dir.join(format!("{}{}.qra", SEGMENT_PREFIX, index));
+            fs::write(&path, vec![0u8; size as usize]).unwrap();
+            let end = now - HOUR * *hours as u32;
+            recorder.segments.push(Segment { path, start: end - Duration::from_secs(60), end, size, frame_count: 1 });
+        }
+        recorder
+    }
+
+    fn segment_names(recorder: &RollingRecorder) -> Vec<String> {
+        recorder.get_segments().iter().map(|segment| segment.path.file_stem().unwrap().to_string_lossy().to_string()).collect()
+    }
+
+    #[test]
+    fn evicts_oldest_over_quota() {
+        let now = SystemTime::now();
+        let config = RecorderConfig { max_bytes: Some(250), ..test_config("quota") };
+        let mut recorder = with_fake_segments(config, &[4, 3, 2, 1], 100, now);
+        let evicted = recorder.enforce_retention(now).unwrap();
+
+        assert_eq!(evicted.len(), 2);
+        assert!(evicted.iter().all(|path| !path.exists()));
+        assert_eq!(segment_names(&recorder), ["segment_2", "segment_3"]);
+        assert_eq!(recorder.get_stats().total_bytes, 200);
+        assert_eq!(recorder.get_stats().segments_evicted, 2);
+        fs::remove_dir_all(&recorder.config.dir).unwrap();
+    }
+
+    #[test]
+    fn evicts_by_age() {
+        let now = SystemTime::now();
+        let config = RecorderConfig { max_age: Some(HOUR * 2), ..test_config("age") };
+        let mut recorder = with_fake_segments(config, &[5, 3, 1, 0], 100, now);
+        recorder.enforce_retention(now).unwrap();
+
+        assert_eq!(segment_names(&recorder), ["segment_2", "segment_3"]);
+        fs::remove_dir_all(&recorder.config.dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_pinned_segments() {
+        let now = SystemTime::now();
+        let config = RecorderConfig { max_bytes: Some(250), ..test_config("pins") };
+        let mut recorder = with_fake_segments(config, &[4, 3, 2, 1], 100, now);
+        let id = recorder.pin_around(now - HOUR * 4, Duration::from_secs(10), Duration::from_secs(10), "meteor").unwrap();
+        recorder.enforce_retention(now).unwrap();
+
+        // The pinned segment stays and still counts, so one more newer segment is evicted instead
+        assert_eq!(segment_names(&recorder), ["segment_0", "segment_3"]);
+
+        // Pins survive a restart
+        let reopened = RollingRecorder::open(recorder.config.clone()).unwrap();
+        assert_eq!(reopened.get_pins().len(), 1);
+        assert_eq!(reopened.get_pins()[0].id, id);
+
+        assert!(recorder.unpin(id).unwrap());
+        assert!(!recorder.unpin(id).unwrap());
+        recorder.config.max_bytes = Some(150);
+        recorder.enforce_retention(now).unwrap();
+        assert_eq!(segment_names(&recorder), ["segment_3"]);
+        fs::remove_dir_all(&recorder.config.dir).unwrap();
+    }
+
+    #[test]
+    fn expires_old_pins() {
+        let now = SystemTime::now();
+        let config = RecorderConfig { pin_max_age: Some(HOUR * 24), ..test_config("pin_expiry") };
+        let mut recorder = RollingRecorder::open(config).unwrap();
+        recorder.pin(now - HOUR * 50, now - HOUR * 48, "old").unwrap();
+        let kept = recorder.pin(now - HOUR * 2, now - HOUR, "recent").unwrap();
+        recorder.enforce_retention(now).unwrap();
+
+        assert_eq!(recorder.get_pins().iter().map(|pin| pin.id).collect::<Vec<_>>(), [kept]);
+        let reopened = RollingRecorder::open(recorder.config.clone()).unwrap();
+        assert_eq!(reopened.get_pins().len(), 1);
+        fs::remove_dir_all(&recorder.config.dir).unwrap();
+    }
+
+    #[test]
+    fn counts_unreadable_segments() {
+        let config = RecorderConfig { max_bytes: Some(500), ..test_config("unreadable") };
+        fs::create_dir_all(&config.dir).unwrap();
+        let path = config.dir.join(format!("{}1.qra", SEGMENT_PREFIX));
+        fs::write(&path, vec![0u8; 1000]).unwrap();
+        // An index that cannot be read makes the whole segment unreadable
+        fs::create_dir(archive::index_path(&path)).unwrap();
+
+        let mut recorder = RollingRecorder::open(config).unwrap();
+        assert_eq!(recorder.get_segments().len(), 1);
+        assert_eq!(recorder.get_segments()[0].frame_count, 0);
+        assert!(recorder.get_stats().total_bytes >= 1000);
+        assert!(recorder.find_segments(UNIX_EPOCH, SystemTime::now() + HOUR).is_empty());
+
+        recorder.enforce_retention(SystemTime::now()).unwrap();
+        assert!(recorder.get_segments().is_empty());
+        assert!(!path.exists());
+        fs::remove_dir_all(&recorder.config.dir).unwrap();
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::SystemTime;
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use crate::astro::{self, SiteLocation};
+use crate::camera::BinMode;
+use crate::pattern_noise::PatternNoiseMode;
+use crate::sdk::StreamMode;
+
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SunPhase {
+    Day,
+    CivilTwilight,
+    NauticalTwilight,
+    AstronomicalTwilight,
+    Night,
+}
+
+impl SunPhase {
+    pub fn from_sun_altitude(altitude: f64) -> SunPhase {
+        if altitude > -0.833 {
+            SunPhase::Day
+        } else if altitude > -6.0 {
+            SunPhase::CivilTwilight
+        } else if altitude > -12.0 {
+            SunPhase::NauticalTwilight
+        } else if altitude > -18.0 {
+            SunPhase::AstronomicalTwilight
+        } else {
+            SunPhase::Night
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaptureProfile {
+    pub name: String,
+    pub exposure: u32,
+    pub gain: u32,
+    pub bin_mode: BinMode,
+    pub bpp: u32,
+    pub read_mode: u32,
+    pub debayer: bool,
+    pub stream_mode: StreamMode,
+    #[serde(default)]
+    pub pattern_noise: PatternNoiseMode,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SkyState {
+    pub sun_altitude: f64,
+    pub moon_altitude: f64,
+    pub moon_illumination: f64,
+}
+
+pub struct DayNightScheduler {
+    site: SiteLocation,
+    profiles: HashMap<String, CaptureProfile>,
+    phase_profiles: HashMap<SunPhase, String>,
+    hysteresis: f64,
+    current_phase: Option<SunPhase>,
+    active_profile: Option<String>,
+    last_sky: SkyState,
+
+    is_debug_info: bool,
+}
+
+impl DayNightScheduler {
+    pub fn new(site: SiteLocation) -> Self {
+        DayNightScheduler {
+            site,
+            profiles: HashMap::new(),
+            phase_profiles: HashMap::new(),
+            hysteresis: 0.25,
+            current_phase: None,
+            active_profile: None,
+            last_sky: SkyState::default(),
+            is_debug_info: false,
+        }
+    }
+
+    pub fn add_profile(&mut self, profile: CaptureProfile) {
+        self.profiles.insert(profile.name.clone(), profile);
+    }
+
+    pub fn assign_profile(&mut self, phase: SunPhase, profile_name: &str) -> bool {
+        if !self.profiles.contains_key(profile_name) {
+            eprintln!("Unknown capture profile: {}", profile_name);
+            return false
+        }
+        self.phase_profiles.insert(phase, profile_name.to_string());
+
+        true
+    }
+
+    pub fn set_hysteresis(&mut self, degrees: f64) {
+        self.hysteresis = degrees.abs();
+    }
+
+    pub fn set_debug_info(&mut self, enable: bool) {
+        self.is_debug_info = enable;
+    }
+
+    pub fn get_site(&self) -> &SiteLocation {
+        &self.site
+    }
+
+    pub fn get_sky_state(&self) -> SkyState {
+        self.last_sky
+    }
+
+    pub fn get_current_phase(&self) -> Option<SunPhase> {
+        self.current_phase
+    }
+
+    pub fn get_active_profile(&self) -> Option<&CaptureProfile> {
+        self.active_profile.as_ref().and_then(|name| self.profiles.get(name))
+    }
+
+    pub fn compute_sky_state(&self, time: SystemTime) -> SkyState {
+        SkyState {
+            sun_altitude: astro::sun_altitude(&self.site, time),
+            moon_altitude: astro::moon_altitude(&self.site, time),
+            moon_illumination: astro::moon_illumination(time),
+        }
+    }
+
+    // Returns the profile to apply when the twilight phase changed since the last call, None otherwise
+    pub fn update(&mut self, time: SystemTime) -> Option<CaptureProfile> {
+        let sky = self.compute_sky_state(time);
+        self.last_sky = sky;
+
+        let phase = self.resolve_phase(sky.sun_altitude);
+        if self.current_phase == Some(phase) {
+            return None
+        }
+        let previous_phase = self.current_phase.replace(phase);
+        let transition = format!("{} -> {}, {}", previous_phase.map_or("none".to_string(), |p| p.to_string()), phase, sky);
+
+        let profile_name = match self.phase_profiles.get(&phase) {
+            Some(name) if self.active_profile.as_ref() != Some(name) => name.clone(),
+            _ => {
+                // Phase changes that keep the profile are only worth a debug line, switches are always logged
+                if self.is_debug_info {
+                    println!("Capture schedule: {}, keeping profile '{}'", transition, self.active_profile.as_deref().unwrap_or("none"));
+                }
+                return None
+            },
+        };
+        let profile = self.profiles.get(&profile_name)?.clone();
+
+        println!("Capture schedule: {}, switching profile '{}' -> '{}'", transition, self.active_profile.as_deref().unwrap_or("none"), profile_name);
+        self.active_profile = Some(profile_name);
+
+        Some(profile)
+    }
+
+    fn resolve_phase(&self, sun_altitude: f64) -> SunPhase {
+        let phase = SunPhase::from_sun_altitude(sun_altitude);
+        match self.current_phase {
+            Some(current) if current != phase => {
+                // Only leave the current phase once the sun is clearly past the boundary
+                let above = SunPhase::from_sun_altitude(sun_altitude + self.hysteresis);
+                let below = SunPhase::from_sun_altitude(sun_altitude - self.hysteresis);
+                if above == phase && below == phase { phase } else { current }
+            },
+            _ => phase,
+        }
+    }
+}
+
+impl fmt::Display for SkyState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sun altitude: {:.2} deg, moon altitude: {:.2} deg ({:.0}% lit)",
+            self.sun_altitude,
+            self.moon_altitude,
+            self.moon_illumination * 100.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GREENWICH: SiteLocation = SiteLocation { latitude: 51.4769, longitude: 0.0, elevation: 0.0 };
+
+    fn test_profile(name: &str, exposure: u32) -> CaptureProfile {
+        CaptureProfile {
+            name: name.to_string(),
+            exposure,
+            gain: 0,
+            bin_mode: BinMode::Bin1x1,
+            bpp: 16,
+            read_mode: 0,
+            debayer: false,
+            stream_mode: StreamMode::SingleFrame,
+            pattern_noise: PatternNoiseMode::Off,
+        }
+    }
+
+    fn parse(text: &str) -> SystemTime {
+        astro::parse_utc(text).unwrap()
+    }
+
+    #[test]
+    fn phases_from_sun_altitude() {
+        assert_eq!(SunPhase::from_sun_altitude(10.0), SunPhase::Day);
+        assert_eq!(SunPhase::from_sun_altitude(-3.0), SunPhase::CivilTwilight);
+        assert_eq!(SunPhase::from_sun_altitude(-9.0), SunPhase::NauticalTwilight);
+        assert_eq!(SunPhase::from_sun_altitude(-15.0), SunPhase::AstronomicalTwilight);
+        assert_eq!(SunPhase::from_sun_altitude(-30.0), SunPhase::Night);
+    }
+
+    #[test]
+    fn hysteresis_around_each_threshold() {
+        let boundaries = [
+            (SunPhase::Day, SunPhase::CivilTwilight, -0.833),
+            (SunPhase::CivilTwilight, SunPhase::NauticalTwilight, -6.0),
+            (SunPhase::NauticalTwilight, SunPhase::AstronomicalTwilight, -12.0),
+            (SunPhase::AstronomicalTwilight, SunPhase::Night, -18.0),
+        ];
+        let mut scheduler = DayNightScheduler::new(GREENWICH);
+        for (upper, lower, threshold) in boundaries {
+            // Setting sun: the lower phase starts once the sun is a hysteresis below the threshold
+            scheduler.current_phase = Some(upper);
+            assert_eq!(scheduler.resolve_phase(threshold - 0.1), upper, "{}", threshold);
+            assert_eq!(scheduler.resolve_phase(threshold - 0.3), lower, "{}", threshold);
+            // Rising sun, the same margin above it
+            scheduler.current_phase = Some(lower);
+            assert_eq!(scheduler.resolve_phase(threshold + 0.1), lower, "{}", threshold);
+            assert_eq!(scheduler.resolve_phase(threshold + 0.3), upper, "{}", threshold);
+        }
+
+        // Without a current phase the altitude decides alone
+        scheduler.current_phase = None;
+        assert_eq!(scheduler.resolve_phase(-6.1), SunPhase::NauticalTwilight);
+        scheduler.set_hysteresis(-1.0);
+        scheduler.current_phase = Some(SunPhase::CivilTwilight);
+        assert_eq!(scheduler.resolve_phase(-6.5), SunPhase::CivilTwilight);
+    }
+
+    #[test]
+    fn switches_profiles_on_phase_changes() {
+        let mut scheduler = DayNightScheduler::new(GREENWICH);
+        scheduler.add_profile(test_profile("day", 100));
+        scheduler.add_profile(test_profile("night", 30_000_000));
+        assert!(scheduler.assign_profile(SunPhase::Day, "day"));
+        assert!(scheduler.assign_profile(SunPhase::Night, "night"));
+        assert!(!scheduler.assign_profile(SunPhase::CivilTwilight, "dusk"));
+
+        let noon = parse("2024-06-21T12:00:00");
+        assert_eq!(scheduler.update(noon).map(|profile| profile.name), Some("day".to_string()));
+        assert_eq!(scheduler.update(noon + std::time::Duration::from_secs(60)), None);
+        assert_eq!(scheduler.get_current_phase(), Some(SunPhase::Day));
+
+        // Civil twilight has no profile, the day profile stays
+        assert_eq!(scheduler.update(parse("2024-06-21T20:45:00")), None);
+        assert_eq!(scheduler.get_current_phase(), Some(SunPhase::CivilTwilight));
+        assert_eq!(scheduler.get_active_profile().map(|profile| profile.name.as_str()), Some("day"));
+
+        let midnight = parse("2024-12-21T00:00:00");
+        assert_eq!(scheduler.update(midnight).map(|profile| profile.exposure), Some(30_000_000));
+        assert!(scheduler.get_sky_state().sun_altitude < -18.0);
+    }
+}
@@ -3,12 +3,13 @@ mod c_bindings;
 
 use derive_more::Display;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
+use serde::{Deserialize, Serialize};
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
 pub struct QhyCcd {
 }
 
-#[derive(Display)]
+#[derive(Display, Debug, Clone, Copy, PartialEq)]
 pub enum CameraStatus {
     Idle,
     Waiting,
@@ -42,7 +43,7 @@ impl Default for SdkError {
 }
 
 #[repr(u32)]
-#[derive(Debug, Copy, Clone, PartialEq, IntoPrimitive, TryFromPrimitive, Display)]
+#[derive(Debug, Copy, Clone, PartialEq, IntoPrimitive, TryFromPrimitive, Display, Serialize, Deserialize)]
 pub enum BayerFormat {
     GB = 1,
     GR = 2,
@@ -58,19 +59,29 @@ impl Default for BayerFormat {
 }
 
 #[repr(u8)]
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum StreamMode {
     SingleFrame = 0,
     LiveFrame = 1,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ParamLimits {
     pub min: f64,
     pub max: f64,
     pub step: f64,
 }
 
+impl ParamLimits {
+    pub fn clamp(&self, value: f64) -> f64 {
+        let mut clamped = value.clamp(self.min, self.max);
+        if self.step > 0.0 {
+            clamped = self.min + ((clamped - self.min) / self.step).round() * self.step;
+        }
+        clamped.clamp(self.min, self.max)
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct ImageResult {
     pub width: u32,
@@ -90,7 +101,7 @@ pub struct ChipInfo {
     pub bpp: u32,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct CameraArea {
     pub start_x: u32,
     pub start_y: u32,
@@ -98,6 +109,8 @@ pub struct CameraArea {
     pub height: u32,
 }
 
+#[derive(Display, Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[display(fmt = "{}.{:02}.{:02}.{}", year, month, day, subday)]
 pub struct SdkVersion {
     pub year: u32,
     pub month: u32,
@@ -105,6 +118,14 @@ pub struct SdkVersion {
     pub subday: u32,
 }
 
+#[derive(Display, Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[display(fmt = "{}-{:02}-{:02}", year, month, day)]
+pub struct FirmwareVersion {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+}
+
 #[repr(u32)]
 #[derive(Display, Copy, Clone, IntoPrimitive, TryFromPrimitive)]
 pub enum ControlId {
@@ -280,6 +301,49 @@ impl QhyCcd {
         }
     }    
     
+    pub fn get_number_of_read_modes(handle: *mut c_bindings::QhyCcdHandle) -> Result<u32, SdkError> {
+        let mut num_modes: u32 = 0;
+        let ret = unsafe { c_bindings::GetQHYCCDNumberOfReadModes(handle, &mut num_modes as *mut u32) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok(num_modes),
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn get_read_mode_name(handle: *mut c_bindings::QhyCcdHandle, mode: u32) -> Result<String, SdkError> {
+        let mut name = vec![0 as c_char; 256]; // Assuming the maximum read mode name length is 256
+
+        let ret = unsafe { c_bindings::GetQHYCCDReadModeName(handle, mode, name.as_mut_ptr()) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => {
+                let c_str = unsafe { CStr::from_ptr(name.as_ptr()) };
+                c_str.to_str().map(|s| s.to_owned()).map_err(|_| error_result)
+            },
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn set_read_mode(handle: *mut c_bindings::QhyCcdHandle, mode: u32) -> Result<(), SdkError> {
+        let ret = unsafe { c_bindings::SetQHYCCDReadMode(handle, mode) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok(()),
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn get_read_mode(handle: *mut c_bindings::QhyCcdHandle) -> Result<u32, SdkError> {
+        let mut mode: u32 = 0;
+        let ret = unsafe { c_bindings::GetQHYCCDReadMode(handle, &mut mode as *mut u32) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok(mode),
+            _ => Err(error_result)
+        }
+    }
+
     pub fn set_stream_mode(handle: *mut c_bindings::QhyCcdHandle, mode: &StreamMode) -> Result<(), SdkError> {
         let ret = unsafe { c_bindings::SetQHYCCDStreamMode(handle, *mode as u8) };
         let error_result = SdkError::try_from(ret).unwrap_or_default();
@@ -601,6 +665,175 @@ impl QhyCcd {
         }
     }
 
+    pub fn set_trigger_mode(handle: *mut c_bindings::QhyCcdHandle, mode: u32) -> Result<(), SdkError> {
+        let ret = unsafe { c_bindings::SetQHYCCDTrigerMode(handle, mode) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok(()),
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn set_trigger_function(handle: *mut c_bindings::QhyCcdHandle, enable: bool) -> Result<(), SdkError> {
+        let ret = unsafe { c_bindings::SetQHYCCDTrigerFunction(handle, enable) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok(()),
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn enable_trigger_out(handle: *mut c_bindings::QhyCcdHandle) -> Result<(), SdkError> {
+        let ret = unsafe { c_bindings::EnableQHYCCDTrigerOut(handle) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok(()),
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn send_soft_trigger(handle: *mut c_bindings::QhyCcdHandle) -> Result<(), SdkError> {
+        let ret = unsafe { c_bindings::SendSoftTriggerQHYCCD(handle) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok(()),
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn enable_burst_mode(handle: *mut c_bindings::QhyCcdHandle, enable: bool) -> Result<(), SdkError> {
+        let ret = unsafe { c_bindings::EnableQHYCCDBurstMode(handle, enable) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok(()),
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn set_burst_mode_start_end(handle: *mut c_bindings::QhyCcdHandle, start: u16, end: u16) -> Result<(), SdkError> {
+        let ret = unsafe { c_bindings::SetQHYCCDBurstModeStartEnd(handle, start, end) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok(()),
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn enable_burst_count_fun(handle: *mut c_bindings::QhyCcdHandle, enable: bool) -> Result<(), SdkError> {
+        let ret = unsafe { c_bindings::EnableQHYCCDBurstCountFun(handle, enable) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok(()),
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn reset_frame_counter(handle: *mut c_bindings::QhyCcdHandle) -> Result<(), SdkError> {
+        let ret = unsafe { c_bindings::ResetQHYCCDFrameCounter(handle) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok(()),
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn set_burst_idle(handle: *mut c_bindings::QhyCcdHandle) -> Result<(), SdkError> {
+        let ret = unsafe { c_bindings::SetQHYCCDBurstIDLE(handle) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok(()),
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn release_burst_idle(handle: *mut c_bindings::QhyCcdHandle) -> Result<(), SdkError> {
+        let ret = unsafe { c_bindings::ReleaseQHYCCDBurstIDLE(handle) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok(()),
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn set_burst_mode_patch_number(handle: *mut c_bindings::QhyCcdHandle, value: u32) -> Result<(), SdkError> {
+        let ret = unsafe { c_bindings::SetQHYCCDBurstModePatchNumber(handle, value) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok(()),
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn control_guide(handle: *mut c_bindings::QhyCcdHandle, direction: u32, duration: u16) -> Result<(), SdkError> {
+        let ret = unsafe { c_bindings::ControlQHYCCDGuide(handle, direction, duration) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok(()),
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn is_cfw_plugged(handle: *mut c_bindings::QhyCcdHandle) -> Result<bool, SdkError> {
+        let ret = unsafe { c_bindings::IsQHYCCDCFWPlugged(handle) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok(true),
+            SdkError::Error => Ok(false),
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn send_order_to_cfw(handle: *mut c_bindings::QhyCcdHandle, order: &str) -> Result<(), SdkError> {
+        let c_order = CString::new(order).unwrap();
+        let ret = unsafe { c_bindings::SendOrder2QHYCCDCFW(handle, c_order.as_ptr() as *mut c_char, order.len() as u32) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok(()),
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn get_cfw_status(handle: *mut c_bindings::QhyCcdHandle) -> Result<String, SdkError> {
+        let mut status = vec![0 as c_char; 64];
+        let ret = unsafe { c_bindings::GetQHYCCDCFWStatus(handle, status.as_mut_ptr()) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => {
+                let c_str = unsafe { CStr::from_ptr(status.as_ptr()) };
+                Ok(c_str.to_string_lossy().into_owned())
+            },
+            _ => Err(error_result)
+        }
+    }
+
+    // The year is packed into the high nibble, values up to 9 stand for 2016-2025
+    pub fn get_fw_version(handle: *mut c_bindings::QhyCcdHandle) -> Result<FirmwareVersion, SdkError> {
+        let mut buf = [0u8; 32];
+        let ret = unsafe { c_bindings::GetQHYCCDFWVersion(handle, buf.as_mut_ptr()) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => {
+                let year = (buf[0] >> 4) as u32;
+                Ok(FirmwareVersion {
+                    year: 2000 + if year <= 9 { year + 0x10 } else { year },
+                    month: (buf[0] & 0x0f) as u32,
+                    day: buf[1] as u32,
+                })
+            },
+            _ => Err(error_result)
+        }
+    }
+
+    pub fn get_fpga_version(handle: *mut c_bindings::QhyCcdHandle, fpga_index: u8) -> Result<[u8; 4], SdkError> {
+        let mut buf = [0u8; 32];
+        let ret = unsafe { c_bindings::GetQHYCCDFPGAVersion(handle, fpga_index, buf.as_mut_ptr()) };
+        let error_result = SdkError::try_from(ret).unwrap_or_default();
+        match error_result {
+            SdkError::Success => Ok([buf[0], buf[1], buf[2], buf[3]]),
+            _ => Err(error_result)
+        }
+    }
+
     pub fn get_sdk_version() -> Result<SdkVersion, SdkError> {
         let mut year: u32 = 0;
         let mut month: u32 = 0;
@@ -0,0 +1,568 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::camera::Camera;
+use crate::frame::Frame;
+use crate::sdk::BayerFormat;
+
+const FILE_ID: &[u8; 14] = b"LUCAM-RECORDER";
+const HEADER_LEN: u64 = 178;
+const TEXT_LEN: usize = 40;
+// SER timestamps count 100 ns ticks from 0001-01-01, the Unix epoch is this many ticks later
+const UNIX_EPOCH_TICKS: i64 = 621_355_968_000_000_000;
+
+#[derive(Debug)]
+pub enum SerError {
+    Io(io::Error),
+    Format(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerColorId {
+    Mono = 0,
+    BayerRggb = 8,
+    BayerGrbg = 9,
+    BayerGbrg = 10,
+    BayerBggr = 11,
+    Rgb = 100,
+    Bgr = 101,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SerOptions {
+    pub observer: String,
+    pub instrument: String,
+    pub telescope: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SerHeader {
+    pub color_id: SerColorId,
+    pub width: u32,
+    pub height: u32,
+    pub pixel_depth: u32,
+    pub frame_count: u32,
+    pub observer: String,
+    pub instrument: String,
+    pub telescope: String,
+    pub start_time: Option<SystemTime>,
+}
+
+// Frames are appended as they come, the header is written with the first frame and completed,
+// together with the timestamp trailer, by finish
+pub struct SerWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    options: SerOptions,
+    header: Option<SerHeader>,
+    timestamps: Vec<Option<SystemTime>>,
+    is_finished: bool,
+}
+
+pub struct SerReader {
+    file: File,
+    header: SerHeader,
+    timestamps: Vec<SystemTime>,
+    next_index: u32,
+}
+
+impl SerColorId {
+    pub fn from_frame(frame: &Frame) -> SerColorId {
+        if frame.channels == 3 {
+            // Debayered frames come from OpenCV in BGR order
+            return SerColorId::Bgr
+        }
+        match frame.metadata.bayer_format {
+            BayerFormat::Mono => SerColorId::Mono,
+            BayerFormat::RG => SerColorId::BayerRggb,
+            BayerFormat::GR => SerColorId::BayerGrbg,
+            BayerFormat::GB => SerColorId::BayerGbrg,
+            BayerFormat::BG => SerColorId::BayerBggr,
+        }
+    }
+
+    pub fn from_id(id: i32) -> Option<SerColorId> {
+        match id {
+            0 => Some(SerColorId::Mono),
+            8 => Some(SerColorId::BayerRggb),
+            9 => Some(SerColorId::BayerGrbg),
+            10 => Some(SerColorId::BayerGbrg),
+            11 => Some(SerColorId::BayerBggr),
+            100 => Some(SerColorId::Rgb),
+            101 => Some(SerColorId::Bgr),
+            _ => None,
+        }
+    }
+
+    pub fn get_channels(&self) -> u32 {
+        match self {
+            SerColorId::Rgb | SerColorId::Bgr => 3,
+            _ => 1,
+        }
+    }
+
+    pub fn get_bayer_format(&self) -> BayerFormat {
+        match self {
+            SerColorId::BayerRggb => BayerFormat::RG,
+            SerColorId::BayerGrbg => BayerFormat::GR,
+            SerColorId::BayerGbrg => BayerFormat::GB,
+            SerColorId::BayerBggr => BayerFormat::BG,
+            _ => BayerFormat::Mono,
+        }
+    }
+}
+
+impl SerHeader {
+    pub fn bytes_per_sample(&self) -> usize {
+        if self.pixel_depth > 8 { 2 } else { 1 }
+    }
+
+    pub fn frame_len(&self) -> usize {
+        self.width as usize * self.height as usize * self.color_id.get_channels() as usize * self.bytes_per_sample()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN as usize);
+        bytes.extend_from_slice(FILE_ID);
+        bytes.extend_from_slice(&0i32.to_le_bytes());
+        bytes.extend_from_slice(&(self.color_id as i32).to_le_bytes());
+        // The flag is inverted in practice: capture tools write 0 for little endian data and readers expect that
+        bytes.extend_from_slice(&0i32.to_le_bytes());
+        bytes.extend_from_slice(&(self.width as i32).to_le_bytes());
+        bytes.extend_from_slice(&(self.height as i32).to_le_bytes());
+        bytes.extend_from_slice(&(self.pixel_depth as i32).to_le_bytes());
+        bytes.extend_from_slice(&(self.frame_count as i32).to_le_bytes());
+        for text in [&self.observer, &self.instrument, &self.telescope] {
+            let mut field = [0u8; TEXT_LEN];
+            let ascii: Vec<u8> = text.bytes().filter(|c| c.is_ascii() && !c.is_ascii_control()).take(TEXT_LEN).collect();
+            field[..ascii.len()].copy_from_slice(&ascii);
+            bytes.extend_from_slice(&field);
+        }
+        // There is no time zone at hand, the local time field carries UTC as well
+        let ticks = self.start_time.map(to_ticks).unwrap_or(0);
+        bytes.extend_from_slice(&ticks.to_le_bytes());
+        bytes.extend_from_slice(&ticks.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<SerHeader, SerError> {
+        if bytes.len() < HEADER_LEN as usize || &bytes[..14] != FILE_ID {
+            return Err(SerError::Format("not a SER file".to_string()))
+        }
+        let get_i32 = |offset: usize| i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let get_text = |offset: usize| {
+            let field = &bytes[offset..offset + TEXT_LEN];
+            let len = field.iter().position(|c| *c == 0).unwrap_or(TEXT_LEN);
+            String::from_utf8_lossy(&field[..len]).trim_end().to_string()
+        };
+        let color_id = SerColorId::from_id(get_i32(18))
+            .ok_or_else(|| SerError::Format(format!("unsupported ColorID: {}", get_i32(18))))?;
+        let pixel_depth = get_i32(34);
+        if !(1..=16).contains(&pixel_depth) {
+            return Err(SerError::Format(format!("unsupported pixel depth: {}", pixel_depth)))
+        }
+        let ticks = i64::from_le_bytes(bytes[170..178].try_into().unwrap());
+
+        Ok(SerHeader {
+            color_id,
+            width: get_i32(26).max(0) as u32,
+            height: get_i32(30).max(0) as u32,
+            pixel_depth: pixel_depth as u32,
+            frame_count: get_i32(38).max(0) as u32,
+            observer: get_text(42),
+            instrument: get_text(82),
+            telescope: get_text(122),
+            start_time: if ticks > 0 { Some(from_ticks(ticks)) } else { None },
+        })
+    }
+}
+
+impl SerWriter {
+    pub fn create(path: &Path, options: SerOptions) -> Result<Self, SerError> {
+        let file = File::create(path).map_err(SerError::Io)?;
+        Ok(SerWriter {
+            path: path.to_path_buf(),
+            file: BufWriter::new(file),
+            options,
+            header: None,
+            timestamps: Vec::new(),
+            is_finished: false,
+        })
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get_frame_count(&self) -> u32 {
+        self.timestamps.len() as u32
+    }
+
+    // Every frame has to match the first one in size, depth and colour layout
+    pub fn write_frame(&mut self, frame: &Frame) -> Result<(), SerError> {
+        if self.is_finished {
+            return Err(SerError::Format("writer already finished".to_string()))
+        }
+        if frame.channels != 1 && frame.channels != 3 {
+            return Err(SerError::Format(format!("unsupported channel count: {}", frame.channels)))
+        }
+        let color_id = SerColorId::from_frame(frame);
+        let pixel_depth = if frame.bytes_per_sample() == 2 { 16 } else { 8 };
+        let timestamp = frame.timestamp();
+
+        match &self.header {
+            Some(header) => {
+                if header.width != frame.width || header.height != frame.height || header.color_id != color_id || header.pixel_depth != pixel_depth {
+                    return Err(SerError::Format(format!("frame {}x{} {:?} {}bit does not match the recording {}x{} {:?} {}bit",
+                        frame.width, frame.height, color_id, pixel_depth, header.width, header.height, header.color_id, header.pixel_depth)))
+                }
+            },
+            None => {
+                let instrument = if self.options.instrument.is_empty() { frame.metadata.model.clone() } else { self.options.instrument.clone() };
+                let header = SerHeader {
+                    color_id,
+                    width: frame.width,
+                    height: frame.height,
+                    pixel_depth,
+                    frame_count: 0,
+                    observer: self.options.observer.clone(),
+                    instrument,
+                    telescope: self.options.telescope.clone(),
+                    start_time: timestamp,
+                };
+                self.file.write_all(&header.to_bytes()).map_err(SerError::Io)?;
+                self.header = Some(header);
+            },
+        }
+
+        let len = frame.data_len().min(frame.data.len());
+        if frame.bytes_per_sample() == 2 {
+            let data: Vec<u8> = (0..len / 2).flat_map(|index| frame.get_sample(index).to_le_bytes()).collect();
+            self.file.write_all(&data).map_err(SerError::Io)?;
+        } else {
+            self.file.write_all(&frame.data[..len]).map_err(SerError::Io)?;
+        }
+        self.timestamps.push(timestamp);
+
+        Ok(())
+    }
+
+    // Reads frames from the camera until max_frames are written or on_frame returns false,
+    // returns the number of frames written
+    pub fn record<F>(&mut self, camera: &mut Camera, max_frames: u32, mut on_frame: F) -> Result<u32, SerError>
+        where F: FnMut(&Frame) -> bool {
+        let mut count = 0;
+        while count < max_frames {
+            let has_frame = camera.get_raw_frame();
+            if has_frame.is_none() {
+                eprintln!("No frame received, SER recording stopped after {} frames", count);
+                break;
+            }
+            let frame = has_frame.unwrap();
+            self.write_frame(&frame)?;
+            count += 1;
+            if !on_frame(&frame) {
+                break;
+            }
+        }
+
+        Ok(count)
+    }
+
+    // Writes the trailer, which is only valid when every frame had a timestamp, and the final frame count
+    pub fn finish(&mut self) -> Result<(), SerError> {
+        if self.is_finished {
+            return Ok(())
+        }
+        self.is_finished = true;
+        if let Some(header) = self.header.as_mut() {
+            if self.timestamps.iter().all(|timestamp| timestamp.is_some()) {
+                let trailer: Vec<u8> = self.timestamps.iter().flat_map(|timestamp| to_ticks(timestamp.unwrap()).to_le_bytes()).collect();
+                self.file.write_all(&trailer).map_err(SerError::Io)?;
+            }
+            header.frame_count = self.timestamps.len() as u32;
+            let header_bytes = header.to_bytes();
+            self.file.seek(SeekFrom::Start(0)).map_err(SerError::Io)?;
+            self.file.write_all(&header_bytes).map_err(SerError::Io)?;
+        }
+
+        self.file.flush().map_err(SerError::Io)
+    }
+}
+
+impl Drop for SerWriter {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            eprintln!("Failed to finish SER file: {}, error: {}", self.path.display(), err);
+        }
+    }
+}
+
+impl SerReader {
+    pub fn open(path: &Path) -> Result<Self, SerError> {
+        let mut file = File::open(path).map_err(SerError::Io)?;
+        let mut header_bytes = vec![0u8; HEADER_LEN as usize];
+        file.read_exact(&mut header_bytes).map_err(SerError::Io)?;
+        let header = SerHeader::from_bytes(&header_bytes)?;
+
+        // The trailer is optional, it is there when the file is long enough to hold it
+        let file_len = file.metadata().map_err(SerError::Io)?.len();
+        let trailer_start = HEADER_LEN + header.frame_len() as u64 * header.frame_count as u64;
+        if file_len < trailer_start {
+            return Err(SerError::Format(format!("file holds less than the {} frames in the header", header.frame_count)))
+        }
+        let mut timestamps = Vec::new();
+        if file_len >= trailer_start + 8 * header.frame_count as u64 {
+            let mut trailer = vec![0u8; 8 * header.frame_count as usize];
+            file.seek(SeekFrom::Start(trailer_start)).map_err(SerError::Io)?;
+            file.read_exact(&mut trailer).map_err(SerError::Io)?;
+            timestamps = trailer.chunks_exact(8).map(|ticks| from_ticks(i64::from_le_bytes(ticks.try_into().unwrap()))).collect();
+        }
+
+        Ok(SerReader { file, header, timestamps, next_index: 0 })
+    }
+
+    pub fn get_header(&self) -> &SerHeader {
+        &self.header
+    }
+
+    pub fn get_frame_count(&self) -> u32 {
+        self.header.frame_count
+    }
+
+    pub fn get_timestamps(&self) -> &[SystemTime] {
+        &self.timestamps
+    }
+
+    // Index of the first frame at or after the given time, needs the trailer
+    pub fn find_frame(&self, time: SystemTime) -> Option<u32> {
+        self.timestamps.iter().position(|timestamp| *timestamp >= time).map(|index| index as u32)
+    }
+
+    pub fn read_frame(&mut self, index: u32) -> Result<Frame, SerError> {
+        if index >= self.header.frame_count {
+            return Err(SerError::Format(format!("frame {} out of {}", index, self.header.frame_count)))
+        }
+        let frame_len = self.header.frame_len();
+        let mut data = vec![0u8; frame_len];
+        self.file.seek(SeekFrom::Start(HEADER_LEN + index as u64 * frame_len as u64)).map_err(SerError::Io)?;
+        self.file.read_exact(&mut data).map_err(SerError::Io)?;
+
+        let mut frame = Frame::new(self.header.width, self.header.height, self.header.bytes_per_sample() as u32 * 8, self.header.color_id.get_channels());
+        if frame.bytes_per_sample() == 2 {
+            for (index, sample) in data.chunks_exact(2).enumerate() {
+                frame.set_sample(index, u16::from_le_bytes([sample[0], sample[1]]));
+            }
+        } else {
+            frame.data = data;
+        }
+        if self.header.color_id == SerColorId::Rgb {
+            // Frames are handed out in the BGR order of camera frames
+            let bytes_per_sample = frame.bytes_per_sample();
+            for pixel in frame.data.chunks_exact_mut(3 * bytes_per_sample) {
+                for byte in 0..bytes_per_sample {
+                    pixel.swap(byte, 2 * bytes_per_sample + byte);
+                }
+            }
+        }
+
+        let metadata = &mut frame.metadata;
+        metadata.model = self.header.instrument.clone();
+        metadata.bayer_format = self.header.color_id.get_bayer_format();
+        metadata.params.bpp = frame.bpp;
+        metadata.params.channels = frame.channels;
+        metadata.exposure_start = self.timestamps.get(index as usize).cloned();
+
+        Ok(frame)
+    }
+
+    pub fn seek(&mut self, index: u32) {
+        self.next_index = index;
+    }
+}
+
+// Plays the recording back from the current position as a frame source
+impl Iterator for SerReader {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        if self.next_index >= self.header.frame_count {
+            return None
+        }
+        match self.read_frame(self.next_index) {
+            Ok(frame) => {
+                self.next_index += 1;
+                Some(frame)
+            },
+            Err(err) => {
+                eprintln!("Failed to read SER frame {}: {}", self.next_index, err);
+                None
+            },
+        }
+    }
+}
+
+impl fmt::Display for SerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SerError::Io(err) => write!(f, "SER I/O error: {}", err),
+            SerError::Format(message) => write!(f, "SER format error: {}", message),
+        }
+    }
+}
+
+fn to_ticks(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => UNIX_EPOCH_TICKS + (since.as_nanos() / 100) as i64,
+        Err(err) => UNIX_EPOCH_TICKS - (err.duration().as_nanos() / 100) as i64,
+    }
+}
+
+fn from_ticks(ticks: i64) -> SystemTime {
+    let since = ticks - UNIX_EPOCH_TICKS;
+    let duration = Duration::from_micros(since.unsigned_abs() / 10) + Duration::from_nanos(since.unsigned_abs() % 10 * 100);
+    if since >= 0 { UNIX_EPOCH + duration } else { UNIX_EPOCH - duration }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Whole 100 ns ticks, so the times survive the trailer unchanged
+    fn test_time(index: u32) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000) + Duration::from_nanos(123_456_700 + index as u64 * 40_000_000)
+    }
+
+    fn test_frame(bpp: u32, channels: u32, bayer_format: BayerFormat, index: u32) -> Frame {
+        let mut frame = Frame::new(7, 5, bpp, channels);
+        for sample in 0..frame.sample_count() {
+            frame.set_sample(sample, ((sample as u32 * 7919 + index * 31) % (frame.max_value() as u32 + 1)) as u16);
+        }
+        frame.metadata.bayer_format = bayer_format;
+        frame.metadata.exposure_start = Some(test_time(index));
+        frame
+    }
+
+    fn test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("qhyccd_ser_{}_{}.ser", name, std::process::id()))
+    }
+
+    fn write_frames(path: &Path, frames: &[Frame]) {
+        let options = SerOptions { observer: "observer".to_string(), instrument: "QHY5III462C".to_string(), telescope: "all-sky".to_string() };
+        let mut writer = SerWriter::create(path, options).unwrap();
+        for frame in frames {
+            writer.write_frame(frame).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn round_trip(name: &str, bpp: u32, channels: u32, bayer_format: BayerFormat, color_id: SerColorId) {
+        let path = test_path(name);
+        let frames: Vec<Frame> = (0..3).map(|index| test_frame(bpp, channels, bayer_format, index)).collect();
+        write_frames(&path, &frames);
+
+        let mut reader = SerReader::open(&path).unwrap();
+        let header = reader.get_header().clone();
+        assert_eq!(header.color_id, color_id);
+        assert_eq!((header.width, header.height, header.pixel_depth, header.frame_count), (7, 5, bpp, 3));
+        assert_eq!(header.start_time, Some(test_time(0)));
+        assert_eq!(reader.get_timestamps(), &[test_time(0), test_time(1), test_time(2)]);
+        for (index, frame) in frames.iter().enumerate() {
+            let read = reader.read_frame(index as u32).unwrap();
+            assert_eq!((read.width, read.height, read.bpp, read.channels), (frame.width, frame.height, frame.bpp, frame.channels));
+            assert_eq!(read.data, frame.data, "{}: frame {}", name, index);
+            assert_eq!(read.metadata.bayer_format, bayer_format);
+            assert_eq!(read.metadata.exposure_start, frame.metadata.exposure_start);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mono_8bit_round_trip() {
+        round_trip("mono_8bit", 8, 1, BayerFormat::Mono, SerColorId::Mono);
+    }
+
+    #[test]
+    fn bayer_16bit_round_trip() {
+        round_trip("bayer_16bit", 16, 1, BayerFormat::GB, SerColorId::BayerGbrg);
+    }
+
+    #[test]
+    fn bgr_round_trip() {
+        round_trip("bgr", 8, 3, BayerFormat::Mono, SerColorId::Bgr);
+    }
+
+    #[test]
+    fn color_ids_match_the_bayer_formats() {
+        for (bayer_format, color_id, id) in [(BayerFormat::Mono, SerColorId::Mono, 0), (BayerFormat::RG, SerColorId::BayerRggb, 8),
+                                             (BayerFormat::GR, SerColorId::BayerGrbg, 9), (BayerFormat::GB, SerColorId::BayerGbrg, 10),
+                                             (BayerFormat::BG, SerColorId::BayerBggr, 11)] {
+            let frame = test_frame(8, 1, bayer_format, 0);
+            assert_eq!(SerColorId::from_frame(&frame), color_id);
+            assert_eq!(SerColorId::from_id(id), Some(color_id));
+            assert_eq!(color_id.get_bayer_format(), bayer_format);
+        }
+        assert_eq!(SerColorId::from_frame(&test_frame(8, 3, BayerFormat::RG, 0)), SerColorId::Bgr);
+        assert_eq!(SerColorId::from_id(101), Some(SerColorId::Bgr));
+        assert_eq!(SerColorId::from_id(1), None);
+    }
+
+    #[test]
+    fn header_fields_at_their_offsets() {
+        let path = test_path("header");
+        let frames: Vec<Frame> = (0..2).map(|index| test_frame(16, 1, BayerFormat::RG, index)).collect();
+        write_frames(&path, &frames);
+
+        let bytes = std::fs::read(&path).unwrap();
+        let get_i32 = |offset: usize| i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let get_i64 = |offset: usize| i64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        assert_eq!(&bytes[..14], b"LUCAM-RECORDER");
+        assert_eq!(get_i32(18), SerColorId::BayerRggb as i32);
+        assert_eq!(get_i32(22), 0);
+        assert_eq!((get_i32(26), get_i32(30), get_i32(34)), (7, 5, 16));
+        // Written as 0 with the first frame, patched by finish
+        assert_eq!(get_i32(38), 2);
+        assert_eq!(&bytes[42..50], b"observer");
+        assert_eq!(&bytes[82..93], b"QHY5III462C");
+        assert_eq!(&bytes[122..129], b"all-sky");
+        assert_eq!(bytes[129], 0);
+        assert_eq!(get_i64(162), to_ticks(test_time(0)));
+        assert_eq!(get_i64(170), to_ticks(test_time(0)));
+
+        let frame_len = 7 * 5 * 2;
+        assert_eq!(bytes.len(), HEADER_LEN as usize + 2 * frame_len + 2 * 8);
+        // Samples are little endian whatever the host order
+        assert_eq!(u16::from_le_bytes([bytes[HEADER_LEN as usize], bytes[HEADER_LEN as usize + 1]]), frames[0].get_sample(0));
+        let trailer = HEADER_LEN as usize + 2 * frame_len;
+        assert_eq!(get_i64(trailer + 8), to_ticks(test_time(1)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ticks_round_trip() {
+        assert_eq!(to_ticks(UNIX_EPOCH), UNIX_EPOCH_TICKS);
+        assert_eq!(to_ticks(UNIX_EPOCH + Duration::from_secs(1)), UNIX_EPOCH_TICKS + 10_000_000);
+        for time in [test_time(0), UNIX_EPOCH - Duration::from_nanos(86_400_000_000_300)] {
+            assert_eq!(from_ticks(to_ticks(time)), time);
+        }
+        // Below 100 ns is truncated
+        assert_eq!(from_ticks(to_ticks(UNIX_EPOCH + Duration::from_nanos(150))), UNIX_EPOCH + Duration::from_nanos(100));
+    }
+
+    #[test]
+    fn frames_without_timestamps_have_no_trailer() {
+        let path = test_path("no_trailer");
+        let mut frame = test_frame(8, 1, BayerFormat::Mono, 0);
+        frame.metadata.exposure_start = None;
+        write_frames(&path, &[frame.clone(), frame]);
+
+        let reader = SerReader::open(&path).unwrap();
+        assert_eq!(reader.get_frame_count(), 2);
+        assert_eq!(reader.get_header().start_time, None);
+        assert!(reader.get_timestamps().is_empty());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), HEADER_LEN + 2 * 7 * 5);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant};
+use crate::camera::Camera;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecoveryEvent {
+    FrameFailures { cam_id: String, count: u32 },
+    Disconnected { cam_id: String, serial_num: String },
+    RescanAttempt { serial_num: String, attempt: u32 },
+    Reconnected { cam_id: String, serial_num: String, downtime: Duration },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionState {
+    Connected,
+    Disconnected,
+}
+
+#[derive(Debug, Clone)]
+pub struct SupervisorConfig {
+    pub max_consecutive_failures: u32,
+    pub presence_check_after: u32,
+    pub rescan_interval: Duration,
+}
+
+pub struct CameraSupervisor {
+    config: SupervisorConfig,
+    cam_id: String,
+    serial_num: String,
+    state: ConnectionState,
+    consecutive_failures: u32,
+    rescan_attempts: u32,
+    disconnected_at: Option<Instant>,
+    last_rescan: Option<Instant>,
+    subscribers: Vec<Sender<RecoveryEvent>>,
+}
+
+impl CameraSupervisor {
+    pub fn new(camera: &Camera, config: SupervisorConfig) -> Self {
+        CameraSupervisor {
+            config,
+            cam_id: camera.get_cam_id().to_string(),
+            serial_num: camera.get_current_info().serial_num.clone(),
+            state: if camera.is_open() { ConnectionState::Connected } else { ConnectionState::Disconnected },
+            consecutive_failures: 0,
+            rescan_attempts: 0,
+            disconnected_at: None,
+            last_rescan: None,
+            subscribers: Vec::new(),
+        }
+    }
+
+    pub fn subscribe(&mut self) -> Receiver<RecoveryEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    pub fn get_state(&self) -> ConnectionState {
+        self.state
+    }
+
+    // Call once per capture loop with the result of get_frame; returns the state after recovery handling
+    pub fn check(&mut self, camera: &mut Camera, frame_ok: bool) -> ConnectionState {
+        match self.state {
+            ConnectionState::Connected => self.check_connected(camera, frame_ok),
+            ConnectionState::Disconnected => self.try_recover(camera),
+        }
+
+        self.state
+    }
+
+    fn check_connected(&mut self, camera: &mut Camera, frame_ok: bool) {
+        if frame_ok {
+            self.consecutive_failures = 0;
+            return
+        }
+        self.consecutive_failures += 1;
+        self.emit(RecoveryEvent::FrameFailures { cam_id: self.cam_id.clone(), count: self.consecutive_failures });
+
+        // A rescan tells a dropped device apart from a slow one without waiting for all failures
+        let mut is_gone = self.consecutive_failures >= self.config.max_consecutive_failures;
+        if !is_gone && self.consecutive_failures >= self.config.presence_check_after {
+            let suffix = format!("-{}", self.serial_num);
+            is_gone = !camera.scan_ids().iter().any(|id| id.ends_with(&suffix));
+        }
+
+        if is_gone {
+            camera.drop_connection();
+            self.state = ConnectionState::Disconnected;
+            self.disconnected_at = Some(Instant::now());
+            self.last_rescan = None;
+            self.rescan_attempts = 0;
+            self.emit(RecoveryEvent::Disconnected { cam_id: self.cam_id.clone(), serial_num: self.serial_num.clone() });
+        }
+    }
+
+    fn try_recover(&mut self, camera: &mut Camera) {
+        let is_due = self.last_rescan.map_or(true, |last| last.elapsed() >= self.config.rescan_interval);
+        if !is_due {
+            return
+        }
+        self.last_rescan = Some(Instant::now());
+        self.rescan_attempts += 1;
+        self.emit(RecoveryEvent::RescanAttempt { serial_num: self.serial_num.clone(), attempt: self.rescan_attempts });
+
+        if camera.reconnect(&self.serial_num) {
+            let downtime = self.disconnected_at.map_or(Duration::ZERO, |since| since.elapsed());
+            self.cam_id = camera.get_cam_id().to_string();
+            self.state = ConnectionState::Connected;
+            self.consecutive_failures = 0;
+            self.disconnected_at = None;
+            self.emit(RecoveryEvent::Reconnected { cam_id: self.cam_id.clone(), serial_num: self.serial_num.clone(), downtime });
+        }
+    }
+
+    fn emit(&mut self, event: RecoveryEvent) {
+        match &event {
+            RecoveryEvent::FrameFailures { .. } | RecoveryEvent::RescanAttempt { .. } => {},
+            _ => println!("{}", event),
+        }
+        self.subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        SupervisorConfig {
+            max_consecutive_failures: 10,
+            presence_check_after: 3,
+            rescan_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+impl fmt::Display for RecoveryEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecoveryEvent::FrameFailures { cam_id, count } => write!(f, "Camera {}: {} consecutive frame failure(s)", cam_id, count),
+            RecoveryEvent::Disconnected { cam_id, serial_num } => write!(f, "Camera {} (serial {}) disconnected", cam_id, serial_num),
+            RecoveryEvent::RescanAttempt { serial_num, attempt } => write!(f, "Rescanning for serial {}, attempt {}", serial_num, attempt),
+            RecoveryEvent::Reconnected { cam_id, serial_num, downtime } => write!(f, "Camera {} (serial {}) reconnected after {:.1}s", cam_id, serial_num, downtime.as_secs_f64()),
+        }
+    }
+}
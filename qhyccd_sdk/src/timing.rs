@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
+use serde::{Deserialize, Serialize};
+
+// Time from the end of the exposure until the frame is handed over by the SDK
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencyModel {
+    pub readout_latency_us: u64,
+    pub transfer_us_per_mb: u64,
+    pub jitter_us: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClockQuality {
+    pub offset_s: f64,
+    pub root_delay_s: f64,
+    pub root_dispersion_s: f64,
+    pub stratum: u32,
+    pub is_synchronised: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClockStatusSource {
+    // `chronyc tracking` or `ntpq -c rv` output, refreshed by cron or a systemd timer
+    File(PathBuf),
+    // Unix socket of a local helper that answers with the same text on connect
+    Socket(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FrameTiming {
+    pub received_utc: SystemTime,
+    pub received_monotonic_ns: u64,
+    pub exposure_start: SystemTime,
+    pub exposure_end: SystemTime,
+    pub midpoint: SystemTime,
+    pub uncertainty: Duration,
+    pub clock_quality: Option<ClockQuality>,
+}
+
+pub struct TimingModel {
+    default_latency: LatencyModel,
+    model_latencies: HashMap<String, LatencyModel>,
+    clock_source: Option<ClockStatusSource>,
+    clock_refresh: Duration,
+    clock_quality: Option<ClockQuality>,
+    clock_checked: Option<Instant>,
+}
+
+// Uncertainty used when there is no clock status at all, an unsynchronised clock is not trusted better than this
+const UNKNOWN_CLOCK_UNCERTAINTY: Duration = Duration::from_millis(100);
+
+// CLOCK_MONOTONIC nanoseconds, the time since boot that other processes on the host see as well
+pub fn monotonic_ns(instant: Instant) -> u64 {
+    static REFERENCE: OnceLock<(Instant, u64)> = OnceLock::new();
+    let (reference, reference_ns) = *REFERENCE.get_or_init(|| {
+        let mut spec = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+        unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut spec) };
+        (Instant::now(), spec.tv_sec as u64 * 1_000_000_000 + spec.tv_nsec as u64)
+    });
+    match instant.checked_duration_since(reference) {
+        Some(after) => reference_ns + after.as_nanos() as u64,
+        None => reference_ns.saturating_sub(reference.duration_since(instant).as_nanos() as u64),
+    }
+}
+
+impl LatencyModel {
+    pub fn latency(&self, frame_bytes: usize) -> Duration {
+        let transfer_us = self.transfer_us_per_mb as f64 * frame_bytes as f64 / (1024.0 * 1024.0);
+        Duration::from_micros(self.readout_latency_us) + Duration::from_secs_f64(transfer_us / 1_000_000.0)
+    }
+}
+
+impl ClockQuality {
+    // Worst case distance from true UTC, as chrony and ntpd define it
+    pub fn max_error(&self) -> Duration {
+        if !self.is_synchronised {
+            return UNKNOWN_CLOCK_UNCERTAINTY
+        }
+        Duration::from_secs_f64(self.offset_s.abs() + self.root_dispersion_s + self.root_delay_s / 2.0)
+    }
+
+    pub fn parse(text: &str) -> Option<ClockQuality> {
+        if text.contains("Root dispersion") {
+            ClockQuality::parse_chrony(text)
+        } else if text.contains("rootdisp") {
+            ClockQuality::parse_ntpq(text)
+        } else {
+            None
+        }
+    }
+
+    fn parse_chrony(text: &str) -> Option<ClockQuality> {
+        let mut quality = ClockQuality::default();
+        for line in text.lines() {
+            let mut parts = line.splitn(2, ':');
+            let (key, value) = (parts.next()?.trim(), parts.next().unwrap_or("").trim());
+            let number = value.split_whitespace().next().and_then(|v| v.parse::<f64>().ok());
+            match key {
+                "System time" => {
+                    let offset = number.unwrap_or(0.0);
+                    quality.offset_s = if value.contains("slow") { -offset } else { offset };
+                },
+                "Root delay" => quality.root_delay_s = number.unwrap_or(0.0),
+                "Root dispersion" => quality.root_dispersion_s = number.unwrap_or(0.0),
+                "Stratum" => quality.stratum = number.unwrap_or(0.0) as u32,
+                "Leap status" => quality.is_synchronised = value != "Not synchronised",
+                _ => {}
+            }
+        }
+
+        Some(quality)
+    }
+
+    // ntpq reports in milliseconds
+    fn parse_ntpq(text: &str) -> Option<ClockQuality> {
+        let mut quality = ClockQuality { is_synchronised: true, ..ClockQuality::default() };
+        for item in text.split(|c: char| c == ',' || c.is_whitespace()) {
+            let mut parts = item.splitn(2, '=');
+            let (key, value) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+            let number = value.trim_matches('"').parse::<f64>().ok();
+            match key {
+                "offset" => quality.offset_s = number.unwrap_or(0.0) / 1000.0,
+                "rootdelay" => quality.root_delay_s = number.unwrap_or(0.0) / 1000.0,
+                "rootdisp" => quality.root_dispersion_s = number.unwrap_or(0.0) / 1000.0,
+                "stratum" => {
+                    quality.stratum = number.unwrap_or(16.0) as u32;
+                    quality.is_synchronised &= quality.stratum < 16;
+                },
+                "leap" => quality.is_synchronised &= value != "11",
+                _ => {}
+            }
+        }
+
+        Some(quality)
+    }
+}
+
+impl ClockStatusSource {
+    pub fn read(&self) -> Option<ClockQuality> {
+        let text = match self {
+            ClockStatusSource::File(path) => fs::read_to_string(path).ok()?,
+            ClockStatusSource::Socket(path) => {
+                let mut stream = UnixStream::connect(path).ok()?;
+                let _ = stream.set_read_timeout(Some(Duration::from_millis(200)));
+                let mut text = String::new();
+                stream.read_to_string(&mut text).ok()?;
+                text
+            },
+        };
+        ClockQuality::parse(&text)
+    }
+}
+
+impl TimingModel {
+    pub fn new() -> Self {
+        TimingModel::default()
+    }
+
+    pub fn set_default_latency(&mut self, latency: LatencyModel) {
+        self.default_latency = latency;
+    }
+
+    pub fn set_model_latency(&mut self, model: &str, latency: LatencyModel) {
+        self.model_latencies.insert(model.to_string(), latency);
+    }
+
+    pub fn get_latency(&self, model: &str) -> &LatencyModel {
+        self.model_latencies.get(model).unwrap_or(&self.default_latency)
+    }
+
+    pub fn set_clock_source(&mut self, source: Option<ClockStatusSource>, refresh: Duration) {
+        self.clock_source = source;
+        self.clock_refresh = refresh;
+        self.clock_checked = None;
+        self.clock_quality = None;
+    }
+
+    pub fn get_clock_quality(&mut self) -> Option<ClockQuality> {
+        let is_stale = self.clock_checked.map_or(true, |checked| checked.elapsed() >= self.clock_refresh);
+        if is_stale {
+            if let Some(source) = &self.clock_source {
+                self.clock_quality = source.read();
+                if self.clock_quality.is_none() {
+                    eprintln!("Cannot read clock status from {:?}", source);
+                }
+            }
+            self.clock_checked = Some(Instant::now());
+        }
+
+        self.clock_quality.clone()
+    }
+
+    // With a known exposure start (single frame) the midpoint follows from it, otherwise it is
+    // worked back from the time the frame was received; poll_interval is the last frame polling sleep
+    pub fn estimate(&mut self, model: &str, exposure: Duration, frame_bytes: usize, received_utc: SystemTime,
+                    received_at: Instant, known_start: Option<SystemTime>, poll_interval: Duration) -> FrameTiming {
+        let latency = self.get_latency(model).clone();
+        let clock_quality = self.get_clock_quality();
+        let clock_error = clock_quality.as_ref().map_or(UNKNOWN_CLOCK_UNCERTAINTY, |quality| quality.max_error());
+
+        let (exposure_start, exposure_end, model_error) = match known_start {
+            Some(start) => (start, start + exposure, Duration::from_micros(latency.jitter_us)),
+            None => {
+                let end = received_utc - latency.latency(frame_bytes);
+                (end - exposure, end, Duration::from_micros(latency.jitter_us) + poll_interval)
+            }
+        };
+
+        FrameTiming {
+            received_utc,
+            received_monotonic_ns: monotonic_ns(received_at),
+            exposure_start,
+            exposure_end,
+            midpoint: exposure_start + exposure / 2,
+            uncertainty: model_error + clock_error,
+            clock_quality,
+        }
+    }
+}
+
+impl Default for TimingModel {
+    fn default() -> Self {
+        TimingModel {
+            default_latency: LatencyModel::default(),
+            model_latencies: HashMap::new(),
+            clock_source: None,
+            clock_refresh: Duration::from_secs(60),
+            clock_quality: None,
+            clock_checked: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHRONY_TRACKING: &str = "Reference ID    : C0A80001 (192.168.0.1)
+Stratum         : 3
+Ref time (UTC)  : Thu Oct 15 12:00:00 2026
+System time     : 0.000250000 seconds slow of NTP time
+Last offset     : -0.000001234 seconds
+RMS offset      : 0.000010000 seconds
+Frequency       : 1.234 ppm fast
+Residual freq   : +0.001 ppm
+Skew            : 0.010 ppm
+Root delay      : 0.012000000 seconds
+Root dispersion : 0.000500000 seconds
+Update interval : 64.2 seconds
+Leap status     : Normal
+";
+
+    const NTPQ_RV: &str = "associd=0 status=0615 leap_none, sync_ntp, 1 event, clock_sync,
+version=\"ntpd 4.2.8p15@1.3728-o\", processor=\"x86_64\",
+system=\"Linux/5.15.0\", leap=00, stratum=2, precision=-23,
+rootdelay=12.345, rootdisp=3.210, refid=192.168.0.1,
+reftime=e8f1a2b3.12345678  Thu, Oct 15 2026 12:00:00.071,
+clock=e8f1a2c0.abcdef01  Thu, Oct 15 2026 12:00:13.671, peer=12345, tc=6,
+mintc=3, offset=-0.456, frequency=-12.345, sys_jitter=0.123,
+clk_jitter=0.045, clk_wander=0.002
+";
+
+    fn assert_close(actual: SystemTime, expected: SystemTime) {
+        let difference = actual.duration_since(expected).or_else(|_| expected.duration_since(actual)).unwrap();
+        assert!(difference < Duration::from_micros(1), "{:?} != {:?}", actual, expected);
+    }
+
+    fn test_latency() -> LatencyModel {
+        LatencyModel { readout_latency_us: 2000, transfer_us_per_mb: 1000, jitter_us: 500 }
+    }
+
+    #[test]
+    fn parses_chrony_tracking() {
+        let quality = ClockQuality::parse(CHRONY_TRACKING).unwrap();
+        assert_eq!(quality.offset_s, -0.00025);
+        assert_eq!(quality.root_delay_s, 0.012);
+        assert_eq!(quality.root_dispersion_s, 0.0005);
+        assert_eq!(quality.stratum, 3);
+        assert!(quality.is_synchronised);
+        // |offset| + dispersion + delay / 2
+        assert_eq!(quality.max_error(), Duration::from_micros(6750));
+
+        let unsynchronised = ClockQuality::parse(&CHRONY_TRACKING.replace("Normal", "Not synchronised")).unwrap();
+        assert!(!unsynchronised.is_synchronised);
+        assert_eq!(unsynchronised.max_error(), UNKNOWN_CLOCK_UNCERTAINTY);
+    }
+
+    #[test]
+    fn parses_ntpq_variables() {
+        let quality = ClockQuality::parse(NTPQ_RV).unwrap();
+        assert!((quality.offset_s + 0.000456).abs() < 1e-12);
+        assert!((quality.root_delay_s - 0.012345).abs() < 1e-12);
+        assert!((quality.root_dispersion_s - 0.00321).abs() < 1e-12);
+        assert_eq!(quality.stratum, 2);
+        assert!(quality.is_synchronised);
+
+        assert!(!ClockQuality::parse(&NTPQ_RV.replace("leap=00", "leap=11")).unwrap().is_synchronised);
+        assert!(!ClockQuality::parse(&NTPQ_RV.replace("stratum=2", "stratum=16")).unwrap().is_synchronised);
+        assert_eq!(ClockQuality::parse("no clock status"), None);
+    }
+
+    #[test]
+    fn latency_grows_with_the_frame_size() {
+        let latency = test_latency();
+        assert_eq!(latency.latency(0), Duration::from_millis(2));
+        assert_eq!(latency.latency(1024 * 1024), Duration::from_millis(3));
+    }
+
+    #[test]
+    fn estimates_from_the_receive_time() {
+        let mut model = TimingModel::new();
+        model.set_model_latency("QHY174M", test_latency());
+        let received_utc = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let exposure = Duration::from_secs(1);
+        let timing = model.estimate("QHY174M", exposure, 2 * 1024 * 1024, received_utc, Instant::now(), None, Duration::from_millis(10));
+
+        // 2 ms readout and 2 ms transfer before the frame arrives
+        assert_close(timing.exposure_end, received_utc - Duration::from_millis(4));
+        assert_close(timing.exposure_start, received_utc - Duration::from_millis(1004));
+        assert_close(timing.midpoint, received_utc - Duration::from_millis(504));
+        // Jitter, the poll interval and an unknown clock
+        assert_eq!(timing.uncertainty, Duration::from_micros(500) + Duration::from_millis(10) + UNKNOWN_CLOCK_UNCERTAINTY);
+        assert_eq!(timing.clock_quality, None);
+
+        // Other models use the default latency
+        let timing = model.estimate("QHY5III462C", exposure, 2 * 1024 * 1024, received_utc, Instant::now(), None, Duration::ZERO);
+        assert_close(timing.exposure_end, received_utc);
+    }
+
+    #[test]
+    fn estimates_from_a_known_start_with_the_clock_status() {
+        let path = std::env::temp_dir().join(format!("qhyccd_timing_chrony_{}", std::process::id()));
+        fs::write(&path, CHRONY_TRACKING).unwrap();
+        let mut model = TimingModel::new();
+        model.set_default_latency(test_latency());
+        model.set_clock_source(Some(ClockStatusSource::File(path.clone())), Duration::from_secs(60));
+
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let received_utc = start + Duration::from_secs(3);
+        let timing = model.estimate("QHY174M", Duration::from_secs(2), 1024, received_utc, Instant::now(), Some(start), Duration::from_millis(10));
+        assert_eq!(timing.exposure_start, start);
+        assert_eq!(timing.exposure_end, start + Duration::from_secs(2));
+        assert_eq!(timing.midpoint, start + Duration::from_secs(1));
+        // The poll interval does not matter once the start is known
+        assert_eq!(timing.uncertainty, Duration::from_micros(500) + Duration::from_micros(6750));
+        assert_eq!(timing.clock_quality.map(|quality| quality.stratum), Some(3));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriggerMode {
+    Off,
+    // Exposure starts on SendSoftTriggerQHYCCD, also used to emulate a hardware edge
+    Software,
+    // Exposure starts on the trigger input, the value is the camera specific trigger mode
+    External(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TriggerPulse {
+    pub sequence: u64,
+    pub time: SystemTime,
+}
+
+// Stands in for a shared hardware trigger line: every subscriber, typically one capture thread
+// per camera, receives the same numbered pulse
+#[derive(Clone, Default)]
+pub struct SimulatedTrigger {
+    subscribers: Arc<Mutex<Vec<Sender<TriggerPulse>>>>,
+    sequence: Arc<AtomicU64>,
+}
+
+impl SimulatedTrigger {
+    pub fn new() -> Self {
+        SimulatedTrigger::default()
+    }
+
+    pub fn subscribe(&self) -> Receiver<TriggerPulse> {
+        let (sender, receiver) = mpsc::channel();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+        receiver
+    }
+
+    pub fn fire(&self) -> TriggerPulse {
+        let pulse = TriggerPulse {
+            sequence: self.sequence.fetch_add(1, Ordering::SeqCst) + 1,
+            time: SystemTime::now(),
+        };
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain(|subscriber| subscriber.send(pulse).is_ok());
+        }
+
+        pulse
+    }
+
+    pub fn start_periodic(&self, interval: Duration, count: u64) -> JoinHandle<()> {
+        let trigger = self.clone();
+        thread::spawn(move || {
+            for _ in 0..count {
+                thread::sleep(interval);
+                trigger.fire();
+            }
+        })
+    }
+}
+
+// Only a software trigger starts the exposure when the pulse is handled on the host; an external
+// edge fires the camera directly and the software pulse time says nothing about it
+pub fn pulse_exposure_start(mode: TriggerMode, pulse: &TriggerPulse) -> Option<SystemTime> {
+    match mode {
+        TriggerMode::Software => Some(pulse.time),
+        TriggerMode::Off | TriggerMode::External(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_subscriber_gets_the_same_pulse() {
+        let trigger = SimulatedTrigger::new();
+        let first = trigger.subscribe();
+        let second = trigger.subscribe();
+        let pulse = trigger.fire();
+        assert_eq!(pulse.sequence, 1);
+        assert_eq!(first.recv_timeout(Duration::from_secs(1)), Ok(pulse));
+        assert_eq!(second.recv_timeout(Duration::from_secs(1)), Ok(pulse));
+    }
+
+    #[test]
+    fn dropped_subscribers_are_removed() {
+        let trigger = SimulatedTrigger::new();
+        let kept = trigger.subscribe();
+        drop(trigger.subscribe());
+        trigger.fire();
+        assert_eq!(trigger.subscribers.lock().unwrap().len(), 1);
+        assert_eq!(kept.try_recv().map(|pulse| pulse.sequence), Ok(1));
+    }
+
+    #[test]
+    fn periodic_pulses_arrive_in_order() {
+        let trigger = SimulatedTrigger::new();
+        let pulses = trigger.subscribe();
+        trigger.start_periodic(Duration::from_millis(5), 4).join().unwrap();
+        let received: Vec<TriggerPulse> = pulses.try_iter().collect();
+        assert_eq!(received.iter().map(|pulse| pulse.sequence).collect::<Vec<u64>>(), vec![1, 2, 3, 4]);
+        assert!(received.windows(2).all(|pair| pair[0].time <= pair[1].time));
+    }
+
+    #[test]
+    fn waiting_for_a_pulse_times_out() {
+        let trigger = SimulatedTrigger::new();
+        let pulses = trigger.subscribe();
+        assert!(pulses.recv_timeout(Duration::from_millis(20)).is_err());
+        trigger.fire();
+        assert!(pulses.recv_timeout(Duration::from_millis(20)).is_ok());
+    }
+
+    #[test]
+    fn only_software_pulses_give_the_exposure_start() {
+        let pulse = SimulatedTrigger::new().fire();
+        assert_eq!(pulse_exposure_start(TriggerMode::Software, &pulse), Some(pulse.time));
+        assert_eq!(pulse_exposure_start(TriggerMode::External(1), &pulse), None);
+        assert_eq!(pulse_exposure_start(TriggerMode::Off, &pulse), None);
+    }
+}
@@ -0,0 +1,194 @@
+use std::time::{Duration, Instant};
+use derive_more::Display;
+use crate::camera::Camera;
+
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscalationStep {
+    CancelExposure = 0,
+    RestartStream = 1,
+    Reopen = 2,
+    ReinitSdk = 3,
+}
+
+const ESCALATION_STEPS: [EscalationStep; 4] = [
+    EscalationStep::CancelExposure,
+    EscalationStep::RestartStream,
+    EscalationStep::Reopen,
+    EscalationStep::ReinitSdk,
+];
+
+#[derive(Debug, Clone)]
+pub struct WatchdogConfig {
+    pub exposure_factor: f64,
+    pub margin: Duration,
+    pub min_timeout: Duration,
+    pub use_fpga_watchdog: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WatchdogStats {
+    pub stalls: u32,
+    pub step_counts: [u32; 4],
+    pub step_failures: [u32; 4],
+}
+
+pub struct CaptureWatchdog {
+    config: WatchdogConfig,
+    last_frame: Instant,
+    next_step: usize,
+    stats: WatchdogStats,
+    has_fpga_watchdog: bool,
+}
+
+impl CaptureWatchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        CaptureWatchdog {
+            config,
+            last_frame: Instant::now(),
+            next_step: 0,
+            stats: WatchdogStats::default(),
+            has_fpga_watchdog: false,
+        }
+    }
+
+    pub fn attach(&mut self, camera: &mut Camera) {
+        if self.config.use_fpga_watchdog {
+            self.has_fpga_watchdog = camera.set_fpga_watchdog(true);
+            if self.has_fpga_watchdog {
+                println!("Watchdog: FPGA watchdog enabled on camera {}", camera.get_cam_id());
+            }
+        }
+        self.last_frame = Instant::now();
+        self.next_step = 0;
+    }
+
+    pub fn get_stats(&self) -> &WatchdogStats {
+        &self.stats
+    }
+
+    // Exposure is in microseconds, the readout and transfer are covered by the margin
+    pub fn stall_timeout(&self, camera: &Camera) -> Duration {
+        let exposure = Duration::from_micros(camera.get_params().exposure as u64);
+        let timeout = exposure.mul_f64(self.config.exposure_factor) + self.config.margin;
+        timeout.max(self.config.min_timeout)
+    }
+
+    pub fn frame_received(&mut self) {
+        self.last_frame = Instant::now();
+        self.next_step = 0;
+    }
+
+    // Call on every capture loop iteration without a frame, runs the next escalation step once the stall timeout passed
+    pub fn check(&mut self, camera: &mut Camera) -> Option<EscalationStep> {
+        let stalled_for = self.last_frame.elapsed();
+        let step = self.escalate(stalled_for, self.stall_timeout(camera), camera.is_sdk_owner())?;
+        println!("Watchdog: camera {} stalled for {:.1}s, escalating to {} (count: {})",
+            camera.get_cam_id(), stalled_for.as_secs_f64(), step, self.stats.step_counts[step as usize]);
+
+        let res = match step {
+            EscalationStep::CancelExposure => camera.cancel_exposure(),
+            EscalationStep::RestartStream => camera.restart_stream(),
+            EscalationStep::Reopen => camera.reopen(),
+            EscalationStep::ReinitSdk => camera.reinit_sdk(),
+        };
+        if !res {
+            self.stats.step_failures[step as usize] += 1;
+            eprintln!("Watchdog: {} failed on camera {}", step, camera.get_cam_id());
+        }
+        if matches!(step, EscalationStep::Reopen | EscalationStep::ReinitSdk) && self.has_fpga_watchdog {
+            camera.set_fpga_watchdog(true);
+        }
+
+        Some(step)
+    }
+
+    // Picks the step for this stall and moves up the ladder, a camera sharing the SDK resources
+    // with a manager cannot re-init them and stays at Reopen
+    fn escalate(&mut self, stalled_for: Duration, timeout: Duration, can_reinit: bool) -> Option<EscalationStep> {
+        if stalled_for < timeout {
+            return None
+        }
+
+        let last_step = if can_reinit { ESCALATION_STEPS.len() - 1 } else { EscalationStep::Reopen as usize };
+        let step = ESCALATION_STEPS[self.next_step.min(last_step)];
+        self.stats.stalls += 1;
+        self.stats.step_counts[step as usize] += 1;
+
+        // Give the camera a full timeout after each step before escalating further
+        self.last_frame = Instant::now();
+        self.next_step = (self.next_step + 1).min(last_step);
+
+        Some(step)
+    }
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            exposure_factor: 3.0,
+            margin: Duration::from_secs(2),
+            min_timeout: Duration::from_secs(5),
+            use_fpga_watchdog: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STALLED: Duration = Duration::from_secs(10);
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn run_steps(watchdog: &mut CaptureWatchdog, count: usize, can_reinit: bool) -> Vec<EscalationStep> {
+        (0..count).filter_map(|_| watchdog.escalate(STALLED, TIMEOUT, can_reinit)).collect()
+    }
+
+    #[test]
+    fn nothing_happens_before_the_timeout() {
+        let mut watchdog = CaptureWatchdog::new(WatchdogConfig::default());
+        assert_eq!(watchdog.escalate(Duration::from_secs(4), TIMEOUT, true), None);
+        assert_eq!(watchdog.get_stats().stalls, 0);
+    }
+
+    #[test]
+    fn escalates_in_order_and_stays_at_the_last_step() {
+        let mut watchdog = CaptureWatchdog::new(WatchdogConfig::default());
+        assert_eq!(run_steps(&mut watchdog, 5, true), vec![
+            EscalationStep::CancelExposure,
+            EscalationStep::RestartStream,
+            EscalationStep::Reopen,
+            EscalationStep::ReinitSdk,
+            EscalationStep::ReinitSdk,
+        ]);
+        assert_eq!(watchdog.get_stats().stalls, 5);
+        assert_eq!(watchdog.get_stats().step_counts, [1, 1, 1, 2]);
+    }
+
+    #[test]
+    fn shared_sdk_never_reinits() {
+        let mut watchdog = CaptureWatchdog::new(WatchdogConfig::default());
+        assert_eq!(run_steps(&mut watchdog, 5, false), vec![
+            EscalationStep::CancelExposure,
+            EscalationStep::RestartStream,
+            EscalationStep::Reopen,
+            EscalationStep::Reopen,
+            EscalationStep::Reopen,
+        ]);
+        assert_eq!(watchdog.get_stats().step_counts, [1, 1, 3, 0]);
+    }
+
+    #[test]
+    fn a_frame_resets_the_escalation() {
+        let mut watchdog = CaptureWatchdog::new(WatchdogConfig::default());
+        run_steps(&mut watchdog, 3, true);
+        watchdog.frame_received();
+        assert_eq!(run_steps(&mut watchdog, 1, true), vec![EscalationStep::CancelExposure]);
+    }
+
+    #[test]
+    fn short_exposures_get_the_minimum_timeout() {
+        let watchdog = CaptureWatchdog::new(WatchdogConfig::default());
+        assert_eq!(watchdog.stall_timeout(&Camera::default()), Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,257 @@
+extern crate opencv;
+
+use opencv::{core, prelude::*};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WhiteBalanceMethod {
+    GrayWorld,
+    WhitePatch,
+    Percentile(f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WhiteBalanceGains {
+    pub red: f64,
+    pub green: f64,
+    pub blue: f64,
+}
+
+impl WhiteBalanceGains {
+    pub fn unity() -> Self {
+        WhiteBalanceGains { red: 1.0, green: 1.0, blue: 1.0 }
+    }
+
+    pub fn is_unity(&self) -> bool {
+        self.red == 1.0 && self.green == 1.0 && self.blue == 1.0
+    }
+
+    pub fn combine(&self, other: &WhiteBalanceGains) -> WhiteBalanceGains {
+        WhiteBalanceGains {
+            red: self.red * other.red,
+            green: self.green * other.green,
+            blue: self.blue * other.blue,
+        }
+    }
+}
+
+impl Default for WhiteBalanceGains {
+    fn default() -> Self {
+        WhiteBalanceGains::unity()
+    }
+}
+
+// Saturated pixels carry no colour information, they are skipped by every method
+const SATURATION_MARGIN: f64 = 0.98;
+
+// Gains are relative to green, the frame is expected to be debayered BGR (8 or 16 bit)
+pub fn compute_gains(image: &Mat, method: WhiteBalanceMethod) -> Option<WhiteBalanceGains> {
+    let samples = read_bgr_samples(image)?;
+    let levels = match method {
+        WhiteBalanceMethod::GrayWorld => gray_world_levels(&samples),
+        WhiteBalanceMethod::WhitePatch => percentile_levels(&samples, 100.0),
+        WhiteBalanceMethod::Percentile(percent) => percentile_levels(&samples, percent.clamp(0.0, 100.0)),
+    }?;
+
+    let (blue, green, red) = levels;
+    if blue <= 0.0 || green <= 0.0 || red <= 0.0 {
+        return None
+    }
+
+    Some(WhiteBalanceGains { red: green / red, green: 1.0, blue: green / blue })
+}
+
+pub fn apply_gains(image: &mut Mat, gains: &WhiteBalanceGains) -> bool {
+    if image.channels() != 3 {
+        return false
+    }
+    let depth = image.depth();
+    let channel_gains = [gains.blue, gains.green, gains.red];
+    let has_data = image.data_bytes_mut();
+    if has_data.is_err() {
+        return false
+    }
+    let data = has_data.unwrap();
+
+    if depth == core::CV_16U {
+        for (index, pixel) in data.chunks_exact_mut(2).enumerate() {
+            let value = u16::from_ne_bytes([pixel[0], pixel[1]]) as f64 * channel_gains[index % 3];
+            pixel.copy_from_slice(&(value.round().min(u16::MAX as f64) as u16).to_ne_bytes());
+        }
+    } else if depth == core::CV_8U {
+        for (index, pixel) in data.iter_mut().enumerate() {
+            let value = *pixel as f64 * channel_gains[index % 3];
+            *pixel = value.round().min(u8::MAX as f64) as u8;
+        }
+    } else {
+        return false
+    }
+
+    true
+}
+
+struct BgrSamples {
+    blue: Vec<u16>,
+    green: Vec<u16>,
+    red: Vec<u16>,
+    max_value: u16,
+}
+
+fn read_bgr_samples(image: &Mat) -> Option<BgrSamples> {
+    if image.channels() != 3 {
+        return None
+    }
+    let depth = image.depth();
+    let data = image.data_bytes().ok()?;
+
+    let (values, max_value): (Vec<u16>, u16) = if depth == core::CV_16U {
+        (data.chunks_exact(2).map(|pixel| u16::from_ne_bytes([pixel[0], pixel[1]])).collect(), u16::MAX)
+    } else if depth == core::CV_8U {
+        (data.iter().map(|pixel| *pixel as u16).collect(), u8::MAX as u16)
+    } else {
+        return None
+    };
+
+    let saturation = (max_value as f64 * SATURATION_MARGIN) as u16;
+    let mut samples = BgrSamples { blue: Vec::new(), green: Vec::new(), red: Vec::new(), max_value };
+    for pixel in values.chunks_exact(3) {
+        if pixel.iter().any(|value| *value >= saturation) {
+            continue;
+        }
+        samples.blue.push(pixel[0]);
+        samples.green.push(pixel[1]);
+        samples.red.push(pixel[2]);
+    }
+
+    if samples.green.is_empty() {
+        return None
+    }
+
+    Some(samples)
+}
+
+fn gray_world_levels(samples: &BgrSamples) -> Option<(f64, f64, f64)> {
+    let mean = |values: &Vec<u16>| values.iter().map(|value| *value as f64).sum::<f64>() / values.len() as f64;
+
+    Some((mean(&samples.blue), mean(&samples.green), mean(&samples.red)))
+}
+
+fn percentile_levels(samples: &BgrSamples, percent: f64) -> Option<(f64, f64, f64)> {
+    let percentile = |values: &Vec<u16>| {
+        let mut histogram = vec![0usize; samples.max_value as usize + 1];
+        for value in values {
+            histogram[*value as usize] += 1;
+        }
+        let target = ((values.len() as f64 * percent / 100.0).ceil() as usize).max(1);
+        let mut count = 0;
+        for (value, bin) in histogram.iter().enumerate() {
+            count += bin;
+            if count >= target {
+                return value as f64
+            }
+        }
+        samples.max_value as f64
+    };
+
+    Some((percentile(&samples.blue), percentile(&samples.green), percentile(&samples.red)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::Frame;
+
+    fn bgr_mat(pixels: &[[u16; 3]], depth: i32) -> Mat {
+        let mut data: Vec<u8> = if depth == core::CV_16U {
+            pixels.iter().flatten().flat_map(|value| value.to_ne_bytes()).collect()
+        } else {
+            pixels.iter().flatten().map(|value| *value as u8).collect()
+        };
+        let mat = unsafe { Mat::new_rows_cols_with_data(1, pixels.len() as i32, core::CV_MAKETYPE(depth, 3), data.as_mut_ptr() as *mut _, core::Mat_AUTO_STEP) };
+        mat.and_then(|mat| mat.try_clone()).unwrap()
+    }
+
+    // Grey scene seen through a cast: red at half and blue at 80% of green
+    fn cast_pixels(scale: u32) -> Vec<[u16; 3]> {
+        (10..50).map(|level| {
+            let green = level * 4 * scale;
+            [(green * 4 / 5) as u16, green as u16, (green / 2) as u16]
+        }).collect()
+    }
+
+    fn assert_gains(gains: WhiteBalanceGains, red: f64, blue: f64) {
+        assert!((gains.red - red).abs() < 0.01 && (gains.blue - blue).abs() < 0.01 && gains.green == 1.0, "{:?}", gains);
+    }
+
+    #[test]
+    fn gray_world_removes_the_cast() {
+        let gains = compute_gains(&bgr_mat(&cast_pixels(1), core::CV_8U), WhiteBalanceMethod::GrayWorld).unwrap();
+        assert_gains(gains, 2.0, 1.25);
+        let gains = compute_gains(&bgr_mat(&cast_pixels(200), core::CV_16U), WhiteBalanceMethod::GrayWorld).unwrap();
+        assert_gains(gains, 2.0, 1.25);
+    }
+
+    #[test]
+    fn white_patch_uses_the_brightest_unsaturated_pixels() {
+        let mut pixels = cast_pixels(1);
+        // A white patch with the same cast, brighter than the scene
+        pixels.push([192, 240, 120]);
+        // Clipped pixels would read as neutral
+        pixels.push([255, 255, 255]);
+        pixels.push([90, 252, 200]);
+        let gains = compute_gains(&bgr_mat(&pixels, core::CV_8U), WhiteBalanceMethod::WhitePatch).unwrap();
+        assert_gains(gains, 2.0, 1.25);
+    }
+
+    #[test]
+    fn percentile_ignores_outliers() {
+        let mut pixels = cast_pixels(1);
+        pixels.push([240, 200, 240]);
+        let gains = compute_gains(&bgr_mat(&pixels, core::CV_8U), WhiteBalanceMethod::Percentile(50.0)).unwrap();
+        assert_gains(gains, 2.0, 1.25);
+        let gains = compute_gains(&bgr_mat(&pixels, core::CV_8U), WhiteBalanceMethod::Percentile(100.0)).unwrap();
+        assert!(gains.red < 1.0, "{:?}", gains);
+    }
+
+    #[test]
+    fn nothing_to_balance() {
+        assert_eq!(compute_gains(&bgr_mat(&[[255, 255, 255]; 4], core::CV_8U), WhiteBalanceMethod::GrayWorld), None);
+        assert_eq!(compute_gains(&bgr_mat(&[[0, 100, 100]; 4], core::CV_8U), WhiteBalanceMethod::GrayWorld), None);
+        let mut mono = Frame::new(4, 1, 8, 1);
+        mono.data.fill(100);
+        let mut mat = mono.to_mat().unwrap();
+        assert_eq!(compute_gains(&mat, WhiteBalanceMethod::GrayWorld), None);
+        assert!(!apply_gains(&mut mat, &WhiteBalanceGains::unity()));
+    }
+
+    #[test]
+    fn applied_gains_neutralise_the_cast() {
+        for (depth, scale) in [(core::CV_8U, 1), (core::CV_16U, 200)] {
+            let mut image = bgr_mat(&cast_pixels(scale), depth);
+            let gains = compute_gains(&image, WhiteBalanceMethod::GrayWorld).unwrap();
+            assert!(apply_gains(&mut image, &gains));
+            let balanced = compute_gains(&image, WhiteBalanceMethod::GrayWorld).unwrap();
+            assert_gains(balanced, 1.0, 1.0);
+        }
+    }
+
+    #[test]
+    fn applied_gains_clip_at_the_maximum() {
+        let gains = WhiteBalanceGains { red: 2.0, green: 1.0, blue: 0.5 };
+        let mut image = bgr_mat(&[[100, 100, 200]], core::CV_8U);
+        assert!(apply_gains(&mut image, &gains));
+        assert_eq!(image.data_bytes().unwrap(), &[50, 100, 255]);
+
+        let mut image = bgr_mat(&[[1001, 1000, 40000]], core::CV_16U);
+        assert!(apply_gains(&mut image, &gains));
+        let values: Vec<u16> = image.data_bytes().unwrap().chunks_exact(2).map(|pair| u16::from_ne_bytes([pair[0], pair[1]])).collect();
+        assert_eq!(values, vec![501, 1000, u16::MAX]);
+    }
+
+    #[test]
+    fn combined_gains_multiply() {
+        let gains = WhiteBalanceGains { red: 2.0, green: 1.0, blue: 0.5 };
+        assert_eq!(gains.combine(&WhiteBalanceGains { red: 0.5, green: 1.0, blue: 2.0 }), WhiteBalanceGains::unity());
+        assert!(WhiteBalanceGains::default().is_unity());
+        assert!(!gains.is_unity());
+    }
+}